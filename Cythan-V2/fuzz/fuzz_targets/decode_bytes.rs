@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Any file must decode or fail with a `FormatError`, without panicking
+fuzz_target!(|data: &[u8]| {
+    let _ = cythan::format::decode_bytes(data);
+});
@@ -1,8 +1,19 @@
+use crate::trace::Tracer;
+
 /// This is the generic trait for all Cythan machine implementations
 pub trait Cythan: std::fmt::Display {
     /// This function is a generic function to compute a Cythan iteration
     fn next(&mut self);
 
+    /// Computes a Cythan iteration and tells the tracer which pair ran and which cell it wrote
+    fn next_traced(&mut self, tracer: &mut dyn Tracer) {
+        let address = self.get_value(0);
+        let cell = self.get_value(address + 1);
+        tracer.step(address);
+        self.next();
+        tracer.write(cell);
+    }
+
     /// This function is a generic function to get a value from Cythan memory
     fn get_value(&self, index: usize) -> usize;
 
@@ -1,23 +1,141 @@
-use std::io::{Cursor, Error, Read, Write};
+use std::{
+    convert::TryInto,
+    fmt::Display,
+    io::{Cursor, Error, Read, Write},
+};
 
-use varint::{VarintRead, VarintWrite};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression, Crc};
+use varint::VarintWrite;
 
+/// The header version of the files made of sections
+pub const CONTAINER_VERSION: u32 = 2;
+
+/// The first bytes of the files made of sections, the files of the first version have none
+pub const MAGIC: [u8; 4] = *b"\x7fCCT";
+
+/// The version of the Cythan specification the machines implement
+pub const SPECIFICATION: u32 = 4;
+
+/// The flag of the header telling the memory section is compressed with zlib
+const COMPRESSED: u8 = 1;
+
+/// The largest memory a file can hold, in cells
+pub const MAX_CELLS: usize = 1 << 24;
+/// The largest memory section once decompressed, a cell takes up to 5 bytes
+const MAX_DECOMPRESSED: u64 = MAX_CELLS as u64 * 5 + 5;
+
+/// Why a file can't be decoded
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    /// The file doesn't start like a Cythan binary
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// The file ends in the middle of a section
+    Truncated {
+        section: &'static str,
+    },
+    /// A length goes past the end of its section or above the limits of the decoder
+    OversizedLength {
+        section: &'static str,
+        length: u64,
+    },
+    /// Bytes are left after the end of a section or of the file
+    TrailingGarbage {
+        section: &'static str,
+        bytes: usize,
+    },
+    /// A varint doesn't fit in 32 bits
+    InvalidNumber {
+        section: &'static str,
+    },
+    /// The CRC of the section doesn't match its content
+    Corrupted {
+        section: &'static str,
+    },
+    MissingSection(&'static str),
+    /// The machine described by the header can't be built
+    UnsupportedMachine {
+        version: u32,
+        base: u8,
+        interupt_configuration: u32,
+    },
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "Not a Cythan binary"),
+            Self::UnsupportedVersion(a) => write!(f, "Unsupported header version {}", a),
+            Self::Truncated { section } => write!(f, "The {} section is truncated", section),
+            Self::OversizedLength { section, length } => {
+                write!(
+                    f,
+                    "Length {} is too large in the {} section",
+                    length, section
+                )
+            }
+            Self::TrailingGarbage { section, bytes } => {
+                write!(
+                    f,
+                    "{} unexpected bytes after the {} section",
+                    bytes, section
+                )
+            }
+            Self::InvalidNumber { section } => {
+                write!(f, "Invalid number in the {} section", section)
+            }
+            Self::Corrupted { section } => write!(f, "The {} section is corrupted", section),
+            Self::MissingSection(a) => write!(f, "The {} section is missing", a),
+            Self::UnsupportedMachine {
+                version,
+                base,
+                interupt_configuration,
+            } => write!(
+                f,
+                "Unsupported machine: Cythan {} in base {} with interrupt configuration {}",
+                version, base, interupt_configuration
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct HeaderData {
     pub header_version: u32,
     pub version: u32,                // The Cythan specification
     pub interupt_configuration: u32, // The interupt configuration
     pub base: u8,
     pub info_string: String,
+    /// Whether the memory is compressed, only in the files made of sections
+    pub compressed: bool,
 }
 
 impl Default for HeaderData {
     fn default() -> Self {
         HeaderData {
-            header_version: 1,
+            header_version: CONTAINER_VERSION,
             version: 4,
             interupt_configuration: 1,
             base: 4,
             info_string: String::new(),
+            compressed: false,
+        }
+    }
+}
+
+impl HeaderData {
+    /// The address of the interrupt of the machine, only the specification 4 in a base from 4 to
+    /// 16 with the interrupt configuration 1 (after the zeros and the constants) is supported
+    pub fn interrupt_place(&self) -> Result<usize, FormatError> {
+        match (self.version, self.base, self.interupt_configuration) {
+            (SPECIFICATION, 4..=16, 1) => Ok(2 * 2_usize.pow(self.base as u32) + 3),
+            _ => Err(FormatError::UnsupportedMachine {
+                version: self.version,
+                base: self.base,
+                interupt_configuration: self.interupt_configuration,
+            }),
         }
     }
 }
@@ -28,51 +146,141 @@ fn encode_u32(encoder: &mut Cursor<Vec<u8>>, data: u32) -> Result<(), Error> {
 
 fn encode_str(encoder: &mut Cursor<Vec<u8>>, data: &str) -> Result<(), Error> {
     encode_u32(encoder, data.len() as u32)?;
-    encoder.write(data.as_bytes()).map(|_| ())
+    encoder.write_all(data.as_bytes())
 }
 
-fn decode_str(encoder: &mut Cursor<Vec<u8>>) -> Result<String, Error> {
-    let len = decode_u32(encoder)?;
-    let mut buf = vec![0; len as usize];
-    encoder.read(&mut buf)?;
-    Ok(String::from_utf8_lossy(&buf).to_string())
+/// Reads the bytes of one section, without reading past its end
+struct Reader<'a> {
+    bytes: &'a [u8],
+    section: &'static str,
 }
 
-fn decode_u8(encoder: &mut Cursor<Vec<u8>>) -> Result<u8, Error> {
-    let mut k = [0];
-    encoder.read(&mut k)?;
-    Ok(k[0])
-}
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], section: &'static str) -> Self {
+        Self { bytes, section }
+    }
 
-fn decode_u32(encoder: &mut Cursor<Vec<u8>>) -> Result<u32, Error> {
-    encoder.read_unsigned_varint_32()
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        let (first, rest) = self.bytes.split_first().ok_or(FormatError::Truncated {
+            section: self.section,
+        })?;
+        self.bytes = rest;
+        Ok(*first)
+    }
+
+    /// A varint, `varint` only reads from cursors so it is decoded here
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        let mut value: u32 = 0;
+        for shift in (0..32).step_by(7) {
+            let byte = self.u8()?;
+            let bits = (byte & 0x7f) as u32;
+            // The fifth byte only has room for 4 bits
+            if shift == 28 && bits > 0xf {
+                break;
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(FormatError::InvalidNumber {
+            section: self.section,
+        })
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], FormatError> {
+        if length > self.bytes.len() {
+            return Err(FormatError::Truncated {
+                section: self.section,
+            });
+        }
+        let (bytes, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    /// A length prefix, each of the items it counts takes at least one byte
+    fn length(&mut self) -> Result<usize, FormatError> {
+        let length = self.u32()?;
+        if length as usize > self.bytes.len() {
+            return Err(FormatError::OversizedLength {
+                section: self.section,
+                length: length as u64,
+            });
+        }
+        Ok(length as usize)
+    }
+
+    fn str(&mut self) -> Result<String, FormatError> {
+        let length = self.length()?;
+        Ok(String::from_utf8_lossy(self.bytes(length)?).to_string())
+    }
+
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, FormatError>,
+    ) -> Result<Vec<T>, FormatError> {
+        (0..self.length()?).map(|_| item(self)).collect()
+    }
+
+    fn end(self) -> Result<(), FormatError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            bytes => Err(FormatError::TrailingGarbage {
+                section: self.section,
+                bytes,
+            }),
+        }
+    }
 }
 
-pub fn decode_bytes(bytes: &[u8]) -> Result<(HeaderData, Vec<u32>), Error> {
-    let mut encoded = Cursor::new(bytes.to_vec());
+/// Reads a file written by `encode_to_bytes`, whatever its header version
+pub fn decode_bytes(bytes: &[u8]) -> Result<(HeaderData, Vec<u32>), FormatError> {
+    if bytes.starts_with(&MAGIC) {
+        let container = Container::decode(bytes)?;
+        return Ok((container.header, container.memory));
+    }
+    let mut encoded = Reader::new(bytes, "header");
+    if encoded.u32() != Ok(1) {
+        return Err(FormatError::BadMagic);
+    }
     let header = HeaderData {
-        header_version: decode_u32(&mut encoded)?,
-        version: decode_u32(&mut encoded)?,
-        interupt_configuration: decode_u32(&mut encoded)?,
-        base: decode_u8(&mut encoded)?,
-        info_string: decode_str(&mut encoded)?,
+        header_version: 1,
+        version: encoded.u32()?,
+        interupt_configuration: encoded.u32()?,
+        base: encoded.u8()?,
+        info_string: encoded.str()?,
+        compressed: false,
     };
+    encoded.section = "memory";
+    let memory = decode_memory(encoded)?;
+    Ok((header, memory))
+}
 
-    let length = decode_u32(&mut encoded)?;
-    Ok((
-        header,
-        (0..length)
-            .map(|_| decode_u32(&mut encoded))
-            .collect::<Result<Vec<_>, _>>()?,
-    ))
+/// Writes the memory in a `Container` whose memory section is compressed with zlib
+pub fn encode_to_bytes_compressed(
+    header: HeaderData,
+    cythan_memory: &[u32],
+) -> Result<Vec<u8>, Error> {
+    let header = HeaderData {
+        header_version: CONTAINER_VERSION,
+        compressed: true,
+        ..header
+    };
+    Container::new(header, cythan_memory.to_vec()).encode()
 }
 
+/// Writes the memory in the layout of the header version, a `Container` without the optional
+/// sections from version 2
 pub fn encode_to_bytes(header: HeaderData, cythan_memory: &[u32]) -> Result<Vec<u8>, Error> {
+    if header.header_version != 1 {
+        return Container::new(header, cythan_memory.to_vec()).encode();
+    }
     let mut encoded = Cursor::new(Vec::new());
     encode_u32(&mut encoded, header.header_version)?;
     encode_u32(&mut encoded, header.version)?;
     encode_u32(&mut encoded, header.interupt_configuration)?;
-    encoded.write(&[header.base])?;
+    encoded.write_all(&[header.base])?;
     encode_str(&mut encoded, &header.info_string)?;
     encode_u32(&mut encoded, cythan_memory.len() as u32)?;
     for x in cythan_memory {
@@ -80,3 +288,513 @@ pub fn encode_to_bytes(header: HeaderData, cythan_memory: &[u32]) -> Result<Vec<
     }
     Ok(encoded.into_inner())
 }
+
+/// A named address of the memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+}
+
+/// The addresses of the labels and of the variables
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols {
+    pub labels: Vec<Symbol>,
+    pub variables: Vec<Symbol>,
+}
+
+/// A part of a source file, in bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// The cells from `start` to `end` (excluded) hold one instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugRange {
+    pub start: u32,
+    pub end: u32,
+    pub lir: String,
+    pub mir: String,
+    pub location: Option<SourceLocation>,
+}
+
+impl std::fmt::Display for DebugRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}..{}\t{}\t{}",
+            self.start, self.end, self.lir, self.mir
+        )?;
+        if let Some(e) = &self.location {
+            write!(f, " at {}:{}..{}", e.file, e.start, e.end)?;
+        }
+        Ok(())
+    }
+}
+
+/// A source file the program was compiled from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+}
+
+// The kinds of the sections, stored in their first byte
+const HEADER: u8 = 0;
+const MEMORY: u8 = 1;
+const SYMBOLS: u8 = 2;
+const DEBUG_INFO: u8 = 3;
+const SOURCES: u8 = 4;
+
+/// A binary made of sections, each followed by the CRC-32 of its content:
+///
+/// ```text
+/// MAGIC  header_version (2)  section count  (kind: u8, length, content, crc: u32 LE)*
+/// ```
+///
+/// The numbers are varints. The sections of unknown kinds are skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Container {
+    pub header: HeaderData,
+    pub memory: Vec<u32>,
+    pub symbols: Option<Symbols>,
+    pub debug_info: Option<Vec<DebugRange>>,
+    pub sources: Option<Vec<SourceFile>>,
+}
+
+impl Container {
+    pub fn new(header: HeaderData, memory: Vec<u32>) -> Self {
+        Self {
+            header,
+            memory,
+            symbols: None,
+            debug_info: None,
+            sources: None,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut sections = vec![
+            (HEADER, self.encode_header()?),
+            (MEMORY, self.encode_memory()?),
+        ];
+        if let Some(e) = &self.symbols {
+            sections.push((SYMBOLS, encode_symbols(e)?));
+        }
+        if let Some(e) = &self.debug_info {
+            sections.push((DEBUG_INFO, encode_debug_info(e)?));
+        }
+        if let Some(e) = &self.sources {
+            sections.push((SOURCES, encode_sources(e)?));
+        }
+        let mut encoded = Cursor::new(Vec::new());
+        encoded.write_all(&MAGIC)?;
+        encode_u32(&mut encoded, CONTAINER_VERSION)?;
+        encode_u32(&mut encoded, sections.len() as u32)?;
+        for (kind, content) in sections {
+            encoded.write_all(&[kind])?;
+            encode_u32(&mut encoded, content.len() as u32)?;
+            encoded.write_all(&content)?;
+            encoded.write_all(&crc(&content).to_le_bytes())?;
+        }
+        Ok(encoded.into_inner())
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut encoded = Reader::new(bytes, "file");
+        if encoded.bytes(MAGIC.len()) != Ok(&MAGIC) {
+            return Err(FormatError::BadMagic);
+        }
+        match encoded.u32()? {
+            CONTAINER_VERSION => (),
+            version => return Err(FormatError::UnsupportedVersion(version)),
+        }
+        let mut header = None;
+        let mut container = Self::new(HeaderData::default(), Vec::new());
+        let mut memory = None;
+        for _ in 0..encoded.length()? {
+            let kind = encoded.u8()?;
+            encoded.section = section_name(kind);
+            let length = encoded.u32()? as usize;
+            let content = encoded.bytes(length)?;
+            let checksum = encoded.bytes(4)?;
+            if crc(content) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+                return Err(FormatError::Corrupted {
+                    section: encoded.section,
+                });
+            }
+            let mut content = Reader::new(content, encoded.section);
+            match kind {
+                HEADER => header = Some(decode_header(&mut content)?),
+                MEMORY => {
+                    memory = Some(content.bytes);
+                    continue;
+                }
+                SYMBOLS => container.symbols = Some(decode_symbols(&mut content)?),
+                DEBUG_INFO => container.debug_info = Some(decode_debug_info(&mut content)?),
+                SOURCES => container.sources = Some(decode_sources(&mut content)?),
+                _ => continue,
+            }
+            content.end()?;
+        }
+        encoded.section = "file";
+        encoded.end()?;
+        container.header = header.ok_or(FormatError::MissingSection("header"))?;
+        let memory = memory.ok_or(FormatError::MissingSection("memory"))?;
+        container.memory = if container.header.compressed {
+            decode_memory(Reader::new(&decompress(memory)?, "memory"))?
+        } else {
+            decode_memory(Reader::new(memory, "memory"))?
+        };
+        Ok(container)
+    }
+
+    fn encode_header(&self) -> Result<Vec<u8>, Error> {
+        let mut encoded = Cursor::new(Vec::new());
+        encode_u32(&mut encoded, self.header.version)?;
+        encode_u32(&mut encoded, self.header.interupt_configuration)?;
+        encoded.write_all(&[self.header.base])?;
+        let flags = if self.header.compressed {
+            COMPRESSED
+        } else {
+            0
+        };
+        encoded.write_all(&[flags])?;
+        encode_str(&mut encoded, &self.header.info_string)?;
+        Ok(encoded.into_inner())
+    }
+
+    fn encode_memory(&self) -> Result<Vec<u8>, Error> {
+        let mut encoded = Cursor::new(Vec::new());
+        encode_u32(&mut encoded, self.memory.len() as u32)?;
+        for x in &self.memory {
+            encode_u32(&mut encoded, *x)?;
+        }
+        if !self.header.compressed {
+            return Ok(encoded.into_inner());
+        }
+        let mut compressor = ZlibEncoder::new(Vec::new(), Compression::best());
+        compressor.write_all(encoded.get_ref())?;
+        compressor.finish()
+    }
+}
+
+fn section_name(kind: u8) -> &'static str {
+    match kind {
+        HEADER => "header",
+        MEMORY => "memory",
+        SYMBOLS => "symbols",
+        DEBUG_INFO => "debug info",
+        SOURCES => "sources",
+        _ => "unknown",
+    }
+}
+
+/// Stops at `MAX_DECOMPRESSED` bytes so that a small file can't fill the memory
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>, FormatError> {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(bytes)
+        .take(MAX_DECOMPRESSED + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|_| FormatError::Corrupted { section: "memory" })?;
+    if decompressed.len() as u64 > MAX_DECOMPRESSED {
+        return Err(FormatError::OversizedLength {
+            section: "memory",
+            length: decompressed.len() as u64,
+        });
+    }
+    Ok(decompressed)
+}
+
+fn crc(bytes: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(bytes);
+    crc.sum()
+}
+
+fn decode_header(encoded: &mut Reader) -> Result<HeaderData, FormatError> {
+    Ok(HeaderData {
+        header_version: CONTAINER_VERSION,
+        version: encoded.u32()?,
+        interupt_configuration: encoded.u32()?,
+        base: encoded.u8()?,
+        compressed: encoded.u8()? & COMPRESSED != 0,
+        info_string: encoded.str()?,
+    })
+}
+
+/// The memory ends its section, or the file in the first version
+fn decode_memory(mut encoded: Reader) -> Result<Vec<u32>, FormatError> {
+    let length = encoded.length()?;
+    if length > MAX_CELLS {
+        return Err(FormatError::OversizedLength {
+            section: "memory",
+            length: length as u64,
+        });
+    }
+    let memory = (0..length)
+        .map(|_| encoded.u32())
+        .collect::<Result<_, _>>()?;
+    encoded.end()?;
+    Ok(memory)
+}
+
+fn encode_symbol_list(encoded: &mut Cursor<Vec<u8>>, symbols: &[Symbol]) -> Result<(), Error> {
+    encode_u32(encoded, symbols.len() as u32)?;
+    for x in symbols {
+        encode_str(encoded, &x.name)?;
+        encode_u32(encoded, x.address)?;
+    }
+    Ok(())
+}
+
+fn decode_symbol(encoded: &mut Reader) -> Result<Symbol, FormatError> {
+    Ok(Symbol {
+        name: encoded.str()?,
+        address: encoded.u32()?,
+    })
+}
+
+fn encode_symbols(symbols: &Symbols) -> Result<Vec<u8>, Error> {
+    let mut encoded = Cursor::new(Vec::new());
+    encode_symbol_list(&mut encoded, &symbols.labels)?;
+    encode_symbol_list(&mut encoded, &symbols.variables)?;
+    Ok(encoded.into_inner())
+}
+
+fn decode_symbols(encoded: &mut Reader) -> Result<Symbols, FormatError> {
+    Ok(Symbols {
+        labels: encoded.list(decode_symbol)?,
+        variables: encoded.list(decode_symbol)?,
+    })
+}
+
+fn encode_debug_info(ranges: &[DebugRange]) -> Result<Vec<u8>, Error> {
+    let mut encoded = Cursor::new(Vec::new());
+    encode_u32(&mut encoded, ranges.len() as u32)?;
+    for x in ranges {
+        encode_u32(&mut encoded, x.start)?;
+        encode_u32(&mut encoded, x.end)?;
+        encode_str(&mut encoded, &x.lir)?;
+        encode_str(&mut encoded, &x.mir)?;
+        match &x.location {
+            Some(e) => {
+                encoded.write_all(&[1])?;
+                encode_str(&mut encoded, &e.file)?;
+                encode_u32(&mut encoded, e.start)?;
+                encode_u32(&mut encoded, e.end)?;
+            }
+            None => encoded.write_all(&[0])?,
+        }
+    }
+    Ok(encoded.into_inner())
+}
+
+fn decode_debug_range(encoded: &mut Reader) -> Result<DebugRange, FormatError> {
+    Ok(DebugRange {
+        start: encoded.u32()?,
+        end: encoded.u32()?,
+        lir: encoded.str()?,
+        mir: encoded.str()?,
+        location: match encoded.u8()? {
+            0 => None,
+            _ => Some(SourceLocation {
+                file: encoded.str()?,
+                start: encoded.u32()?,
+                end: encoded.u32()?,
+            }),
+        },
+    })
+}
+
+fn decode_debug_info(encoded: &mut Reader) -> Result<Vec<DebugRange>, FormatError> {
+    encoded.list(decode_debug_range)
+}
+
+fn encode_sources(sources: &[SourceFile]) -> Result<Vec<u8>, Error> {
+    let mut encoded = Cursor::new(Vec::new());
+    encode_u32(&mut encoded, sources.len() as u32)?;
+    for x in sources {
+        encode_str(&mut encoded, &x.name)?;
+        encode_str(&mut encoded, &x.text)?;
+    }
+    Ok(encoded.into_inner())
+}
+
+fn decode_sources(encoded: &mut Reader) -> Result<Vec<SourceFile>, FormatError> {
+    encoded.list(|encoded| {
+        Ok(SourceFile {
+            name: encoded.str()?,
+            text: encoded.str()?,
+        })
+    })
+}
+
+#[test]
+fn test_container() {
+    let mut container = Container::new(HeaderData::default(), vec![2, 0, 7070, 1 << 20]);
+    container.symbols = Some(Symbols {
+        labels: vec![Symbol {
+            name: "start".to_owned(),
+            address: 1,
+        }],
+        variables: vec![],
+    });
+    container.debug_info = Some(vec![DebugRange {
+        start: 1,
+        end: 3,
+        lir: "stop".to_owned(),
+        mir: "stop".to_owned(),
+        location: Some(SourceLocation {
+            file: "Main.ct".to_owned(),
+            start: 4,
+            end: 9,
+        }),
+    }]);
+    container.sources = Some(vec![SourceFile {
+        name: "Main.ct".to_owned(),
+        text: "class Main {}".to_owned(),
+    }]);
+    let mut bytes = container.encode().unwrap();
+    assert_eq!(Container::decode(&bytes).unwrap(), container);
+    assert_eq!(decode_bytes(&bytes).unwrap().1, container.memory);
+    let last = bytes.len() - 6;
+    bytes[last] ^= 1;
+    assert!(Container::decode(&bytes).is_err());
+}
+
+#[test]
+fn test_decode_v1() {
+    let header = HeaderData {
+        header_version: 1,
+        ..HeaderData::default()
+    };
+    let bytes = encode_to_bytes(header, &[5, 6, 7]).unwrap();
+    let (header, memory) = decode_bytes(&bytes).unwrap();
+    assert_eq!((header.header_version, memory), (1, vec![5, 6, 7]));
+}
+
+#[test]
+fn test_compressed() {
+    let memory: Vec<u32> = (0..2000).map(|x| x % 17).collect();
+    let bytes = encode_to_bytes_compressed(HeaderData::default(), &memory).unwrap();
+    assert!(
+        bytes.len()
+            < encode_to_bytes(HeaderData::default(), &memory)
+                .unwrap()
+                .len()
+    );
+    let (header, decoded) = decode_bytes(&bytes).unwrap();
+    assert!(header.compressed);
+    assert_eq!(decoded, memory);
+}
+
+#[test]
+fn test_decode_errors() {
+    let bytes = encode_to_bytes(HeaderData::default(), &[5, 6, 7]).unwrap();
+    assert_eq!(decode_bytes(&[]).unwrap_err(), FormatError::BadMagic);
+    assert_eq!(decode_bytes(&[9]).unwrap_err(), FormatError::BadMagic);
+    assert_eq!(
+        decode_bytes(b"\x7fCCT\x09").unwrap_err(),
+        FormatError::UnsupportedVersion(9)
+    );
+    assert!(matches!(
+        decode_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+        FormatError::Truncated { section: "memory" }
+    ));
+    let mut garbage = bytes.clone();
+    garbage.push(0);
+    assert_eq!(
+        decode_bytes(&garbage).unwrap_err(),
+        FormatError::TrailingGarbage {
+            section: "file",
+            bytes: 1
+        }
+    );
+    // A string claiming to be 4 GiB long in a file of a few bytes
+    let v1 = [1, 4, 1, 4, 0xff, 0xff, 0xff, 0xff, 0x0f];
+    assert!(matches!(
+        decode_bytes(&v1).unwrap_err(),
+        FormatError::OversizedLength {
+            section: "header",
+            ..
+        }
+    ));
+    // The length ends with the file, then it doesn't fit in 32 bits
+    assert!(matches!(
+        decode_bytes(&v1[..6]).unwrap_err(),
+        FormatError::Truncated { section: "header" }
+    ));
+    let v1 = [1, 4, 1, 4, 0xff, 0xff, 0xff, 0xff, 0x7f];
+    assert_eq!(
+        decode_bytes(&v1).unwrap_err(),
+        FormatError::InvalidNumber { section: "header" }
+    );
+}
+
+#[test]
+fn test_varint() {
+    for number in [0, 1, 127, 128, 300, 1 << 28, u32::MAX].iter() {
+        let mut encoded = Cursor::new(Vec::new());
+        encode_u32(&mut encoded, *number).unwrap();
+        let bytes = encoded.into_inner();
+        let mut reader = Reader::new(&bytes, "test");
+        assert_eq!(reader.u32(), Ok(*number));
+        assert!(reader.bytes.is_empty());
+    }
+}
+
+/// Decoding mutated files must fail cleanly, `fuzz/fuzz_targets/decode_bytes.rs` explores further
+#[test]
+fn test_decode_mutations() {
+    let mut container = Container::new(HeaderData::default(), (0..300).collect());
+    container.sources = Some(vec![SourceFile {
+        name: "Main.ct".to_owned(),
+        text: "class Main {}".to_owned(),
+    }]);
+    let plain = container.encode().unwrap();
+    container.header.compressed = true;
+    let compressed = container.encode().unwrap();
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut next = |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % bound as u64) as usize
+    };
+    for bytes in [plain, compressed].iter() {
+        for _ in 0..2000 {
+            let mut mutated = bytes.clone();
+            for _ in 0..=next(4) {
+                let i = next(mutated.len());
+                mutated[i] = next(256) as u8;
+            }
+            mutated.truncate(next(bytes.len() + 1));
+            let _ = decode_bytes(&mutated);
+        }
+    }
+}
+
+#[test]
+fn test_interrupt_place() {
+    assert_eq!(HeaderData::default().interrupt_place(), Ok(35));
+    let header = HeaderData {
+        base: 5,
+        ..HeaderData::default()
+    };
+    assert_eq!(header.interrupt_place(), Ok(67));
+    // The registers and the interrupt don't fit in the smaller bases
+    let header = HeaderData {
+        base: 3,
+        ..HeaderData::default()
+    };
+    assert!(header.interrupt_place().is_err());
+    let header = HeaderData {
+        version: 5,
+        ..HeaderData::default()
+    };
+    assert!(header.interrupt_place().is_err());
+}
@@ -5,8 +5,8 @@ use std::io::{Read, Write};
 ///
 /// ```rust
 /// use cythan::{Cythan,InterruptedCythan};
-/// // This function create a Cythan Machine with a step of 2 and a base value of 0
-/// let machine = InterruptedCythan::new(vec![12,23,45,20,0]);
+/// // This function create a Cythan Machine in base 4 whose interrupt is after the constants
+/// let machine = InterruptedCythan::new(vec![12,23,45,20,0], 4, 35, |_| (), || 0);
 /// ```
 pub struct InterruptedCythan {
     pub cases: Vec<usize>,
@@ -53,7 +53,7 @@ impl InterruptedCythan {
     }
 }
 
-use crate::cythan::Cythan;
+use crate::{cythan::Cythan, trace::Tracer};
 
 impl InterruptedCythan {
 
@@ -78,6 +78,19 @@ impl InterruptedCythan {
             self.set_value_interupt(c1, self.get_value(c2))
         }
     }
+    /// The high and the low digit of a character read by the input interrupt. Zero is written as
+    /// the base like every other zero of the machine: `if_0` and `match` jump through the cell a
+    /// digit points to, and cell 0 is the program counter.
+    fn input_digits(&self, character: u8) -> (usize, usize) {
+        // From the base 8 the base doesn't fit in a byte
+        let character = character as usize;
+        let digit = |x: usize| if x == 0 { self.base_as_pow } else { x };
+        (
+            digit(character / self.base_as_pow),
+            digit(character % self.base_as_pow),
+        )
+    }
+
     #[inline]
     fn set_value_interupt(&mut self, index: usize, value: usize) -> bool{
         if index == self.interrupt_place {
@@ -120,6 +133,20 @@ impl Cythan for InterruptedCythan {
         }
     }
 
+    fn next_traced(&mut self, tracer: &mut dyn Tracer) {
+        let address = self.get_value(0);
+        let cell = self.get_value(address + 1);
+        let input = cell == self.interrupt_place && self.get_value(self.get_value(address)) == 2;
+        tracer.step(address);
+        self.next();
+        tracer.write(cell);
+        if input {
+            // Reading a character writes it after the interrupt
+            tracer.write(cell + 1);
+            tracer.write(cell + 2);
+        }
+    }
+
     #[inline]
     fn get_value(&self, index: usize) -> usize {
         if let Some(e) = self.cases.get(index) {
@@ -148,11 +175,10 @@ impl Cythan for InterruptedCythan {
                 // println!("INPUT");
                 //let o: u8 = std::io::stdin().bytes().next().unwrap().unwrap();
                 let o: u8 = (self.input_provider)();
-                let a = o % self.base_as_pow as u8;
-                let b = o / self.base_as_pow as u8;
+                let (b, a) = self.input_digits(o);
                 // println!("vals:{} {}",a,b);
-                self.set_value(self.interrupt_place + 1, b as usize);
-                self.set_value(self.interrupt_place + 2, a as usize);
+                self.set_value(self.interrupt_place + 1, b);
+                self.set_value(self.interrupt_place + 2, a);
             }
         }
         if self.cases.len() <= index {
@@ -167,3 +193,48 @@ impl Cythan for InterruptedCythan {
         }
     }
 }
+
+/// The digits of `@` and `\n` include a zero, which must be read as the base
+#[test]
+fn test_input_zero_digit() {
+    use std::{cell::Cell, rc::Rc};
+    let printed = Rc::new(Cell::new(0));
+    let printed2 = printed.clone();
+    let input = Rc::new(Cell::new(b'@'));
+    let input2 = input.clone();
+    let place = 2 * 16 + 3;
+    let mut machine =
+        InterruptedCythan::new(vec![], 4, place, move |x| printed2.set(x), move || input2.get());
+    for (character, digits) in [(b'@', (4, 16)), (b'\n', (16, 10)), (0, (16, 16))].iter() {
+        input.set(*character);
+        machine.set_value(place, 2);
+        assert_eq!(
+            (machine.get_value(place + 1), machine.get_value(place + 2)),
+            *digits
+        );
+        machine.set_value(place, 1);
+        assert_eq!(printed.get(), *character);
+    }
+}
+
+/// The characters are read and printed in any base large enough for a byte to fit in two digits
+#[test]
+fn test_input_bases() {
+    use std::{cell::Cell, rc::Rc};
+    for base in [4, 5, 8, 16].iter().copied() {
+        let printed = Rc::new(Cell::new(0));
+        let printed2 = printed.clone();
+        let place = 2 * 2_usize.pow(base as u32) + 3;
+        let mut machine =
+            InterruptedCythan::new(vec![], base, place, move |x| printed2.set(x), || b'A');
+        machine.set_value(place, 2);
+        let digits = 2_usize.pow(base as u32);
+        let digit = |x: usize| if x == 0 { digits } else { x };
+        assert_eq!(
+            (machine.get_value(place + 1), machine.get_value(place + 2)),
+            (digit(b'A' as usize / digits), digit(b'A' as usize % digits))
+        );
+        machine.set_value(place, 1);
+        assert_eq!(printed.get(), b'A');
+    }
+}
@@ -36,5 +36,7 @@ pub mod implementations;
 pub use implementations::*;
 
 pub mod format;
+pub mod trace;
 
 pub use crate::cythan::Cythan;
+pub use trace::{Profile, Tracer};
@@ -0,0 +1,74 @@
+/// Observes the iterations of a machine, see `Cythan::next_traced`
+pub trait Tracer {
+    /// The pair of cells starting at `address` is executed
+    fn step(&mut self, address: usize);
+
+    /// The iteration wrote to `cell`
+    fn write(&mut self, cell: usize);
+}
+
+/// Counts the executions of each pair and the writes to each cell
+///
+/// ```rust
+/// use cythan::{BasicCythan, Cythan, Profile};
+/// let mut cythan = BasicCythan::new(vec![1, 5, 3, 0, 0, 999]);
+/// let mut profile = Profile::default();
+/// cythan.next_traced(&mut profile);
+/// assert_eq!(profile.executed(1), 1);
+/// assert_eq!(profile.written(3), 1);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub steps: usize,
+    /// By address of the pair
+    pub executed: Vec<usize>,
+    /// By cell
+    pub writes: Vec<usize>,
+}
+
+impl Profile {
+    pub fn executed(&self, address: usize) -> usize {
+        self.executed.get(address).copied().unwrap_or(0)
+    }
+
+    pub fn written(&self, cell: usize) -> usize {
+        self.writes.get(cell).copied().unwrap_or(0)
+    }
+}
+
+/// Adds one to the counter at `index`, the counters past the end of the vector are 0
+fn count(counters: &mut Vec<usize>, index: usize) {
+    if counters.len() <= index {
+        counters.resize(index + 1, 0);
+    }
+    counters[index] += 1;
+}
+
+impl Tracer for Profile {
+    fn step(&mut self, address: usize) {
+        self.steps += 1;
+        count(&mut self.executed, address);
+    }
+
+    fn write(&mut self, cell: usize) {
+        count(&mut self.writes, cell);
+    }
+}
+
+#[test]
+fn test_profile() {
+    use crate::{BasicCythan, Cythan};
+    let mut cythan = BasicCythan::new(vec![1, 9, 5, 10, 1, 0, 0, 11, 0, 1, 20, 21]);
+    let mut profile = Profile::default();
+    for _ in 0..10 {
+        cythan.next_traced(&mut profile);
+    }
+    assert_eq!(cythan.cases, vec![34, 20, 5, 10, 1, 1, 0, 11, 0, 1, 20, 21]);
+    assert_eq!(profile.steps, 10);
+    assert_eq!(profile.executed.iter().sum::<usize>(), 10);
+    // The third pair jumps past the end of the code, where each pair copies the program counter
+    assert_eq!(profile.executed(5), 1);
+    assert_eq!(profile.executed(20), 1);
+    assert_eq!(profile.written(5), 1);
+    assert_eq!(profile.written(0), 8);
+}
@@ -1,4 +1,8 @@
-use std::{cmp::Ordering, ops::Range};
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+    ops::Range,
+};
 
 use ariadne::{Color, ColorGenerator, Fmt, Label, Report, ReportBuilder, ReportKind};
 
@@ -10,7 +14,7 @@ mod wrappers;
 
 pub use wrappers::*;
 
-pub use reporting::report;
+pub use reporting::{render, report};
 
 #[derive(Debug, Clone, Eq)]
 pub struct Span {
@@ -41,6 +45,11 @@ impl PartialEq for Span {
     }
 }
 
+// Like the equality, the hash doesn't depend on where the code is
+impl Hash for Span {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
 impl Span {
     pub fn as_range(&self) -> Range<usize> {
         Range {
@@ -330,6 +339,61 @@ pub fn method_return_type_invalid(
         )
 }
 
+/// `chain` holds each method of the cycle and where it is called, the method called again is
+/// the last one
+pub fn recursive_method(chain: &[(String, Span)]) -> Error {
+    let mut colors = ColorGenerator::new();
+    let a = colors.next();
+    let b = colors.next();
+    let (name, span) = chain.last().expect("Empty call chain");
+    let er = Report::build(ReportKind::Error, span.file.to_owned(), span.start)
+        .with_code(19)
+        .with_message(format!("Method {} calls itself", name))
+        .with_note(format!(
+            "{}\nAnnotate the method with {} to call it with a stack",
+            chain
+                .iter()
+                .map(|(x, _)| x.as_str())
+                .collect::<Vec<_>>()
+                .join(" -> "),
+            "@Recursive".fg(b)
+        ));
+    chain.iter().enumerate().fold(er, |er, (i, (name, span))| {
+        er.with_label(
+            Label::new(span.as_span())
+                .with_message(format!("{}. Calls {}", i + 1, name.fg(a)))
+                .with_color(a),
+        )
+    })
+}
+
+pub fn invalid_recursion_depth(span: &Span, max: u8) -> Error {
+    let mut colors = ColorGenerator::new();
+    let a = colors.next();
+    Report::build(ReportKind::Error, span.file.to_owned(), span.start)
+        .with_code(20)
+        .with_message("Invalid recursion depth")
+        .with_label(
+            Label::new(span.as_span())
+                .with_message(format!("The depth must be a number from 1 to {}", max))
+                .with_color(a),
+        )
+}
+
+pub fn nesting_too_deep(span: &Span, max: usize) -> Error {
+    let mut colors = ColorGenerator::new();
+    let a = colors.next();
+    Report::build(ReportKind::Error, span.file.to_owned(), span.start)
+        .with_code(21)
+        .with_message("Code nested too deeply")
+        .with_label(
+            Label::new(span.as_span())
+                .with_message(format!("More than {} levels deep", max.to_string().fg(a)))
+                .with_color(a),
+        )
+        .with_note("Split the code in smaller methods or raise the nesting limit")
+}
+
 pub fn invalid_length_asm(this: &Span, length1: u32, length2: u32) -> Error {
     let mut colors = ColorGenerator::new();
     let a = colors.next();
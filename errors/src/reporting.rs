@@ -1,8 +1,8 @@
-use ariadne::FnCache;
+use ariadne::{FnCache, Report};
 
 use crate::Error;
 
-use std::fmt::Debug;
+use std::{collections::HashMap, fmt::Debug, ops::Range};
 
 use super::mirrors::{MirrorLabel, MirrorReport};
 
@@ -15,7 +15,8 @@ fn provider(x: &String) -> Result<String, Box<(dyn Debug + 'static)>> {
     }
 }
 
-pub fn report(e: Error) {
+/// Points the errors in native code at the native message
+fn finish(e: Error) -> Report<(String, Range<usize>)> {
     let mut e = e.finish();
     let mirror = MirrorReport::from(&mut e);
     let file_name = mirror.location.0.clone();
@@ -25,5 +26,25 @@ pub fn report(e: Error) {
             MirrorLabel::from(l).span.1 = 0..("Error originated from native context".len());
         });
     }
-    e.print(FnCache::new(provider)).unwrap();
+    e
+}
+
+pub fn report(e: Error) {
+    finish(e).print(FnCache::new(provider)).unwrap();
+}
+
+/// Renders the error with the sources given by file name instead of reading the files
+pub fn render(e: Error, sources: &HashMap<String, String>) -> String {
+    let mut out = Vec::new();
+    finish(e)
+        .write(
+            FnCache::new(|x: &String| match sources.get(x) {
+                Some(e) => Ok(e.clone()),
+                None if x == "<internal>" || x == "<native>" => provider(x),
+                None => Err(Box::new(format!("Unknown source {}", x)) as Box<dyn Debug>),
+            }),
+            &mut out,
+        )
+        .unwrap();
+    String::from_utf8_lossy(&out).into_owned()
 }
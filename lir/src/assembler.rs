@@ -0,0 +1,369 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{AsmValue, CompilableInstruction, Counter, DebugEntry, DebugInfo, Label, Origin, Var};
+
+/// Separates the parts of the memory to make dumps easier to read
+const DELIMITER: usize = 7070;
+/// The value of the cells overwritten before being read
+const EARASABLE: usize = 999;
+
+/// Something whose address is only known once the memory is laid out
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Symbol {
+    Start,
+    /// `'#N`, the cell holding N (0 is stored as 16)
+    Constant(u8),
+    /// `'#return_N`
+    Register(u8),
+    Var(usize),
+    Label(Label),
+    /// A label private to one expansion of a macro
+    Local(usize),
+}
+
+#[derive(Debug)]
+enum Cell {
+    Value(usize),
+    Address(Symbol),
+}
+
+/// Lays out LIR in the memory of the machine, the same way `template.ct` does
+#[derive(Default)]
+pub struct Assembler {
+    cells: Vec<Cell>,
+    symbols: HashMap<Symbol, usize>,
+    locals: Counter,
+    debug_info: Option<DebugInfo>,
+    origin: Option<Origin>,
+}
+
+impl Assembler {
+    /// Also maps the addresses to the instructions when `debug_info` is set
+    pub fn assemble(
+        instrs: &[CompilableInstruction],
+        debug_info: bool,
+    ) -> (Vec<usize>, Option<DebugInfo>) {
+        let mut asm = Self {
+            debug_info: debug_info.then(DebugInfo::default),
+            ..Self::default()
+        };
+        asm.address(Symbol::Start);
+        (0..16).for_each(|_| asm.value(0));
+        for i in 0..16 {
+            asm.define(Symbol::Constant(i));
+            asm.value(if i == 0 { 16 } else { i as usize });
+        }
+        asm.value(0);
+        asm.value(DELIMITER);
+        for i in 0..16 {
+            asm.define(Symbol::Register(i));
+            asm.value(16);
+        }
+        asm.value(DELIMITER);
+        for var in variables(instrs) {
+            // Variables start at 0 which is stored as 16 like in the constants
+            asm.define(Symbol::Var(var));
+            asm.value(16);
+        }
+        asm.value(DELIMITER);
+        asm.define(Symbol::Start);
+        asm.no_op();
+        instrs.iter().for_each(|i| asm.instruction(i));
+        asm.copy(Symbol::Constant(0), Symbol::Register(0));
+        asm.stop();
+        asm.finish()
+    }
+
+    fn finish(mut self) -> (Vec<usize>, Option<DebugInfo>) {
+        if let Some(debug_info) = &mut self.debug_info {
+            for (symbol, address) in &self.symbols {
+                let name = match symbol {
+                    Symbol::Start => "'start".to_owned(),
+                    Symbol::Constant(a) => format!("'#{}", a),
+                    Symbol::Register(a) => format!("'#return_{}", a),
+                    Symbol::Var(a) => {
+                        debug_info.variables.push((Var(*a).to_string(), *address));
+                        continue;
+                    }
+                    Symbol::Label(a) => a.to_string(),
+                    Symbol::Local(_) => continue,
+                };
+                debug_info.labels.push((name, *address));
+            }
+            debug_info.labels.sort_by_key(|x| x.1);
+            debug_info.variables.sort_by_key(|x| x.1);
+        }
+        let symbols = self.symbols;
+        let memory = self
+            .cells
+            .into_iter()
+            .map(|cell| match cell {
+                Cell::Value(a) => a,
+                Cell::Address(a) => *symbols
+                    .get(&a)
+                    .unwrap_or_else(|| panic!("Undefined symbol {:?}", a)),
+            })
+            .collect();
+        (memory, self.debug_info)
+    }
+
+    fn instruction(&mut self, instr: &CompilableInstruction) {
+        let start = self.cells.len();
+        self.lay_out(instr);
+        let end = self.cells.len();
+        if let Some(debug_info) = &mut self.debug_info {
+            if end > start {
+                debug_info.entries.push(DebugEntry {
+                    start,
+                    end,
+                    lir: instr.to_string(),
+                    origin: self.origin.clone(),
+                });
+            }
+        }
+    }
+
+    fn lay_out(&mut self, instr: &CompilableInstruction) {
+        match instr {
+            CompilableInstruction::Copy(a, b) => self.copy(value(b), var(a)),
+            CompilableInstruction::Increment(a) => self.inc(var(a)),
+            CompilableInstruction::Decrement(a) => self.dec(var(a)),
+            CompilableInstruction::Jump(a) => self.jump(Symbol::Label(a.clone())),
+            CompilableInstruction::Label(a) => {
+                self.define(Symbol::Label(a.clone()));
+                self.no_op();
+            }
+            CompilableInstruction::If0(a, b) => self.if_0(var(a), Symbol::Label(b.clone())),
+            CompilableInstruction::Match(a, b) => {
+                self.table(var(a), b.clone().map(|x| x.map(Symbol::Label)))
+            }
+            CompilableInstruction::Stop => self.stop(),
+            CompilableInstruction::Origin(a) => self.origin = Some(a.clone()),
+            CompilableInstruction::ReadRegister(a, b) => self.copy(Symbol::Register(b.0), var(a)),
+            CompilableInstruction::WriteRegister(a, b) => {
+                self.copy(value(b), Symbol::Register(a.0))
+            }
+        }
+    }
+
+    fn value(&mut self, value: usize) {
+        self.cells.push(Cell::Value(value));
+    }
+
+    fn address(&mut self, symbol: Symbol) {
+        self.cells.push(Cell::Address(symbol));
+    }
+
+    /// `~N`
+    fn relative(&mut self, offset: isize) {
+        self.value((self.cells.len() as isize + offset) as usize);
+    }
+
+    fn define(&mut self, symbol: Symbol) {
+        self.symbols.insert(symbol, self.cells.len());
+    }
+
+    fn local(&mut self) -> Symbol {
+        Symbol::Local(self.locals.count())
+    }
+
+    fn copy(&mut self, from: Symbol, to: Symbol) {
+        self.address(from);
+        self.address(to);
+    }
+
+    fn no_op(&mut self) {
+        self.value(1);
+        self.value(1);
+    }
+
+    fn stop(&mut self) {
+        self.relative(2);
+        self.value(0);
+        self.relative(-2);
+    }
+
+    fn jump(&mut self, to: Symbol) {
+        self.relative(2);
+        self.value(0);
+        self.address(to);
+    }
+
+    /// Replaces the value at `a` by the one `next` maps it to
+    fn step(&mut self, a: Symbol, next: impl Fn(u8) -> usize) {
+        let test = self.local();
+        self.copy(a.clone(), test.clone());
+        for i in (0..16).rev() {
+            self.address(Symbol::Constant(i));
+            self.value(next(i));
+        }
+        self.define(test);
+        self.value(EARASABLE);
+        self.address(a);
+    }
+
+    fn inc(&mut self, a: Symbol) {
+        self.step(a, |i| match i {
+            0 => 15,
+            1 => 16,
+            i => i as usize - 1,
+        });
+    }
+
+    fn dec(&mut self, a: Symbol) {
+        self.step(a, |i| i as usize + 1);
+    }
+
+    fn if_0(&mut self, a: Symbol, to: Symbol) {
+        let (test, pointer, end, end1) = (self.local(), self.local(), self.local(), self.local());
+        self.copy(a, test.clone());
+        self.address(pointer.clone());
+        self.value(16);
+        for i in (1..16).rev() {
+            self.address(end.clone());
+            self.value(i);
+        }
+        self.define(test);
+        self.value(EARASABLE);
+        self.value(0);
+        self.jump(end1.clone());
+        self.define(pointer);
+        self.address(to);
+        self.define(end);
+        self.relative(1);
+        self.define(end1);
+        self.no_op();
+    }
+
+    /// Jumps to the target of the value at `a`, or after the table if it has none
+    fn table(&mut self, a: Symbol, targets: [Option<Symbol>; 16]) {
+        let (test, end, end1) = (self.local(), self.local(), self.local());
+        let pointers: Vec<Symbol> = (0..16).map(|_| self.local()).collect();
+        self.copy(a, test.clone());
+        for (i, x) in targets.iter().enumerate() {
+            // The jump table entry of 0 is stored at 16
+            let entry = if i == 0 { 16 } else { i };
+            match x {
+                Some(_) => self.address(pointers[i].clone()),
+                None => self.address(end.clone()),
+            }
+            self.value(entry);
+        }
+        self.define(test);
+        self.value(EARASABLE);
+        self.value(0);
+        self.jump(end1.clone());
+        for (pointer, x) in pointers.into_iter().zip(targets) {
+            self.define(pointer);
+            self.address(x.unwrap_or_else(|| end.clone()));
+        }
+        self.define(end);
+        self.relative(1);
+        self.define(end1);
+        self.no_op();
+    }
+}
+
+fn var(var: &Var) -> Symbol {
+    Symbol::Var(var.0)
+}
+
+fn value(value: &AsmValue) -> Symbol {
+    match value {
+        AsmValue::Var(a) => var(a),
+        AsmValue::Number(a) => Symbol::Constant(a.0),
+    }
+}
+
+/// The variables in the order they are first used
+fn variables(instrs: &[CompilableInstruction]) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    let mut add = |a: &Var| {
+        if seen.insert(a.0) {
+            out.push(a.0);
+        }
+    };
+    for i in instrs {
+        match i {
+            CompilableInstruction::Copy(a, b) => {
+                add(a);
+                if let AsmValue::Var(b) = b {
+                    add(b);
+                }
+            }
+            CompilableInstruction::Increment(a)
+            | CompilableInstruction::Decrement(a)
+            | CompilableInstruction::If0(a, _)
+            | CompilableInstruction::Match(a, _)
+            | CompilableInstruction::ReadRegister(a, _) => add(a),
+            CompilableInstruction::WriteRegister(_, AsmValue::Var(b)) => add(b),
+            CompilableInstruction::Jump(_)
+            | CompilableInstruction::Label(_)
+            | CompilableInstruction::Stop
+            | CompilableInstruction::Origin(_)
+            | CompilableInstruction::WriteRegister(_, AsmValue::Number(_)) => (),
+        }
+    }
+    out
+}
+
+#[test]
+fn layout() {
+    use crate::{LabelType, Number};
+    let l = Label(0, LabelType::LoopStart);
+    let (memory, _) = Assembler::assemble(
+        &[
+            CompilableInstruction::Copy(Var(0), AsmValue::Number(Number(3))),
+            CompilableInstruction::Label(l.clone()),
+            CompilableInstruction::Decrement(Var(0)),
+            CompilableInstruction::If0(Var(0), l),
+            CompilableInstruction::WriteRegister(Number(1), AsmValue::Var(Var(0))),
+            CompilableInstruction::Stop,
+        ],
+        false,
+    );
+    // Produced by assembling `compile_to_string` with the text assembler
+    assert_eq!(
+        memory,
+        [
+            54, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 16, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10,
+            11, 12, 13, 14, 15, 0, 7070, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+            16, 16, 7070, 16, 7070, 1, 1, 20, 52, 1, 1, 52, 94, 32, 16, 31, 15, 30, 14, 29, 13, 28,
+            12, 27, 11, 26, 10, 25, 9, 24, 8, 23, 7, 22, 6, 21, 5, 20, 4, 19, 3, 18, 2, 17, 1, 999,
+            52, 52, 130, 135, 16, 136, 15, 136, 14, 136, 13, 136, 12, 136, 11, 136, 10, 136, 9,
+            136, 8, 136, 7, 136, 6, 136, 5, 136, 4, 136, 3, 136, 2, 136, 1, 999, 0, 134, 0, 137,
+            58, 137, 1, 1, 52, 36, 143, 0, 141, 17, 35, 148, 0, 146
+        ]
+    );
+}
+
+#[test]
+fn debug_info() {
+    use crate::Number;
+    let origin = Origin {
+        mir: "v0 = 3".to_owned(),
+        span: None,
+        methods: Vec::new(),
+    };
+    let (_, debug_info) = Assembler::assemble(
+        &[
+            CompilableInstruction::Origin(origin.clone()),
+            CompilableInstruction::Copy(Var(0), AsmValue::Number(Number(3))),
+            CompilableInstruction::Stop,
+        ],
+        true,
+    );
+    let debug_info = debug_info.unwrap();
+    // The code starts after the header, the variable and the first `no_op`
+    let entry = debug_info.lookup(57).unwrap();
+    assert_eq!((entry.start, entry.end), (56, 58));
+    assert_eq!(entry.lir, "$0 = 3");
+    assert_eq!(entry.origin, Some(origin.clone()));
+    assert_eq!(debug_info.lookup(58).unwrap().lir, "stop");
+    assert!(debug_info.lookup(55).is_none());
+    assert!(debug_info.lookup(61).is_none());
+    assert_eq!(debug_info.variables, [("'v0".to_owned(), 52)]);
+    assert_eq!(debug_info.labels[0], ("'#0".to_owned(), 17));
+    assert!(debug_info.labels.contains(&("'start".to_owned(), 54)));
+}
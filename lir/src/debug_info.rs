@@ -0,0 +1,66 @@
+use std::fmt::Display;
+
+use errors::Span;
+
+/// Where the instructions following a `CompilableInstruction::Origin` were lowered from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    /// The MIR node
+    pub mir: String,
+    /// The statement the MIR node was generated for, if it comes from the source code
+    pub span: Option<Span>,
+    /// The methods the node was inlined in, the outermost first, when the compiler marks them
+    pub methods: Vec<String>,
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.mir)?;
+        if let Some(span) = &self.span {
+            write!(f, " at {}:{}..{}", span.file, span.start, span.end)?;
+        }
+        Ok(())
+    }
+}
+
+/// The memory cells of one LIR instruction
+#[derive(Debug, Clone)]
+pub struct DebugEntry {
+    pub start: usize,
+    /// The address after the last cell of the instruction
+    pub end: usize,
+    pub lir: String,
+    pub origin: Option<Origin>,
+}
+
+/// Maps the addresses of a binary back to the code it was compiled from
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    /// Sorted by address, the instructions that take no cell have no entry
+    pub entries: Vec<DebugEntry>,
+    /// The names of the labels, constants and registers with their addresses, sorted by address
+    pub labels: Vec<(String, usize)>,
+    /// The names of the variables with their addresses, sorted by address
+    pub variables: Vec<(String, usize)>,
+}
+
+impl DebugInfo {
+    /// The instruction whose cells contain the address
+    pub fn lookup(&self, address: usize) -> Option<&DebugEntry> {
+        let i = self.entries.partition_point(|x| x.end <= address);
+        self.entries.get(i).filter(|x| x.start <= address)
+    }
+}
+
+impl Display for DebugInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.entries {
+            write!(f, "{}..{}\t{}", entry.start, entry.end, entry.lir)?;
+            if let Some(origin) = &entry.origin {
+                write!(f, "\t{}", origin)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,15 @@
 use std::{borrow::Cow, collections::HashSet, fmt::Display};
 
-use crate::{label::Label, number::Number, optimizer, value::AsmValue, var::Var, Counter};
+use crate::{
+    assembler::Assembler,
+    debug_info::{DebugInfo, Origin},
+    label::Label,
+    number::Number,
+    optimizer,
+    value::AsmValue,
+    var::Var,
+    Counter,
+};
 
 use super::template::Template;
 
@@ -22,6 +31,8 @@ pub enum CompilableInstruction {
     Match(Var, [Option<Label>; 16]),
     ReadRegister(Var, Number),
     WriteRegister(Number, AsmValue),
+    /// Takes no cell, tells where the next instructions come from
+    Origin(Origin),
 }
 
 #[test]
@@ -54,6 +65,34 @@ fn test_v3() {
     println!("{}", template.build());
 }
 
+#[test]
+fn test_zero_is_16() {
+    use crate::Number;
+    // Like the constants, the variables and the return registers store 0 as 16
+    let text = CompilableInstruction::compile_to_string(vec![
+        CompilableInstruction::ReadRegister(Var(1), Number(5)),
+        CompilableInstruction::Stop,
+    ]);
+    assert!(text.contains("'v1:16"));
+    assert!(text.contains("'#return_5:16"));
+}
+
+#[test]
+fn test_match_without_0() {
+    let mut counter = crate::Counter::default();
+    let mut labels: [Option<Label>; 16] = Default::default();
+    labels[1..].iter_mut().for_each(|x| {
+        *x = Some(Label::alloc(&mut counter, crate::LabelType::BlockEnd));
+    });
+    let text = CompilableInstruction::compile_to_string(vec![
+        CompilableInstruction::Match(Var(1), labels),
+        CompilableInstruction::Stop,
+    ]);
+    // The entry of 0 goes to the end of the match, it is stored at 16
+    assert!(text.lines().any(|x| x.starts_with("'end_") && x.ends_with(" 16")));
+    assert!(!text.lines().any(|x| x.starts_with("'end_") && x.ends_with(" 0")));
+}
+
 impl CompilableInstruction {
     pub fn optimize(instrs: Vec<Self>) -> Vec<Self> {
         optimizer::opt_asm(instrs)
@@ -66,15 +105,20 @@ impl CompilableInstruction {
             .for_each(|i| i.compile_inner(&mut compile_state, &mut ctx));
         compile_state.build()
     }
+    /// Lays out the memory `compile_to_string` describes, without going through the text
     pub fn compile_to_binary(instrs: Vec<Self>) -> Vec<usize> {
-        let ks = Self::compile_to_string(instrs);
-        std::fs::write("target/v3.ct", &ks);
-        cythan_compiler::compile(&ks).unwrap()
+        Assembler::assemble(&instrs, false).0
+    }
+    /// Like `compile_to_binary`, also tells which instruction each address belongs to
+    pub fn compile_with_debug_info(instrs: Vec<Self>) -> (Vec<usize>, DebugInfo) {
+        let (memory, debug_info) = Assembler::assemble(&instrs, true);
+        (memory, debug_info.unwrap_or_default())
     }
     fn check_compile_var(var: &Var, template: &mut Template, ctx: &mut Context) {
         if !ctx.variables.contains(&var.0) {
             ctx.variables.insert(var.0);
-            template.add_section("VAR_DEF", Cow::Owned(format!("{}:0", var)));
+            // Variables start at 0 which is stored as 16 like in the constants
+            template.add_section("VAR_DEF", Cow::Owned(format!("{}:16", var)));
         }
     }
 
@@ -113,9 +157,13 @@ impl CompilableInstruction {
                     "{a} 'test_{k} \n{}\n'test_{k}:earasable 0\njump('end1_{k})\n{}\n'end_{k}:~+1\n'end1_{k}:no_op",
                     b.iter()
                         .enumerate()
-                        .map(|(i, x)| match x {
-                            Some(_) => format!("'pt{}_{k} {}", i, if i == 0 { 16 } else { i }),
-                            None => format!("'end_{k} {}", i),
+                        .map(|(i, x)| {
+                            // The jump table entry of 0 is stored at 16
+                            let entry = if i == 0 { 16 } else { i };
+                            match x {
+                                Some(_) => format!("'pt{}_{k} {}", i, entry),
+                                None => format!("'end_{k} {}", entry),
+                            }
                         })
                         .collect::<Vec<_>>()
                         .join("\n"),
@@ -130,7 +178,9 @@ impl CompilableInstruction {
                 )))
             }
             Self::Stop => template.add_code(Cow::Borrowed("stop")),
+            Self::Origin(a) => template.add_code(Cow::Owned(format!("# {}", a))),
             Self::ReadRegister(a, b) => {
+                Self::check_compile_var(a, template, ctx);
                 template.add_code(Cow::Owned(format!("'#return_{} {}", b.0, a)));
             }
             Self::WriteRegister(a, b) => match b {
@@ -178,6 +228,7 @@ impl Display for CompilableInstruction {
                     .join(", ")
             ),
             Self::Stop => write!(f, "stop"),
+            Self::Origin(a) => write!(f, "# {}", a),
             Self::ReadRegister(a, b) => write!(f, "${} = @{}", a.0, b.0),
             Self::WriteRegister(a, b) => write!(
                 f,
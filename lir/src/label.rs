@@ -13,6 +13,8 @@ pub enum LabelType {
     IfEnd,
     BlockEnd,
     Match,
+    ProcedureStart,
+    Return,
 }
 
 impl Display for LabelType {
@@ -27,6 +29,8 @@ impl Display for LabelType {
                 LabelType::IfEnd => 'F',
                 LabelType::BlockEnd => 'G',
                 LabelType::Match => 'H',
+                LabelType::ProcedureStart => 'I',
+                LabelType::Return => 'J',
             }
         )
     }
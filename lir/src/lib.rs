@@ -1,5 +1,7 @@
 #![feature(format_args_capture)]
 
+mod assembler;
+mod debug_info;
 mod instruction;
 mod label;
 mod number;
@@ -8,6 +10,7 @@ mod template;
 mod value;
 mod var;
 
+pub use debug_info::*;
 pub use instruction::*;
 pub use label::*;
 pub use number::*;
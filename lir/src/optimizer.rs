@@ -13,11 +13,15 @@ pub fn opt_asm(input: Vec<CompilableInstruction>) -> Vec<CompilableInstruction>
     for el in input {
         if let CompilableInstruction::Jump(b) = &el {
             in_jump = true;
+            // The origins between the labels are kept without preventing their removal
+            let mut origins = Vec::new();
             loop {
                 match out.pop() {
-                    Some(CompilableInstruction::Label(a)) => {
+                    // A label followed by a jump to itself is an infinite loop and must be kept
+                    Some(CompilableInstruction::Label(a)) if &a != b => {
                         label_map.insert(a, b.clone());
                     }
+                    Some(e @ CompilableInstruction::Origin(_)) => origins.push(e),
                     Some(e) => {
                         out.push(e);
                         break;
@@ -25,6 +29,7 @@ pub fn opt_asm(input: Vec<CompilableInstruction>) -> Vec<CompilableInstruction>
                     _ => break,
                 }
             }
+            out.extend(origins.into_iter().rev());
             out.push(el);
             continue;
         }
@@ -53,6 +58,9 @@ fn remap(asm: &mut [CompilableInstruction], amap: &HashMap<Label, Label>) {
         {
             *a = update(a, amap);
         }
+        if let CompilableInstruction::Match(_, labels) = i {
+            labels.iter_mut().flatten().for_each(|a| *a = update(a, amap));
+        }
     });
 }
 
@@ -62,3 +70,35 @@ fn update(a: &Label, amap: &HashMap<Label, Label>) -> Label {
         None => a.clone(),
     }
 }
+
+#[test]
+fn test_self_jump() {
+    use crate::LabelType;
+    let l = Label(0, LabelType::LoopStart);
+    let code = vec![
+        CompilableInstruction::Label(l.clone()),
+        CompilableInstruction::Jump(l),
+    ];
+    // The loop never ends, it can't be removed
+    assert_eq!(format!("{:?}", opt_asm(code.clone())), format!("{:?}", code));
+}
+
+#[test]
+fn test_remap_match() {
+    use crate::{LabelType, Var};
+    let (a, b) = (Label(0, LabelType::BlockEnd), Label(1, LabelType::BlockEnd));
+    let mut labels: [Option<Label>; 16] = Default::default();
+    labels[1] = Some(a.clone());
+    let code = opt_asm(vec![
+        CompilableInstruction::Match(Var(0), labels),
+        CompilableInstruction::Label(a),
+        CompilableInstruction::Jump(b.clone()),
+        CompilableInstruction::Label(b.clone()),
+        CompilableInstruction::Stop,
+    ]);
+    // The removed label is replaced by the one it jumps to
+    match &code[0] {
+        CompilableInstruction::Match(_, labels) => assert_eq!(labels[1], Some(b)),
+        e => panic!("{:?}", e),
+    }
+}
@@ -52,10 +52,13 @@ impl IntoIterator for MirCodeBlock {
 
 impl MirCodeBlock {
     pub fn optimize_code_new(self) -> Self {
-        crate::optimizer::new_opt::optimize_code(self, 0)
+        crate::PassManager::new(crate::OptLevel::O2).run(self)
     }
     pub fn to_asm(&self, state: &mut MirState) -> SkipStatus {
         for i in &self.0 {
+            if state.debug_info && !matches!(i, Mir::Location(..) | Mir::Debug(_)) {
+                state.origin(i);
+            }
             match i.to_asm(state) {
                 SkipStatus::None => (),
                 e => return e,
@@ -72,37 +75,6 @@ impl Default for MirCodeBlock {
 }
 
 impl MirCodeBlock {
-    /* #[allow(dead_code)]
-    pub fn optimize_code(&self) -> Self {
-        let before = self.instr_count();
-        let mut after = self.clone();
-        let mut bf = before;
-        let mut cafter = 0;
-
-        let opti = OptConfig::default();
-        while bf != cafter {
-            bf = cafter;
-            let opt = after.optimize(&mut OptimizerState::new(), &opti);
-            after = if opti.remove_unused_vars {
-                keep_block(&opt, &mut opt.get_reads())
-            } else {
-                opt
-            };
-            cafter = after.instr_count();
-            println!("OPT Pass done {}", cafter);
-        }
-        std::fs::write(
-            "target/opt-loop.mir",
-            after
-                .0
-                .iter()
-                .map(|x| x.to_string())
-                .collect::<Vec<_>>()
-                .join("\n"),
-        )
-        .unwrap();
-        after
-    } */
     #[allow(dead_code)]
     pub fn instr_count(&self) -> usize {
         self.0
@@ -110,11 +82,41 @@ impl MirCodeBlock {
             .map(|x| match x {
                 Mir::If0(_, a, b) => a.instr_count() + b.instr_count() + 1,
                 Mir::Loop(a) | Mir::Block(a) => 1 + a.instr_count(),
+                Mir::Location(..) | Mir::Debug(_) => 0,
                 _ => 1,
             })
             .sum()
     }
 
+    /// Removes the locations followed by no instruction of their block, the optimizations leave
+    /// them when they remove the code of a statement
+    pub fn drop_dangling_locations(self) -> Self {
+        let mut out: Vec<Mir> = Vec::with_capacity(self.0.len());
+        for mir in self.0 {
+            if matches!(mir, Mir::Location(..)) && matches!(out.last(), Some(Mir::Location(..))) {
+                out.pop();
+            }
+            out.push(match mir {
+                Mir::If0(a, b, c) => {
+                    Mir::If0(a, b.drop_dangling_locations(), c.drop_dangling_locations())
+                }
+                Mir::Loop(a) => Mir::Loop(a.drop_dangling_locations()),
+                Mir::Block(a) => Mir::Block(a.drop_dangling_locations()),
+                Mir::Match(a, b) => Mir::Match(
+                    a,
+                    b.into_iter()
+                        .map(|(x, y)| (x.drop_dangling_locations(), y))
+                        .collect(),
+                ),
+                e => e,
+            });
+        }
+        if matches!(out.last(), Some(Mir::Location(..))) {
+            out.pop();
+        }
+        Self(out)
+    }
+
     pub fn add(&mut self, mut mir: MirCodeBlock) -> &mut Self {
         self.0.append(&mut mir.0);
         self
@@ -156,3 +158,26 @@ impl MirCodeBlock {
         self
     }
 }
+
+#[test]
+fn test_drop_dangling_locations() {
+    let location = || Mir::Location(Span::default(), Vec::new());
+    let code = MirCodeBlock(vec![
+        location(),
+        location(),
+        Mir::Increment(1),
+        Mir::If0(1, MirCodeBlock(vec![location()]), MirCodeBlock::default()),
+        location(),
+    ]);
+    assert_eq!(
+        format!("{:?}", code.drop_dangling_locations().0),
+        format!(
+            "{:?}",
+            vec![
+                location(),
+                Mir::Increment(1),
+                Mir::If0(1, MirCodeBlock::default(), MirCodeBlock::default()),
+            ]
+        )
+    );
+}
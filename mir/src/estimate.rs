@@ -0,0 +1,543 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
+
+use errors::Span;
+
+use crate::{DebugMarker, Mir, MirCodeBlock};
+
+// Amount of Cythan steps taken by the compiled instructions (See `lir/src/template.ct`), measured
+// on the binaries once the jumps to the next instruction are removed
+pub(crate) const COPY: usize = 1;
+pub(crate) const INCREMENT: usize = 18;
+/// The `if_0` when the variable isn't zero, it goes through the `no_op` at its end
+pub(crate) const CONDITION: usize = 19;
+/// The `if_0` when the variable is zero, it jumps straight to its label
+pub(crate) const CONDITION_TAKEN: usize = 18;
+pub(crate) const JUMP: usize = 1;
+/// The `no_op` a label is placed on
+pub(crate) const LABEL: usize = 1;
+/// The copy into the jump table, the table and the jump to the label of the value
+pub(crate) const MATCH: usize = 18;
+/// The values without arm go through the end of the table before jumping to the end of the match
+const MATCH_DEFAULT: usize = MATCH + JUMP;
+/// `stop` is a jump to itself, run twice before the machine is seen stopped
+pub(crate) const STOP: usize = 2;
+/// Setting the return slot, the jump to the procedure, its label, the jump to its return
+/// dispatch, the dispatch and the label of the call
+pub(crate) const CALL: usize = 2 * COPY + JUMP + LABEL + JUMP + LABEL + MATCH + LABEL;
+
+/// A number of steps depending on unknown amounts, the keys are the products of the `Bound`s
+/// (by index) multiplying each coefficient, the empty product being the constant
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cost(BTreeMap<Vec<usize>, usize>);
+
+impl Cost {
+    pub fn constant(steps: usize) -> Self {
+        let mut cost = Self::default();
+        if steps > 0 {
+            cost.0.insert(Vec::new(), steps);
+        }
+        cost
+    }
+
+    pub fn bound(bound: usize) -> Self {
+        Self(std::iter::once((vec![bound], 1)).collect())
+    }
+
+    /// The steps without the terms depending on the bounds
+    pub fn fixed(&self) -> usize {
+        self.0.get(&Vec::new()).copied().unwrap_or_default()
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        self.0.keys().all(Vec::is_empty)
+    }
+
+    pub fn plus(&self, other: &Self) -> Self {
+        let mut cost = self.clone();
+        for (term, coefficient) in &other.0 {
+            *cost.0.entry(term.clone()).or_default() += coefficient;
+        }
+        cost
+    }
+
+    /// A cost at least as high as both, term by term
+    pub fn max(&self, other: &Self) -> Self {
+        let mut cost = self.clone();
+        for (term, coefficient) in &other.0 {
+            let e = cost.0.entry(term.clone()).or_default();
+            *e = (*e).max(*coefficient);
+        }
+        cost
+    }
+
+    /// The cost repeated as many times as the bound
+    pub fn times(&self, bound: usize) -> Self {
+        Self(
+            self.0
+                .iter()
+                .map(|(term, coefficient)| {
+                    let mut term = term.clone();
+                    term.push(bound);
+                    term.sort_unstable();
+                    (term, *coefficient)
+                })
+                .collect(),
+        )
+    }
+
+    /// The steps once the bounds are known, the missing bounds are zero
+    pub fn eval(&self, bounds: &[usize]) -> usize {
+        self.0
+            .iter()
+            .map(|(term, coefficient)| {
+                term.iter()
+                    .map(|x| bounds.get(*x).copied().unwrap_or_default())
+                    .product::<usize>()
+                    * coefficient
+            })
+            .sum()
+    }
+}
+
+impl Display for Cost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut terms: Vec<_> = self.0.iter().filter(|(_, x)| **x > 0).collect();
+        terms.sort_by(|a, b| a.0.len().cmp(&b.0.len()).then_with(|| a.0.cmp(b.0)));
+        if terms.is_empty() {
+            return write!(f, "0");
+        }
+        for (i, (term, coefficient)) in terms.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, " + ")?;
+            }
+            if term.is_empty() {
+                write!(f, "{}", coefficient)?;
+                continue;
+            }
+            if *coefficient != 1 {
+                write!(f, "{}*", coefficient)?;
+            }
+            let names: Vec<_> = term.iter().map(|x| format!("n{}", x + 1)).collect();
+            write!(f, "{}", names.join("*"))?;
+        }
+        Ok(())
+    }
+}
+
+/// The fewest and the most steps a piece of code can take
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Steps {
+    pub best: usize,
+    pub worst: Cost,
+}
+
+impl Steps {
+    pub fn constant(steps: usize) -> Self {
+        Self {
+            best: steps,
+            worst: Cost::constant(steps),
+        }
+    }
+
+    fn then(&self, other: &Self) -> Self {
+        Self {
+            best: self.best + other.best,
+            worst: self.worst.plus(&other.worst),
+        }
+    }
+
+    fn or(&self, other: &Self) -> Self {
+        Self {
+            best: self.best.min(other.best),
+            worst: self.worst.max(&other.worst),
+        }
+    }
+}
+
+impl Display for Steps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.worst == Cost::constant(self.best) {
+            write!(f, "{}", self.best)
+        } else {
+            write!(f, "{} to {}", self.best, self.worst)
+        }
+    }
+}
+
+/// An unknown amount the worst case depends on, named `n` followed by its index from 1
+#[derive(Debug, Clone)]
+pub enum Bound {
+    /// The iterations of the loop after its first one, at the statement it comes from if the
+    /// code has locations
+    Loop(Option<Span>),
+    /// The steps of the recursive calls of a procedure
+    Recursion(String),
+}
+
+/// The steps of a method from where it is called to where it returns
+#[derive(Debug, Clone)]
+pub struct MethodSteps {
+    /// How many times the method is inlined or outlined in the code
+    pub sites: usize,
+    pub steps: Steps,
+}
+
+fn or(a: Option<Steps>, b: Option<Steps>) -> Option<Steps> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.or(&b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The steps of the ways a piece of code can be left, `None` when it can't be left this way
+#[derive(Debug, Clone, Default)]
+struct Exits {
+    normal: Option<Steps>,
+    breaks: Option<Steps>,
+    continues: Option<Steps>,
+    skips: Option<Steps>,
+    stops: Option<Steps>,
+}
+
+impl Exits {
+    fn steps(steps: usize) -> Self {
+        Self {
+            normal: Some(Steps::constant(steps)),
+            ..Self::default()
+        }
+    }
+
+    /// The code followed by `next` when it isn't left otherwise
+    fn then(self, next: &Self) -> Self {
+        let through = |exit: &Option<Steps>| match (&self.normal, exit) {
+            (Some(a), Some(b)) => Some(a.then(b)),
+            _ => None,
+        };
+        Self {
+            normal: through(&next.normal),
+            breaks: or(self.breaks.clone(), through(&next.breaks)),
+            continues: or(self.continues.clone(), through(&next.continues)),
+            skips: or(self.skips.clone(), through(&next.skips)),
+            stops: or(self.stops.clone(), through(&next.stops)),
+        }
+    }
+
+    /// Either of the two pieces of code
+    fn or(self, other: Self) -> Self {
+        Self {
+            normal: or(self.normal, other.normal),
+            breaks: or(self.breaks, other.breaks),
+            continues: or(self.continues, other.continues),
+            skips: or(self.skips, other.skips),
+            stops: or(self.stops, other.stops),
+        }
+    }
+
+    fn any(self) -> Option<Steps> {
+        vec![self.breaks, self.continues, self.skips, self.stops]
+            .into_iter()
+            .fold(self.normal, or)
+    }
+}
+
+/// Estimates the steps the lowered code takes, the best case leaves each loop on its first
+/// iteration and the worst case repeats each loop as many times as its `Bound`
+#[derive(Default)]
+pub struct Estimator {
+    pub bounds: Vec<Bound>,
+    /// The methods delimited by the `DebugMarker`s of the code, by name
+    pub methods: BTreeMap<String, MethodSteps>,
+    /// The bounds of the loops by statement, the copies of a loop share its bound
+    loops: HashMap<(String, usize, usize), usize>,
+    procedures: HashMap<String, Exits>,
+    recursions: HashMap<String, usize>,
+    calls: Vec<String>,
+    location: Option<Span>,
+}
+
+impl Estimator {
+    /// The steps of the program until it stops, `None` if it runs forever
+    pub fn estimate(&mut self, code: &MirCodeBlock) -> Option<Steps> {
+        // The procedures of another program can have the same names
+        self.procedures.clear();
+        self.location = None;
+        self.block(code).any()
+    }
+
+    fn block(&mut self, code: &[Mir]) -> Exits {
+        let mut exits = Exits::steps(0);
+        let mut i = 0;
+        while i < code.len() {
+            let next = match &code[i] {
+                Mir::Debug(DebugMarker::Enter { method, .. }) => match leave(code, i) {
+                    Some(end) => {
+                        let method_exits = self.block(&code[i + 1..end]);
+                        if let Some(steps) = method_exits.clone().any() {
+                            self.record(method, steps);
+                        }
+                        i = end;
+                        method_exits
+                    }
+                    None => Exits::steps(0),
+                },
+                e => self.node(e),
+            };
+            exits = exits.then(&next);
+            i += 1;
+        }
+        exits
+    }
+
+    fn record(&mut self, method: &str, steps: Steps) {
+        match self.methods.get_mut(method) {
+            Some(e) => {
+                e.sites += 1;
+                e.steps = e.steps.or(&steps);
+            }
+            None => {
+                self.methods
+                    .insert(method.to_owned(), MethodSteps { sites: 1, steps });
+            }
+        }
+    }
+
+    fn node(&mut self, mir: &Mir) -> Exits {
+        match mir {
+            Mir::Location(span, _) => {
+                self.location = Some(span.clone());
+                Exits::steps(0)
+            }
+            Mir::Debug(_) => Exits::steps(0),
+            Mir::Copy(a, b) if a == b => Exits::steps(0),
+            Mir::Set(..) | Mir::Copy(..) | Mir::ReadRegister(..) | Mir::WriteRegister(..) => {
+                Exits::steps(COPY)
+            }
+            Mir::Increment(_) | Mir::Decrement(_) => Exits::steps(INCREMENT),
+            Mir::Break | Mir::Continue | Mir::Skip => jump(mir, JUMP),
+            Mir::Stop => Exits {
+                stops: Some(Steps::constant(STOP)),
+                ..Exits::default()
+            },
+            Mir::If0(_, a, b) if a == b => self.block(a),
+            Mir::If0(_, a, b) if a.is_empty() => {
+                let other = Exits::steps(CONDITION)
+                    .then(&self.block(b))
+                    .then(&Exits::steps(LABEL));
+                Exits::steps(CONDITION_TAKEN + LABEL).or(other)
+            }
+            // The `if_0` jumps to the target of the jump starting the branch
+            Mir::If0(_, a, b) if a.first().is_some_and(is_jump) => {
+                let other = Exits::steps(CONDITION)
+                    .then(&self.block(b))
+                    .then(&Exits::steps(JUMP + LABEL));
+                jump(&a[0], CONDITION_TAKEN).or(other)
+            }
+            Mir::If0(_, a, b) => {
+                let other = Exits::steps(CONDITION)
+                    .then(&self.block(b))
+                    .then(&Exits::steps(JUMP + LABEL));
+                Exits::steps(CONDITION_TAKEN + LABEL)
+                    .then(&self.block(a))
+                    .then(&Exits::steps(LABEL))
+                    .or(other)
+            }
+            // The program does nothing forever
+            Mir::Loop(a) if a.is_empty() => Exits {
+                stops: Some(Steps::constant(LABEL + JUMP)),
+                ..Exits::default()
+            },
+            Mir::Loop(a) => self.iterate(a),
+            Mir::Block(a) => {
+                let exits = self.block(a);
+                Exits {
+                    normal: or(exits.normal, exits.skips),
+                    skips: None,
+                    ..exits
+                }
+                .then(&Exits::steps(LABEL))
+            }
+            Mir::Match(_, arms) => {
+                let mut covered = [false; 16];
+                arms.iter()
+                    .flat_map(|(_, values)| values)
+                    .for_each(|x| covered[*x as usize] = true);
+                // The values without arm jump to the end of the match
+                let mut exits = if covered.iter().all(|x| *x) {
+                    Exits::default()
+                } else {
+                    Exits::steps(MATCH_DEFAULT + JUMP + LABEL)
+                };
+                for (arm, _) in arms {
+                    let arm = Exits::steps(MATCH + LABEL)
+                        .then(&self.block(arm))
+                        .then(&Exits::steps(JUMP + LABEL));
+                    exits = exits.or(arm);
+                }
+                exits
+            }
+            Mir::Call(procedure) => {
+                let body = self.procedure(&procedure.name, procedure.code());
+                let call = Exits {
+                    normal: or(body.normal, body.skips),
+                    stops: body.stops,
+                    ..Exits::default()
+                };
+                Exits::steps(CALL).then(&call)
+            }
+        }
+    }
+
+    /// The loop is left by its breaks, its skips and its stops after any amount of iterations
+    fn iterate(&mut self, code: &MirCodeBlock) -> Exits {
+        let location = self.location.clone();
+        // A label followed by a jump is replaced by the target of the jump
+        let start = if code.first().is_some_and(is_jump) {
+            0
+        } else {
+            LABEL
+        };
+        let body = Exits::steps(start).then(&self.block(code));
+        let back = or(
+            body.normal.as_ref().map(|x| x.then(&Steps::constant(JUMP))),
+            body.continues.clone(),
+        );
+        let bound = back.as_ref().map(|_| self.loop_bound(location));
+        let repeat = |exit: Option<Steps>| {
+            exit.map(|exit| match (&back, bound) {
+                (Some(back), Some(bound)) => Steps {
+                    best: exit.best,
+                    worst: back.worst.times(bound).plus(&exit.worst),
+                },
+                _ => exit,
+            })
+        };
+        Exits {
+            normal: repeat(body.breaks.map(|x| x.then(&Steps::constant(LABEL)))),
+            skips: repeat(body.skips),
+            stops: repeat(body.stops),
+            ..Exits::default()
+        }
+    }
+
+    fn loop_bound(&mut self, location: Option<Span>) -> usize {
+        let key = location.as_ref().map(|x| (x.file.clone(), x.start, x.end));
+        if let Some(e) = key.as_ref().and_then(|x| self.loops.get(x)) {
+            return *e;
+        }
+        self.bounds.push(Bound::Loop(location));
+        if let Some(key) = key {
+            self.loops.insert(key, self.bounds.len() - 1);
+        }
+        self.bounds.len() - 1
+    }
+
+    /// The steps of the code of a procedure, a recursive call counts as its `Bound`
+    fn procedure(&mut self, name: &str, code: &MirCodeBlock) -> Exits {
+        if self.calls.iter().any(|x| x == name) {
+            let bounds = &mut self.bounds;
+            let bound = *self.recursions.entry(name.to_owned()).or_insert_with(|| {
+                bounds.push(Bound::Recursion(name.to_owned()));
+                bounds.len() - 1
+            });
+            return Exits {
+                normal: Some(Steps {
+                    best: 0,
+                    worst: Cost::bound(bound),
+                }),
+                ..Exits::default()
+            };
+        }
+        if let Some(e) = self.procedures.get(name) {
+            return e.clone();
+        }
+        let location = self.location.take();
+        self.calls.push(name.to_owned());
+        let exits = self.block(code);
+        self.calls.pop();
+        self.location = location;
+        self.procedures.insert(name.to_owned(), exits.clone());
+        exits
+    }
+}
+
+fn is_jump(mir: &Mir) -> bool {
+    matches!(mir, Mir::Break | Mir::Continue | Mir::Skip)
+}
+
+/// Leaving the code the way the jump does, in that many steps
+fn jump(mir: &Mir, steps: usize) -> Exits {
+    let steps = Some(Steps::constant(steps));
+    match mir {
+        Mir::Break => Exits {
+            breaks: steps,
+            ..Exits::default()
+        },
+        Mir::Continue => Exits {
+            continues: steps,
+            ..Exits::default()
+        },
+        _ => Exits {
+            skips: steps,
+            ..Exits::default()
+        },
+    }
+}
+
+/// The index of the `Leave` of the method entered at `start`
+fn leave(code: &[Mir], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, mir) in code.iter().enumerate().skip(start) {
+        match mir {
+            Mir::Debug(DebugMarker::Enter { .. }) => depth += 1,
+            Mir::Debug(DebugMarker::Leave) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+#[test]
+fn test_estimate_branches() {
+    // v1 = 3; if v1 { v2 = 1 } else { v2++ }
+    let code = MirCodeBlock(vec![
+        Mir::Set(1, 3),
+        Mir::If0(1, Mir::Set(2, 1).into(), Mir::Increment(2).into()),
+        Mir::Stop,
+    ]);
+    let steps = Estimator::default().estimate(&code).unwrap();
+    let taken = COPY + CONDITION_TAKEN + LABEL + COPY + LABEL + STOP;
+    let other = COPY + CONDITION + INCREMENT + JUMP + LABEL + STOP;
+    assert_eq!(steps.best, taken.min(other));
+    assert_eq!(steps.worst, Cost::constant(taken.max(other)));
+}
+
+#[test]
+fn test_estimate_loop_bound() {
+    // loop { if v1 { break }; v1-- }
+    let code = MirCodeBlock(vec![
+        Mir::Loop(MirCodeBlock(vec![
+            Mir::If0(1, Mir::Break.into(), MirCodeBlock(vec![])),
+            Mir::Decrement(1),
+        ])),
+        Mir::Stop,
+    ]);
+    let mut estimator = Estimator::default();
+    let steps = estimator.estimate(&code).unwrap();
+    assert!(matches!(estimator.bounds.as_slice(), [Bound::Loop(None)]));
+    let iteration = LABEL + CONDITION + JUMP + LABEL + INCREMENT + JUMP;
+    let exit = LABEL + CONDITION_TAKEN + LABEL + STOP;
+    assert_eq!(steps.best, exit);
+    assert_eq!(steps.worst.eval(&[3]), 3 * iteration + exit);
+    assert_eq!(
+        steps.to_string(),
+        format!("{} to {} + {}*n1", exit, exit, iteration)
+    );
+}
@@ -0,0 +1,326 @@
+use std::collections::HashSet;
+
+use either::Either;
+
+use crate::{Mir, MirCodeBlock};
+
+/// Xorshift generator, programs are reproducible from their seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must never be 0
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, max: u32) -> u32 {
+        (self.next_u64() % max as u64) as u32
+    }
+
+    pub fn value(&mut self) -> u8 {
+        self.below(16) as u8
+    }
+}
+
+pub struct GeneratorConfig {
+    /// Variables the program computes with, they are all printed at the end of the program
+    pub variables: u32,
+    pub max_depth: usize,
+    pub max_block_size: u32,
+    pub max_loop_iterations: u8,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            variables: 6,
+            max_depth: 3,
+            max_block_size: 6,
+            max_loop_iterations: 5,
+        }
+    }
+}
+
+/// The register that contains the high half of a printed character
+const PRINT_HIGH: u8 = 1;
+const PRINT_LOW: u8 = 2;
+/// Printed values are in the `@ABCDEFGHIJKLMNO` range
+const PRINT_OFFSET: u8 = 4;
+
+struct Generator<'a> {
+    rng: Rng,
+    config: &'a GeneratorConfig,
+    /// Loop counters are stored after the variables, one per loop depth
+    loop_depth: u32,
+    in_block: bool,
+}
+
+/// Generates a random well formed program. Every loop is bounded by a counter that is only
+/// written by the loop header, `Break`, `Continue` and `Skip` only appear where they have a
+/// target and every variable is set before being used.
+pub fn generate_program(seed: u64, config: &GeneratorConfig) -> MirCodeBlock {
+    let mut generator = Generator {
+        rng: Rng::new(seed),
+        config,
+        loop_depth: 0,
+        in_block: false,
+    };
+    let mut out = MirCodeBlock::default();
+    for i in 0..config.variables {
+        let value = generator.rng.value();
+        out.add_mir(Mir::Set(i, value));
+    }
+    out.0.extend(generator.block(0).0);
+    for i in 0..config.variables {
+        out.0.extend(print(i));
+    }
+    out.add_mir(Mir::Stop);
+    out
+}
+
+/// Generates the input given to a generated program
+pub fn generate_input(seed: u64) -> String {
+    let mut rng = Rng::new(!seed);
+    (0..32).map(|_| (b'0' + rng.below(64) as u8) as char).collect()
+}
+
+fn print(var: u32) -> Vec<Mir> {
+    vec![
+        Mir::WriteRegister(PRINT_HIGH, Either::Left(PRINT_OFFSET)),
+        Mir::WriteRegister(PRINT_LOW, Either::Right(var)),
+        Mir::WriteRegister(0, Either::Left(1)),
+    ]
+}
+
+impl<'a> Generator<'a> {
+    fn var(&mut self) -> u32 {
+        self.rng.below(self.config.variables)
+    }
+
+    fn counter(&self) -> u32 {
+        self.config.variables + self.loop_depth
+    }
+
+    fn block(&mut self, depth: usize) -> MirCodeBlock {
+        let size = self.rng.below(self.config.max_block_size + 1);
+        let mut out = MirCodeBlock::default();
+        for _ in 0..size {
+            out.0.extend(self.instruction(depth));
+        }
+        out
+    }
+
+    fn instruction(&mut self, depth: usize) -> Vec<Mir> {
+        let nested = depth < self.config.max_depth;
+        match self.rng.below(if nested { 20 } else { 12 }) {
+            0 | 1 => vec![Mir::Set(self.var(), self.rng.value())],
+            2 | 3 => vec![Mir::Copy(self.var(), self.var())],
+            4 | 5 => vec![Mir::Increment(self.var())],
+            6 | 7 => vec![Mir::Decrement(self.var())],
+            8 => print(self.var()),
+            9 => vec![
+                Mir::WriteRegister(0, Either::Left(2)),
+                Mir::ReadRegister(self.var(), PRINT_HIGH + self.rng.below(2) as u8),
+            ],
+            10 => {
+                if self.loop_depth > 0 && self.rng.below(2) == 0 {
+                    vec![Mir::Break]
+                } else if self.loop_depth > 0 {
+                    vec![Mir::Continue]
+                } else if self.in_block {
+                    vec![Mir::Skip]
+                } else {
+                    vec![Mir::Increment(self.var())]
+                }
+            }
+            11 => {
+                if self.rng.below(8) == 0 {
+                    vec![Mir::Stop]
+                } else {
+                    print(self.var())
+                }
+            }
+            12..=14 => {
+                let var = self.var();
+                vec![Mir::If0(var, self.block(depth + 1), self.block(depth + 1))]
+            }
+            15 | 16 => {
+                let var = self.var();
+                let arms = 1 + self.rng.below(4) as usize;
+                let mut values = vec![Vec::new(); arms];
+                // Some values may be left without arm
+                let covered = if self.rng.below(2) == 0 {
+                    16
+                } else {
+                    self.rng.below(16) as u8
+                };
+                for value in 0..covered {
+                    values[self.rng.below(arms as u32) as usize].push(value);
+                }
+                vec![Mir::Match(
+                    var,
+                    values
+                        .into_iter()
+                        .filter(|x| !x.is_empty())
+                        .map(|x| (self.block(depth + 1), x))
+                        .collect(),
+                )]
+            }
+            17 | 18 => {
+                let counter = self.counter();
+                let iterations = self.rng.below(self.config.max_loop_iterations as u32 + 1);
+                self.loop_depth += 1;
+                let mut body = MirCodeBlock(vec![
+                    Mir::If0(counter, Mir::Break.into(), MirCodeBlock::default()),
+                    Mir::Decrement(counter),
+                ]);
+                body.0.extend(self.block(depth + 1).0);
+                self.loop_depth -= 1;
+                vec![Mir::Set(counter, iterations as u8), Mir::Loop(body)]
+            }
+            _ => {
+                let in_block = self.in_block;
+                self.in_block = true;
+                let block = self.block(depth + 1);
+                self.in_block = in_block;
+                vec![Mir::Block(block)]
+            }
+        }
+    }
+}
+
+/// Checks the properties the generated programs rely on to terminate and be compiled. The
+/// variables (Except for loop counters) must be set at the start of the program.
+pub fn is_well_formed(block: &MirCodeBlock) -> bool {
+    fn check(block: &MirCodeBlock, in_loop: bool, in_block: bool, counters: &mut HashSet<u32>) -> bool {
+        block.iter().all(|x| match x {
+            Mir::Break | Mir::Continue => in_loop,
+            Mir::Skip => in_block,
+            Mir::If0(_, a, b) => {
+                check(a, in_loop, in_block, counters) && check(b, in_loop, in_block, counters)
+            }
+            Mir::Match(_, arms) => arms
+                .iter()
+                .all(|(a, _)| check(a, in_loop, in_block, counters)),
+            Mir::Block(a) => check(a, in_loop, true, counters),
+            Mir::Loop(a) => match a.0.as_slice() {
+                [Mir::If0(counter, brk, els), Mir::Decrement(c), ..]
+                    if counter == c && brk.0 == [Mir::Break] && els.is_empty() =>
+                {
+                    counters.insert(*c);
+                    check(&MirCodeBlock(a.0[2..].to_vec()), true, in_block, counters)
+                }
+                _ => false,
+            },
+            _ => true,
+        })
+    }
+    let mut counters = HashSet::new();
+    if !check(block, false, false, &mut counters) {
+        return false;
+    }
+    let defined: HashSet<u32> = block
+        .iter()
+        .map_while(|x| match x {
+            Mir::Set(a, _) => Some(*a),
+            _ => None,
+        })
+        .chain(counters)
+        .collect();
+    block
+        .iter()
+        .all(|x| x.get_acesses().iter().all(|x| defined.contains(x)))
+}
+
+/// Greedily applies the simplifications that keep `failing` true until none does
+pub fn shrink(mut program: MirCodeBlock, mut failing: impl FnMut(&MirCodeBlock) -> bool) -> MirCodeBlock {
+    'outer: loop {
+        for candidate in simplifications(&program) {
+            if is_well_formed(&candidate) && failing(&candidate) {
+                program = candidate;
+                continue 'outer;
+            }
+        }
+        return program;
+    }
+}
+
+/// Every program that can be made from this one by removing or simplifying one instruction
+fn simplifications(block: &MirCodeBlock) -> Vec<MirCodeBlock> {
+    let mut out = Vec::new();
+    for (i, instruction) in block.iter().enumerate() {
+        let replace = |with: Vec<Mir>| {
+            let mut k = block.0[..i].to_vec();
+            k.extend(with);
+            k.extend(block.0[i + 1..].iter().cloned());
+            MirCodeBlock(k)
+        };
+        out.push(replace(vec![]));
+        match instruction {
+            Mir::If0(a, b, c) => {
+                out.push(replace(b.0.clone()));
+                out.push(replace(c.0.clone()));
+                out.extend(
+                    simplifications(b)
+                        .into_iter()
+                        .map(|x| replace(vec![Mir::If0(*a, x, c.clone())])),
+                );
+                out.extend(
+                    simplifications(c)
+                        .into_iter()
+                        .map(|x| replace(vec![Mir::If0(*a, b.clone(), x)])),
+                );
+            }
+            Mir::Match(a, arms) => {
+                for (j, (arm, _)) in arms.iter().enumerate() {
+                    out.push(replace(arm.0.clone()));
+                    let mut k = arms.clone();
+                    k.remove(j);
+                    out.push(replace(vec![Mir::Match(*a, k)]));
+                    out.extend(simplifications(arm).into_iter().map(|x| {
+                        let mut k = arms.clone();
+                        k[j].0 = x;
+                        replace(vec![Mir::Match(*a, k)])
+                    }));
+                }
+            }
+            Mir::Loop(a) => {
+                // The loop header is kept, the well formed check rejects programs without it
+                out.push(replace(a.0.get(2..).unwrap_or_default().to_vec()));
+                out.extend(
+                    simplifications(a)
+                        .into_iter()
+                        .map(|x| replace(vec![Mir::Loop(x)])),
+                );
+            }
+            Mir::Block(a) => {
+                out.push(replace(a.0.clone()));
+                out.extend(
+                    simplifications(a)
+                        .into_iter()
+                        .map(|x| replace(vec![Mir::Block(x)])),
+                );
+            }
+            Mir::Set(a, b) if *b != 0 => out.push(replace(vec![Mir::Set(*a, 0)])),
+            _ => (),
+        }
+    }
+    out
+}
+
+#[test]
+fn test_generated_programs_are_well_formed() {
+    let config = GeneratorConfig::default();
+    for seed in 0..200 {
+        let program = generate_program(seed, &config);
+        assert!(is_well_formed(&program), "{:?}", program);
+        assert_eq!(program, generate_program(seed, &config));
+    }
+}
@@ -10,6 +10,15 @@ pub struct StdIoContext;
 pub trait RunContext {
     fn input(&mut self) -> u8;
     fn print(&mut self, i: char);
+    /// Stops the execution after an input when the context can't provide inputs anymore
+    fn exhausted(&self) -> bool {
+        false
+    }
+    /// Called before each instruction with the state it runs on, ends the execution when it
+    /// returns false
+    fn instruction(&mut self, _mir: &Mir, _state: &MemoryState) -> bool {
+        true
+    }
 }
 
 impl RunContext for StdIoContext {
@@ -29,6 +38,12 @@ pub struct MemoryState {
     pub memory: Vec<u8>,
     pub registers: Vec<u8>,
     pub instr_count: usize,
+    /// Stops the execution with `SkipStatus::Timeout` once this many instructions ran
+    pub max_instr: Option<usize>,
+    /// When set, records the instruction that emitted each printed character
+    pub print_trace: Option<Vec<Mir>>,
+    /// How many blocks the running instruction is nested in
+    pub depth: usize,
 }
 
 impl MemoryState {
@@ -37,6 +52,9 @@ impl MemoryState {
             memory: vec![0; memory_size],
             registers: vec![0; register_size],
             instr_count: 0,
+            max_instr: None,
+            print_trace: None,
+            depth: 0,
         }
     }
 
@@ -51,12 +69,9 @@ impl MemoryState {
         self.memory[index as usize] = value;
     }
 
+    /// The variables that were never written still hold 0, the value every variable starts with
     pub fn get_mem(&self, index: u32) -> u8 {
-        if let Some(e) = self.memory.get(index as usize).copied() {
-            e
-        } else {
-            panic!("Variable wasn't found: {}", index);
-        }
+        self.memory.get(index as usize).copied().unwrap_or(0)
     }
 
     pub fn execute_block(
@@ -64,17 +79,28 @@ impl MemoryState {
         block: &MirCodeBlock,
         printer: &mut impl RunContext,
     ) -> SkipStatus {
-        for instruction in block.0.iter() {
-            match self.execute(instruction, printer) {
-                SkipStatus::None => continue,
-                e => return e,
-            }
-        }
-        SkipStatus::None
+        self.depth += 1;
+        let status = block
+            .0
+            .iter()
+            .map(|x| self.execute(x, printer))
+            .find(|x| !matches!(x, SkipStatus::None))
+            .unwrap_or(SkipStatus::None);
+        self.depth -= 1;
+        status
     }
 
     pub fn execute(&mut self, mir: &Mir, printer: &mut impl RunContext) -> SkipStatus {
+        if !printer.instruction(mir, self) {
+            return SkipStatus::End;
+        }
+        if let Mir::Location(..) | Mir::Debug(_) = mir {
+            return SkipStatus::None;
+        }
         self.instr_count += 1;
+        if matches!(self.max_instr, Some(e) if self.instr_count > e) {
+            return SkipStatus::Timeout;
+        }
         match mir {
             Mir::Set(a, b) => self.set_mem(*a, *b),
             Mir::Copy(a, b) => self.set_mem(*a, self.get_mem(*b)),
@@ -109,12 +135,18 @@ impl MemoryState {
                         let b = self.registers[2];
                         let char = ((a % 16) * 16) + (b % 16);
                         printer.print(char as char);
+                        if let Some(trace) = &mut self.print_trace {
+                            trace.push(mir.clone());
+                        }
                     } else if p == 2 {
                         let o: u8 = printer.input();
                         let a = o % 16u8;
                         let b = o / 16u8;
                         self.registers[1] = b;
                         self.registers[2] = a;
+                        if printer.exhausted() {
+                            return SkipStatus::End;
+                        }
                     }
                 }
                 self.registers[*a as usize] = p
@@ -132,6 +164,14 @@ impl MemoryState {
                     }
                 }
             }
+            Mir::Call(a) => {
+                if let e @ (SkipStatus::End | SkipStatus::Timeout) =
+                    self.execute_block(a.code(), printer)
+                {
+                    return e;
+                }
+            }
+            Mir::Location(..) | Mir::Debug(_) => (),
         }
         SkipStatus::None
     }
@@ -143,4 +183,5 @@ pub enum SkipStatus {
     Skip,
     None,
     End,
+    Timeout,
 }
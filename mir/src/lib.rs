@@ -1,18 +1,20 @@
 mod block;
+mod estimate;
+mod generator;
 mod interpreter;
 mod mir;
 mod optimizer;
+mod procedure;
 mod skip_status;
 mod state;
-mod no;
 
 pub use block::MirCodeBlock;
+pub use estimate::{Bound, Cost, Estimator, MethodSteps, Steps};
+pub use generator::{generate_input, generate_program, is_well_formed, shrink, GeneratorConfig};
 pub use interpreter::*;
-pub use mir::Mir;
+pub use mir::{DebugMarker, Mir};
 pub use optimizer::block_inliner::*;
-pub use state::MirState;
-
-pub fn opt(input: MirCodeBlock) -> MirCodeBlock {
-    let reads = crate::no::remove_no_reads(input);
-    reads
-}
\ No newline at end of file
+pub use optimizer::coalesce::{coalesce_variables, CoalesceStats};
+pub use procedure::{Procedure, ProcedureBody};
+pub use optimizer::pass_manager::{OptLevel, Pass, PassDump, PassManager, PassStats, PASSES};
+pub use state::{MirState, MAX_CALLS};
@@ -1,9 +1,10 @@
-use std::{fmt::Display, collections::HashSet};
+use std::{collections::HashSet, fmt::Display};
 
 use either::Either;
+use errors::Span;
 use lir::{AsmValue, CompilableInstruction, Label, LabelType, Number, Var};
 
-use crate::{block::MirCodeBlock, skip_status::SkipStatus, state::MirState};
+use crate::{block::MirCodeBlock, procedure::Procedure, skip_status::SkipStatus, state::MirState};
 
 #[derive(PartialEq, Clone, Hash, Debug)]
 #[allow(dead_code)]
@@ -22,6 +23,48 @@ pub enum Mir {
     Skip,
     Block(MirCodeBlock),
     Match(u32, Vec<(MirCodeBlock, Vec<u8>)>),
+    Call(Procedure),
+    // Does nothing, the next instructions were generated for this code inlined in these methods
+    Location(Span, Vec<String>),
+    Debug(DebugMarker), // Does nothing, tells the source debugger about the code around it
+}
+
+/// The structure of the source the compiler emits for the source debugger, only valid before
+/// the optimizations since they move the variables
+#[derive(PartialEq, Clone, Hash, Debug)]
+pub enum DebugMarker {
+    /// A method is called, `call` is the span of the call
+    Enter { method: String, call: Span },
+    /// The method entered last returns
+    Leave,
+    /// A variable of the source is stored in these cells
+    Variable {
+        name: String,
+        ty: String,
+        cells: Vec<u32>,
+    },
+}
+
+impl Display for DebugMarker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Enter { method, call } => {
+                write!(f, "enter {} from {}:{}..{}", method, call.file, call.start, call.end)
+            }
+            Self::Leave => write!(f, "leave"),
+            Self::Variable { name, ty, cells } => write!(
+                f,
+                "{} {} in {}",
+                ty,
+                name,
+                cells
+                    .iter()
+                    .map(|x| format!("v{}", x))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
 }
 
 impl Display for Mir {
@@ -122,6 +165,9 @@ impl Display for Mir {
                 s.push_str("}");
                 write!(f, "{}", s)
             }
+            Self::Call(a) => write!(f, "call {}#{}", a.name, a.return_slot.0),
+            Self::Location(a, _) => write!(f, "// {}:{}..{}", a.file, a.start, a.end),
+            Self::Debug(a) => write!(f, "// {}", a),
         }
     }
 }
@@ -172,11 +218,75 @@ impl Mir {
                     set.extend(c.iter().flat_map(|x| x.get_acesses()));
                 }
             }
+            Mir::Call(a) => {
+                set.extend(a.accesses());
+            }
+            Mir::Location(..) | Mir::Debug(_) => {}
         }
         set
     }
+
+    /// The first line of the display, without the nested code
+    pub(crate) fn head(&self) -> String {
+        match self {
+            Mir::If0(a, _, _) => format!("if v{}", a),
+            Mir::Loop(_) => "loop".to_owned(),
+            Mir::Block(_) => "block".to_owned(),
+            Mir::Match(a, _) => format!("match v{}", a),
+            e => e.to_string(),
+        }
+    }
+    /// Whether this can break the loop it is in
+    pub(crate) fn breaks(&self) -> bool {
+        match self {
+            Mir::Break => true,
+            Mir::If0(_, a, b) => a.iter().chain(b.iter()).any(Mir::breaks),
+            Mir::Block(a) => a.iter().any(Mir::breaks),
+            Mir::Match(_, a) => a.iter().any(|(a, _)| a.iter().any(Mir::breaks)),
+            _ => false,
+        }
+    }
+
+    /// Whether this can go to the next iteration of the loop it is in
+    pub(crate) fn continues(&self) -> bool {
+        match self {
+            Mir::Continue => true,
+            Mir::If0(_, a, b) => a.iter().chain(b.iter()).any(Mir::continues),
+            Mir::Block(a) => a.iter().any(Mir::continues),
+            Mir::Match(_, a) => a.iter().any(|(a, _)| a.iter().any(Mir::continues)),
+            _ => false,
+        }
+    }
+
+    /// Whether this can skip the block it is in
+    pub(crate) fn skips(&self) -> bool {
+        match self {
+            Mir::Skip => true,
+            Mir::If0(_, a, b) => a.iter().chain(b.iter()).any(Mir::skips),
+            Mir::Loop(a) => a.iter().any(Mir::skips),
+            Mir::Match(_, a) => a.iter().any(|(a, _)| a.iter().any(Mir::skips)),
+            _ => false,
+        }
+    }
+
+    /// Whether this calls a procedure
+    pub(crate) fn calls(&self) -> bool {
+        match self {
+            Mir::Call(_) => true,
+            Mir::If0(_, a, b) => a.iter().chain(b.iter()).any(Mir::calls),
+            Mir::Loop(a) | Mir::Block(a) => a.iter().any(Mir::calls),
+            Mir::Match(_, a) => a.iter().any(|(a, _)| a.iter().any(Mir::calls)),
+            _ => false,
+        }
+    }
+
     pub fn to_asm(&self, state: &mut MirState) -> SkipStatus {
         match self {
+            Self::Location(a, b) => {
+                state.span = Some(a.clone());
+                state.methods = b.clone();
+            }
+            Self::Debug(_) => (),
             Self::Copy(a, b) => {
                 if a == b {
                     return SkipStatus::None;
@@ -192,7 +302,7 @@ impl Mir {
                 let end = Label::alloc(&mut state.count, LabelType::IfEnd);
                 if b.0.is_empty() {
                     state.if0(Var(*a as usize), end.clone());
-                    b.to_asm(state);
+                    c.to_asm(state);
                     state.label(end);
                 } else {
                     let start = end.derive(LabelType::IfStart);
@@ -221,7 +331,7 @@ impl Mir {
                 state.loops.pop();
                 state.jump(loopstart);
                 state.label(loopend);
-                if matches!(k, SkipStatus::Stoped) {
+                if matches!(k, SkipStatus::Stoped) && !a.iter().any(Mir::breaks) {
                     return SkipStatus::Stoped;
                 }
             }
@@ -256,6 +366,10 @@ impl Mir {
                 let k = a.to_asm(state);
                 state.blocks.pop();
                 state.label(blockend);
+                // The code after the block is reached by the skips
+                if a.iter().any(Mir::skips) {
+                    return SkipStatus::None;
+                }
                 if matches!(k, SkipStatus::Stoped) {
                     return SkipStatus::Stoped;
                 }
@@ -280,6 +394,7 @@ impl Mir {
                     }
                     k.push(lbl);
                 }
+                let exhaustive = g.iter().all(|x| x.is_some());
                 state
                     .instructions
                     .push(CompilableInstruction::Match(Var(*a as usize), g));
@@ -299,8 +414,25 @@ impl Mir {
                 }
 
                 state.label(end);
+                // The values without arm continue after the match
+                if !exhaustive {
+                    return SkipStatus::None;
+                }
                 return sk.unwrap_or(SkipStatus::None);
             }
+            Self::Call(a) => {
+                let (start, call, ret) = state.call_site(a);
+                state.copy(
+                    Var(a.return_slot.0 as usize),
+                    AsmValue::Number(Number((call % 16) as u8)),
+                );
+                state.copy(
+                    Var(a.return_slot.1 as usize),
+                    AsmValue::Number(Number((call / 16) as u8)),
+                );
+                state.jump(start);
+                state.label(ret);
+            }
         }
         SkipStatus::None
     }
@@ -0,0 +1,507 @@
+use std::collections::{HashMap, HashSet};
+
+use either::Either;
+
+use crate::{Mir, MirCodeBlock};
+
+use super::dataflow::ValueSet;
+
+pub type BlockId = usize;
+/// Instructions are numbered in the order of a pre-order walk of the code, a compound
+/// instruction comes before the instructions it contains
+pub type InstrId = usize;
+
+pub const ENTRY: BlockId = 0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    Goto(BlockId),
+    /// An `If0` on the variable, the first target is taken when it is 0
+    Branch(InstrId, u32, BlockId, BlockId),
+    /// A `Match` on the variable, the values without arm go to the last target
+    Switch(InstrId, u32, Vec<(ValueSet, BlockId)>, BlockId),
+    /// A `Stop` or the end of the program
+    Stop,
+}
+
+#[derive(Clone, Debug)]
+pub struct BasicBlock {
+    /// Only contains the instructions that don't change the control flow
+    pub instructions: Vec<(InstrId, Mir)>,
+    pub terminator: Terminator,
+}
+
+/// The control flow graph of a `MirCodeBlock`. It keeps the id of every instruction so the
+/// results of the analyses can be applied back to the structured code with `rewrite`.
+#[derive(Clone, Debug)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    /// The block in which each instruction starts
+    pub location: Vec<BlockId>,
+}
+
+struct Builder {
+    blocks: Vec<BasicBlock>,
+    location: Vec<BlockId>,
+    /// Header and exit of the loops the code is in
+    loops: Vec<(BlockId, BlockId)>,
+    /// End of the blocks the code is in
+    ends: Vec<BlockId>,
+}
+
+impl Builder {
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(BasicBlock {
+            instructions: Vec::new(),
+            terminator: Terminator::Stop,
+        });
+        self.blocks.len() - 1
+    }
+
+    fn terminate(&mut self, block: BlockId, terminator: Terminator) {
+        self.blocks[block].terminator = terminator;
+    }
+
+    /// Adds the code at the end of the block and returns the block where the code after it
+    /// continues, `None` if it never ends
+    fn lower(&mut self, code: &MirCodeBlock, mut current: Option<BlockId>) -> Option<BlockId> {
+        for mir in code.iter() {
+            // Code after a jump is unreachable, it is put in a block without predecessor
+            let block = match current {
+                Some(e) => e,
+                None => self.new_block(),
+            };
+            let id = self.location.len();
+            self.location.push(block);
+            current = match mir {
+                Mir::If0(a, b, c) => {
+                    let (zero, other) = (self.new_block(), self.new_block());
+                    self.terminate(block, Terminator::Branch(id, *a, zero, other));
+                    let ends = [self.lower(b, Some(zero)), self.lower(c, Some(other))];
+                    Some(self.join(&ends))
+                }
+                Mir::Match(a, arms) => {
+                    let join = self.new_block();
+                    let targets: Vec<_> = arms
+                        .iter()
+                        .map(|(_, x)| (ValueSet::from_values(x), self.new_block()))
+                        .collect();
+                    self.terminate(block, Terminator::Switch(id, *a, targets.clone(), join));
+                    for ((code, _), (_, target)) in arms.iter().zip(targets) {
+                        if let Some(e) = self.lower(code, Some(target)) {
+                            self.terminate(e, Terminator::Goto(join));
+                        }
+                    }
+                    Some(join)
+                }
+                Mir::Loop(a) => {
+                    let (header, exit) = (self.new_block(), self.new_block());
+                    self.terminate(block, Terminator::Goto(header));
+                    self.loops.push((header, exit));
+                    if let Some(e) = self.lower(a, Some(header)) {
+                        self.terminate(e, Terminator::Goto(header));
+                    }
+                    self.loops.pop();
+                    Some(exit)
+                }
+                Mir::Block(a) => {
+                    let end = self.new_block();
+                    self.ends.push(end);
+                    if let Some(e) = self.lower(a, Some(block)) {
+                        self.terminate(e, Terminator::Goto(end));
+                    }
+                    self.ends.pop();
+                    Some(end)
+                }
+                Mir::Break => {
+                    let exit = self.loops.last().expect("Break outside of a loop").1;
+                    self.terminate(block, Terminator::Goto(exit));
+                    None
+                }
+                Mir::Continue => {
+                    let header = self.loops.last().expect("Continue outside of a loop").0;
+                    self.terminate(block, Terminator::Goto(header));
+                    None
+                }
+                Mir::Skip => {
+                    let end = *self.ends.last().expect("Skip outside of a block");
+                    self.terminate(block, Terminator::Goto(end));
+                    None
+                }
+                Mir::Stop => {
+                    self.terminate(block, Terminator::Stop);
+                    None
+                }
+                e => {
+                    self.blocks[block].instructions.push((id, e.clone()));
+                    Some(block)
+                }
+            };
+        }
+        current
+    }
+
+    fn join(&mut self, ends: &[Option<BlockId>]) -> BlockId {
+        let join = self.new_block();
+        for e in ends.iter().flatten() {
+            self.terminate(*e, Terminator::Goto(join));
+        }
+        join
+    }
+}
+
+/// The variable read by an instruction that doesn't change the control flow
+pub fn read(mir: &Mir) -> Option<u32> {
+    match mir {
+        Mir::Copy(_, a) | Mir::Increment(a) | Mir::Decrement(a) => Some(*a),
+        Mir::WriteRegister(_, Either::Right(a)) => Some(*a),
+        _ => None,
+    }
+}
+
+/// The variables read by an instruction that doesn't change the control flow, a call reads
+/// every variable its procedure accesses
+pub fn reads(mir: &Mir) -> Vec<u32> {
+    match mir {
+        Mir::Call(_) => mir.get_acesses().into_iter().collect(),
+        e => read(e).into_iter().collect(),
+    }
+}
+
+/// The variable written by an instruction that doesn't change the control flow
+pub fn written(mir: &Mir) -> Option<u32> {
+    match mir {
+        Mir::Set(a, _)
+        | Mir::Copy(a, _)
+        | Mir::Increment(a)
+        | Mir::Decrement(a)
+        | Mir::ReadRegister(a, _) => Some(*a),
+        _ => None,
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Goto(a) => vec![*a],
+            Terminator::Branch(_, _, a, b) => vec![*a, *b],
+            Terminator::Switch(_, _, a, b) => a.iter().map(|x| x.1).chain(Some(*b)).collect(),
+            Terminator::Stop => Vec::new(),
+        }
+    }
+
+    pub fn read(&self) -> Option<(InstrId, u32)> {
+        match self {
+            Terminator::Branch(id, a, _, _) | Terminator::Switch(id, a, _, _) => Some((*id, *a)),
+            _ => None,
+        }
+    }
+}
+
+impl Cfg {
+    pub fn new(code: &MirCodeBlock) -> Self {
+        let mut builder = Builder {
+            blocks: Vec::new(),
+            location: Vec::new(),
+            loops: Vec::new(),
+            ends: Vec::new(),
+        };
+        let entry = builder.new_block();
+        builder.lower(code, Some(entry));
+        Self {
+            blocks: builder.blocks,
+            location: builder.location,
+        }
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut out = vec![Vec::new(); self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            for j in block.terminator.successors() {
+                out[j].push(i);
+            }
+        }
+        out
+    }
+
+    /// The blocks reachable from the entry, each one before its successors except on back edges
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        // The recursion is done with a stack because the graph can be very deep
+        let mut stack = vec![(ENTRY, 0)];
+        visited[ENTRY] = true;
+        while let Some((block, next)) = stack.pop() {
+            let successors = self.blocks[block].terminator.successors();
+            if let Some(&successor) = successors.get(next) {
+                stack.push((block, next + 1));
+                if !visited[successor] {
+                    visited[successor] = true;
+                    stack.push((successor, 0));
+                }
+            } else {
+                order.push(block);
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// The immediate dominator of every reachable block, `None` for the other blocks and the
+    /// entry (Cooper, Harvey and Kennedy algorithm)
+    pub fn dominators(&self) -> Vec<Option<BlockId>> {
+        let order = self.reverse_postorder();
+        let mut index = vec![usize::MAX; self.blocks.len()];
+        for (i, b) in order.iter().enumerate() {
+            index[*b] = i;
+        }
+        let predecessors = self.predecessors();
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        idom[ENTRY] = Some(ENTRY);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in order.iter().skip(1) {
+                let mut new: Option<BlockId> = None;
+                for &p in &predecessors[b] {
+                    if idom[p].is_none() {
+                        continue;
+                    }
+                    new = Some(match new {
+                        None => p,
+                        Some(mut x) => {
+                            let mut y = p;
+                            while x != y {
+                                while index[x] > index[y] {
+                                    x = idom[x].unwrap();
+                                }
+                                while index[y] > index[x] {
+                                    y = idom[y].unwrap();
+                                }
+                            }
+                            x
+                        }
+                    });
+                }
+                if new.is_some() && idom[b] != new {
+                    idom[b] = new;
+                    changed = true;
+                }
+            }
+        }
+        idom[ENTRY] = None;
+        idom
+    }
+
+    /// The blocks where the domination of each block stops
+    pub fn dominance_frontiers(&self, idom: &[Option<BlockId>]) -> Vec<HashSet<BlockId>> {
+        let mut frontiers = vec![HashSet::new(); self.blocks.len()];
+        for (b, predecessors) in self.predecessors().iter().enumerate() {
+            if predecessors.len() < 2 || (idom[b].is_none() && b != ENTRY) {
+                continue;
+            }
+            for &p in predecessors {
+                let mut runner = p;
+                // Unreachable predecessors don't have a dominator
+                while Some(runner) != idom[b] && (idom[runner].is_some() || runner == ENTRY) {
+                    frontiers[runner].insert(b);
+                    match idom[runner] {
+                        Some(e) => runner = e,
+                        None => break,
+                    }
+                }
+            }
+        }
+        frontiers
+    }
+
+    /// The variables whose value can be read after the end of each block
+    pub fn live_out(&self) -> Vec<HashSet<u32>> {
+        // The variables read before being written in each block
+        let mut uses = vec![HashSet::new(); self.blocks.len()];
+        let mut defs = vec![HashSet::new(); self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            if let Some((_, a)) = block.terminator.read() {
+                uses[i].insert(a);
+            }
+            for (_, mir) in block.instructions.iter().rev() {
+                if let Some(a) = written(mir) {
+                    uses[i].remove(&a);
+                    defs[i].insert(a);
+                }
+                uses[i].extend(reads(mir));
+            }
+        }
+        let mut order = self.reverse_postorder();
+        order.reverse();
+        let mut live_in: Vec<HashSet<u32>> = uses.clone();
+        let mut live_out = vec![HashSet::new(); self.blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in &order {
+                let mut out = HashSet::new();
+                for s in self.blocks[b].terminator.successors() {
+                    out.extend(live_in[s].iter().copied());
+                }
+                if out.len() != live_out[b].len() {
+                    let mut new = uses[b].clone();
+                    new.extend(out.iter().filter(|x| !defs[b].contains(x)));
+                    live_in[b] = new;
+                    live_out[b] = out;
+                    changed = true;
+                }
+            }
+        }
+        live_out
+    }
+
+    /// The writes whose value is never read
+    pub fn dead_stores(&self) -> HashSet<InstrId> {
+        let live_out = self.live_out();
+        let mut dead = HashSet::new();
+        for b in self.reverse_postorder() {
+            let block = &self.blocks[b];
+            let mut live = live_out[b].clone();
+            if let Some((_, a)) = block.terminator.read() {
+                live.insert(a);
+            }
+            for (id, mir) in block.instructions.iter().rev() {
+                if let Some(a) = written(mir) {
+                    if !live.remove(&a) {
+                        dead.insert(*id);
+                        continue;
+                    }
+                }
+                live.extend(reads(mir));
+            }
+        }
+        dead
+    }
+}
+
+/// A change to apply to an instruction of the code
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rewrite {
+    Remove,
+    Replace(Mir),
+    /// Replaces an `If0` or a `Match` by the code of one of its branches
+    Inline(usize),
+    /// Only keeps these arms of a `Match`
+    Arms(Vec<usize>),
+}
+
+/// Applies the rewrites to the code, this is how the results of the analyses done on the
+/// graph go back to structured MIR
+pub fn rewrite(code: MirCodeBlock, rewrites: &HashMap<InstrId, Rewrite>) -> MirCodeBlock {
+    fn inner(code: MirCodeBlock, rewrites: &HashMap<InstrId, Rewrite>, id: &mut InstrId) -> Vec<Mir> {
+        let mut out = Vec::new();
+        for mir in code {
+            let current = *id;
+            *id += 1;
+            // The children are always rewritten so the ids stay in sync
+            let mir = match mir {
+                Mir::If0(a, b, c) => {
+                    let b = MirCodeBlock(inner(b, rewrites, id));
+                    let c = MirCodeBlock(inner(c, rewrites, id));
+                    Mir::If0(a, b, c)
+                }
+                Mir::Loop(a) => Mir::Loop(MirCodeBlock(inner(a, rewrites, id))),
+                Mir::Block(a) => Mir::Block(MirCodeBlock(inner(a, rewrites, id))),
+                Mir::Match(a, b) => Mir::Match(
+                    a,
+                    b.into_iter()
+                        .map(|(x, y)| (MirCodeBlock(inner(x, rewrites, id)), y))
+                        .collect(),
+                ),
+                e => e,
+            };
+            match (rewrites.get(&current), mir) {
+                (None, e) => out.push(e),
+                (Some(Rewrite::Remove), _) => (),
+                (Some(Rewrite::Replace(e)), _) => out.push(e.clone()),
+                (Some(Rewrite::Inline(i)), Mir::If0(_, b, c)) => {
+                    out.extend(if *i == 0 { b } else { c });
+                }
+                (Some(Rewrite::Inline(i)), Mir::Match(_, mut b)) => {
+                    out.extend(b.swap_remove(*i).0);
+                }
+                (Some(Rewrite::Arms(arms)), Mir::Match(a, b)) => out.push(Mir::Match(
+                    a,
+                    b.into_iter()
+                        .enumerate()
+                        .filter(|(i, _)| arms.contains(i))
+                        .map(|(_, x)| x)
+                        .collect(),
+                )),
+                (Some(e), mir) => panic!("Can't apply {:?} to {}", e, mir),
+            }
+        }
+        out
+    }
+    MirCodeBlock(inner(code, rewrites, &mut 0))
+}
+
+/// Removes the writes to variables that are not read before being overwritten on every path
+pub fn dead_store_elimination(mut code: MirCodeBlock) -> MirCodeBlock {
+    loop {
+        let dead = Cfg::new(&code).dead_stores();
+        if dead.is_empty() {
+            return code;
+        }
+        let rewrites = dead.into_iter().map(|x| (x, Rewrite::Remove)).collect();
+        code = rewrite(code, &rewrites);
+    }
+}
+
+#[test]
+fn test_cfg_dominators() {
+    // v1 = 1; loop { if v1 { break } else { v1-- } }; 0 <@ v1
+    let code = MirCodeBlock(vec![
+        Mir::Set(1, 1),
+        Mir::Loop(MirCodeBlock(vec![Mir::If0(
+            1,
+            Mir::Break.into(),
+            Mir::Decrement(1).into(),
+        )])),
+        Mir::WriteRegister(0, Either::Right(1)),
+    ]);
+    let cfg = Cfg::new(&code);
+    let idom = cfg.dominators();
+    let header = match cfg.blocks[ENTRY].terminator {
+        Terminator::Goto(e) => e,
+        _ => panic!(),
+    };
+    let zero = match cfg.blocks[header].terminator {
+        Terminator::Branch(_, 1, a, _) => a,
+        _ => panic!(),
+    };
+    // The loop is only left by the break
+    let exit = cfg.location[5];
+    assert_eq!(idom[header], Some(ENTRY));
+    assert_eq!(idom[zero], Some(header));
+    assert_eq!(idom[exit], Some(zero));
+    assert!(cfg.dominance_frontiers(&idom)[header].contains(&header));
+    assert!(cfg.live_out()[ENTRY].contains(&1));
+}
+
+#[test]
+fn test_dead_stores_across_branches() {
+    let code = MirCodeBlock(vec![
+        Mir::ReadRegister(2, 1),
+        // Overwritten in both branches
+        Mir::Set(1, 3),
+        Mir::If0(2, Mir::Set(1, 4).into(), Mir::Set(1, 5).into()),
+        Mir::WriteRegister(2, Either::Right(1)),
+        // Read in the next iterations of the loop
+        Mir::Set(3, 1),
+        Mir::Loop(MirCodeBlock(vec![
+            Mir::WriteRegister(2, Either::Right(3)),
+            Mir::Set(3, 2),
+        ])),
+    ]);
+    let code = dead_store_elimination(code);
+    assert_eq!(code.0[1], Mir::If0(2, Mir::Set(1, 4).into(), Mir::Set(1, 5).into()));
+    assert_eq!(code.0[3], Mir::Set(3, 1));
+    assert_eq!(code.len(), 5);
+}
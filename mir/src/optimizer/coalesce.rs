@@ -0,0 +1,250 @@
+use std::collections::{HashMap, HashSet};
+
+use either::Either;
+
+use crate::{Mir, MirCodeBlock};
+
+use super::cfg::{reads, written, Cfg, ENTRY};
+
+/// How many memory cells the variables used before and after `coalesce_variables`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CoalesceStats {
+    pub variables: usize,
+    pub slots: usize,
+}
+
+impl CoalesceStats {
+    pub fn saved(&self) -> usize {
+        self.variables - self.slots
+    }
+}
+
+/// The variables each variable can't share a memory cell with
+type Interferences = HashMap<u32, HashSet<u32>>;
+
+/// The pairs of variables that hold a value at the same time and the copies between variables
+fn interferences(code: &MirCodeBlock) -> (Interferences, Vec<(u32, u32)>) {
+    let cfg = Cfg::new(code);
+    let live_out = cfg.live_out();
+    let mut graph: Interferences = HashMap::new();
+    let mut copies = Vec::new();
+    let mut add = |a: u32, b: u32| {
+        if a != b {
+            graph.entry(a).or_default().insert(b);
+            graph.entry(b).or_default().insert(a);
+        }
+    };
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        let mut live = live_out[b].clone();
+        if let Some((_, a)) = block.terminator.read() {
+            live.insert(a);
+        }
+        for (_, mir) in block.instructions.iter().rev() {
+            if let Some(a) = written(mir) {
+                // After a copy both variables hold the same value so they can share a cell
+                let source = match mir {
+                    Mir::Copy(_, e) => {
+                        copies.push((a, *e));
+                        Some(*e)
+                    }
+                    _ => None,
+                };
+                for &l in &live {
+                    if Some(l) != source {
+                        add(a, l);
+                    }
+                }
+                live.remove(&a);
+            }
+            live.extend(reads(mir));
+        }
+        // The variables read before being written rely on starting at 0
+        if b == ENTRY {
+            for &a in &live {
+                for &l in &live {
+                    add(a, l);
+                }
+            }
+        }
+    }
+    (graph, copies)
+}
+
+fn variables(code: &MirCodeBlock, out: &mut Vec<u32>) {
+    for mir in code.iter() {
+        match mir {
+            Mir::If0(a, b, c) => {
+                out.push(*a);
+                variables(b, out);
+                variables(c, out);
+            }
+            Mir::Loop(a) | Mir::Block(a) => variables(a, out),
+            Mir::Match(a, b) => {
+                out.push(*a);
+                b.iter().for_each(|(x, _)| variables(x, out));
+            }
+            e => {
+                out.extend(written(e));
+                out.extend(reads(e));
+            }
+        }
+    }
+}
+
+/// The variables accessed by the procedures the code calls
+fn procedure_variables(code: &MirCodeBlock, out: &mut HashSet<u32>) {
+    for mir in code.iter() {
+        match mir {
+            Mir::If0(_, b, c) => {
+                procedure_variables(b, out);
+                procedure_variables(c, out);
+            }
+            Mir::Loop(a) | Mir::Block(a) => procedure_variables(a, out),
+            Mir::Match(_, b) => b.iter().for_each(|(x, _)| procedure_variables(x, out)),
+            Mir::Call(_) => out.extend(mir.get_acesses()),
+            _ => (),
+        }
+    }
+}
+
+fn rename(code: MirCodeBlock, slots: &HashMap<u32, u32>) -> MirCodeBlock {
+    let slot = |a: u32| slots[&a];
+    MirCodeBlock(
+        code.into_iter()
+            .filter_map(|mir| {
+                Some(match mir {
+                    Mir::Set(a, b) => Mir::Set(slot(a), b),
+                    Mir::Copy(a, b) if slot(a) == slot(b) => return None,
+                    Mir::Copy(a, b) => Mir::Copy(slot(a), slot(b)),
+                    Mir::Increment(a) => Mir::Increment(slot(a)),
+                    Mir::Decrement(a) => Mir::Decrement(slot(a)),
+                    Mir::If0(a, b, c) => Mir::If0(slot(a), rename(b, slots), rename(c, slots)),
+                    Mir::Loop(a) => Mir::Loop(rename(a, slots)),
+                    Mir::Block(a) => Mir::Block(rename(a, slots)),
+                    Mir::Match(a, b) => Mir::Match(
+                        slot(a),
+                        b.into_iter().map(|(x, y)| (rename(x, slots), y)).collect(),
+                    ),
+                    Mir::ReadRegister(a, b) => Mir::ReadRegister(slot(a), b),
+                    Mir::WriteRegister(a, Either::Right(b)) => {
+                        Mir::WriteRegister(a, Either::Right(slot(b)))
+                    }
+                    e => e,
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Makes the variables that never hold a value at the same time share the same memory cell.
+/// Each cell keeps the id of the first variable it was given to.
+pub fn coalesce_variables(code: MirCodeBlock) -> (MirCodeBlock, CoalesceStats) {
+    let (graph, copies) = interferences(&code);
+    let mut order = Vec::new();
+    variables(&code, &mut order);
+    let mut seen = HashSet::new();
+    order.retain(|x| seen.insert(*x));
+
+    // The code of the procedures is shared by every call so their variables keep their cell
+    let mut pinned = HashSet::new();
+    procedure_variables(&code, &mut pinned);
+
+    let empty = HashSet::new();
+    let mut slots: HashMap<u32, u32> = HashMap::new();
+    let mut members: Vec<(u32, Vec<u32>)> = Vec::new();
+    for var in order {
+        if pinned.contains(&var) {
+            members.push((var, vec![var]));
+            slots.insert(var, var);
+            continue;
+        }
+        let conflicts = graph.get(&var).unwrap_or(&empty);
+        let free = |(id, vars): &&(u32, Vec<u32>)| {
+            !pinned.contains(id) && !vars.iter().any(|x| conflicts.contains(x))
+        };
+        // The cell of a variable it is copied to or from is tried first to remove the copy
+        let partners: HashSet<u32> = copies
+            .iter()
+            .filter_map(|&(a, b)| {
+                if a == var {
+                    slots.get(&b).copied()
+                } else if b == var {
+                    slots.get(&a).copied()
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let found = members
+            .iter()
+            .filter(|x| partners.contains(&x.0))
+            .find(free)
+            .or_else(|| members.iter().find(free))
+            .map(|x| x.0);
+        let id = match found {
+            Some(e) => e,
+            None => {
+                members.push((var, Vec::new()));
+                var
+            }
+        };
+        members.iter_mut().find(|x| x.0 == id).unwrap().1.push(var);
+        slots.insert(var, id);
+    }
+    let stats = CoalesceStats {
+        variables: slots.len(),
+        slots: members.len(),
+    };
+    (rename(code, &slots), stats)
+}
+
+#[test]
+fn test_coalesce_disjoint_variables() {
+    // v1 and v2 are never alive at the same time, v3 is a copy of v2
+    let code = MirCodeBlock(vec![
+        Mir::ReadRegister(1, 1),
+        Mir::WriteRegister(2, Either::Right(1)),
+        Mir::ReadRegister(2, 1),
+        Mir::Copy(3, 2),
+        Mir::Increment(3),
+        Mir::WriteRegister(2, Either::Right(3)),
+    ]);
+    let (code, stats) = coalesce_variables(code);
+    assert_eq!(stats.slots, 1);
+    assert_eq!(stats.saved(), 2);
+    assert_eq!(code.instr_count(), 5);
+}
+
+#[test]
+fn test_coalesce_keeps_live_variables() {
+    let code = MirCodeBlock(vec![
+        Mir::ReadRegister(1, 1),
+        Mir::ReadRegister(2, 1),
+        Mir::If0(1, Mir::Increment(2).into(), MirCodeBlock(vec![])),
+        Mir::WriteRegister(2, Either::Right(2)),
+    ]);
+    let (_, stats) = coalesce_variables(code);
+    assert_eq!(stats.saved(), 0);
+}
+
+#[test]
+fn test_coalesce_keeps_procedure_variables() {
+    use crate::Procedure;
+    let call = Mir::Call(Procedure::with_code(
+        "inc".to_owned(),
+        (12, 13),
+        Mir::Increment(10).into(),
+    ));
+    // v1 and v2 can share a cell, v10 is used by the procedure
+    let code = MirCodeBlock(vec![
+        Mir::ReadRegister(1, 1),
+        Mir::Copy(10, 1),
+        call.clone(),
+        Mir::Copy(2, 10),
+        Mir::WriteRegister(2, Either::Right(2)),
+    ]);
+    let (code, stats) = coalesce_variables(code);
+    assert_eq!(code[2], call);
+    assert_eq!(code[3], Mir::Copy(1, 10));
+    assert_eq!(stats.saved(), 1);
+}
@@ -0,0 +1,41 @@
+use crate::{
+    estimate::{CALL, CONDITION, COPY, INCREMENT, JUMP, LABEL, MATCH},
+    Mir, MirCodeBlock,
+};
+
+impl MirCodeBlock {
+    /// Estimates the amount of Cythan steps needed to run the block once compiled. Loops are
+    /// counted as a single iteration and only the most expensive branch of a condition is
+    /// counted so this is only meaningful to compare versions of the same program.
+    pub fn estimated_steps(&self) -> usize {
+        self.iter().map(Mir::estimated_steps).sum()
+    }
+}
+
+impl Mir {
+    pub fn estimated_steps(&self) -> usize {
+        match self {
+            Mir::Set(_, _)
+            | Mir::Copy(_, _)
+            | Mir::ReadRegister(_, _)
+            | Mir::WriteRegister(_, _)
+            | Mir::Stop => COPY,
+            Mir::Increment(_) | Mir::Decrement(_) => INCREMENT,
+            Mir::Break | Mir::Continue | Mir::Skip => JUMP + LABEL,
+            Mir::If0(_, a, b) => CONDITION + a.estimated_steps().max(b.estimated_steps()),
+            Mir::Loop(a) => a.estimated_steps() + JUMP + LABEL,
+            Mir::Block(a) => a.estimated_steps(),
+            Mir::Match(_, a) => {
+                MATCH
+                    + JUMP
+                    + 2 * LABEL
+                    + a.iter()
+                        .map(|(x, _)| x.estimated_steps())
+                        .max()
+                        .unwrap_or_default()
+            }
+            Mir::Call(a) => CALL + a.steps(),
+            Mir::Location(..) | Mir::Debug(_) => 0,
+        }
+    }
+}
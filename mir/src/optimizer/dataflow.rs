@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use crate::Mir;
+
+/// The values a variable can hold at a point of the program, bit `n` is set if it can be `n`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ValueSet(u16);
+
+impl ValueSet {
+    pub const ALL: Self = Self(u16::MAX);
+    pub const EMPTY: Self = Self(0);
+
+    pub fn single(value: u8) -> Self {
+        Self(1 << (value % 16))
+    }
+
+    pub fn from_values(values: &[u8]) -> Self {
+        values
+            .iter()
+            .fold(Self::EMPTY, |a, b| a.union(Self::single(*b)))
+    }
+
+    pub fn contains(self, value: u8) -> bool {
+        self.0 & Self::single(value).0 != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The value of the variable if it can only hold one
+    pub fn single_value(self) -> Option<u8> {
+        (self.0.count_ones() == 1).then(|| self.0.trailing_zeros() as u8)
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// The values after an `Increment`
+    pub fn increment(self) -> Self {
+        Self(self.0.rotate_left(1))
+    }
+
+    /// The values after a `Decrement`
+    pub fn decrement(self) -> Self {
+        Self(self.0.rotate_right(1))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VarState {
+    Values(ValueSet),
+    /// The variable holds the same value as this one
+    Ref(u32),
+}
+
+/// What is known about the variables at a point of the program. Variables that aren't in the
+/// state can hold any value.
+#[derive(Clone, Debug)]
+pub struct State {
+    variables: HashMap<u32, VarState>,
+    /// Whether this point of the program can be reached (The code after a `Break` can't)
+    reachable: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            variables: HashMap::new(),
+            reachable: true,
+        }
+    }
+}
+
+impl State {
+    pub fn unreachable() -> Self {
+        Self {
+            variables: HashMap::new(),
+            reachable: false,
+        }
+    }
+
+    pub fn is_reachable(&self) -> bool {
+        self.reachable
+    }
+
+    /// The variable that holds the value of this one
+    pub fn root(&self, id: u32) -> u32 {
+        match self.variables.get(&id) {
+            Some(VarState::Ref(a)) => self.root(*a),
+            _ => id,
+        }
+    }
+
+    pub fn values(&self, id: u32) -> ValueSet {
+        match self.variables.get(&self.root(id)) {
+            Some(VarState::Values(a)) => *a,
+            _ => ValueSet::ALL,
+        }
+    }
+
+    pub fn get_value(&self, id: u32) -> Option<u8> {
+        self.values(id).single_value()
+    }
+
+    /// Forgets everything about the variable. The variables that were a reference to it keep
+    /// its previous value.
+    pub fn remove(&mut self, id: u32) {
+        let previous = self.variables.remove(&id);
+        let refs: Vec<u32> = self
+            .variables
+            .iter()
+            .filter(|(_, x)| **x == VarState::Ref(id))
+            .map(|(a, _)| *a)
+            .collect();
+        let mut refs = refs.into_iter();
+        // The first reference becomes the root of the other ones
+        if let Some(first) = refs.next() {
+            match previous {
+                Some(e) => {
+                    self.variables.insert(first, e);
+                }
+                None => {
+                    self.variables.remove(&first);
+                }
+            }
+            for i in refs {
+                self.variables.insert(i, VarState::Ref(first));
+            }
+        }
+    }
+
+    pub fn set(&mut self, id: u32, state: VarState) {
+        self.remove(id);
+        match state {
+            VarState::Values(ValueSet::ALL) => (),
+            e => {
+                self.variables.insert(id, e);
+            }
+        }
+    }
+
+    pub fn set_values(&mut self, id: u32, values: ValueSet) {
+        self.set(id, VarState::Values(values))
+    }
+
+    /// Restricts the values of the variable, for instance in the branches of a condition on it.
+    /// A state in which the variable can't hold any value is unreachable.
+    pub fn narrow(&mut self, id: u32, values: ValueSet) {
+        let root = self.root(id);
+        let values = self.values(root).intersection(values);
+        if values.is_empty() {
+            *self = Self::unreachable();
+        } else if values != ValueSet::ALL {
+            // The references to the root are still valid so `set` isn't used
+            self.variables.insert(root, VarState::Values(values));
+        }
+    }
+
+    /// The state after code that can come from both states
+    pub fn merge(&self, other: &Self) -> Self {
+        if !self.reachable {
+            return other.clone();
+        }
+        if !other.reachable {
+            return self.clone();
+        }
+        let mut variables = HashMap::new();
+        // A variable that is missing from one of the states can hold any value
+        for &id in self.variables.keys() {
+            let root = self.root(id);
+            if root != id && root == other.root(id) {
+                variables.insert(id, VarState::Ref(root));
+            } else {
+                let values = self.values(id).union(other.values(id));
+                if values != ValueSet::ALL {
+                    variables.insert(id, VarState::Values(values));
+                }
+            }
+        }
+        Self {
+            variables,
+            reachable: true,
+        }
+    }
+}
+
+/// Whether the code after this instruction is never reached, because it leaves the loop or
+/// stops the program in every case
+pub fn does_break_in_all_cases(mir: &Mir) -> bool {
+    match mir {
+        Mir::If0(_, a, b) => {
+            a.iter().any(does_break_in_all_cases) && b.iter().any(does_break_in_all_cases)
+        }
+        Mir::Break => true,
+        Mir::Stop => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn test_value_set() {
+    let a = ValueSet::from_values(&[0, 15]);
+    assert_eq!(a.increment(), ValueSet::from_values(&[0, 1]));
+    assert_eq!(a.decrement(), ValueSet::from_values(&[14, 15]));
+    assert_eq!(ValueSet::single(3).single_value(), Some(3));
+    assert_eq!(a.single_value(), None);
+    assert!(a.contains(15) && !a.contains(1));
+}
+
+#[test]
+fn test_merge_keeps_value_sets() {
+    let mut a = State::default();
+    let mut b = State::default();
+    a.set_values(1, ValueSet::single(2));
+    b.set_values(1, ValueSet::single(5));
+    a.set(2, VarState::Ref(1));
+    b.set(2, VarState::Ref(1));
+    let merged = a.merge(&b);
+    assert_eq!(merged.values(1), ValueSet::from_values(&[2, 5]));
+    assert_eq!(merged.root(2), 1);
+    assert_eq!(merged.values(3), ValueSet::ALL);
+}
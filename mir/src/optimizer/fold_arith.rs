@@ -0,0 +1,139 @@
+use crate::{Mir, MirCodeBlock};
+
+/// What the start of a run of instructions on a variable sets it to
+#[derive(Clone, Copy)]
+enum Base {
+    /// The variable keeps the value it had before the run
+    Unchanged,
+    Value(u8),
+    Copy(u32),
+}
+
+/// The variable written by the instructions that can be folded
+fn target(mir: &Mir) -> Option<u32> {
+    match mir {
+        Mir::Set(a, _) | Mir::Increment(a) | Mir::Decrement(a) => Some(*a),
+        Mir::Copy(a, b) if a != b => Some(*a),
+        _ => None,
+    }
+}
+
+/// The shortest code adding `offset` to the variable, modulo 16
+fn add(var: u32, offset: u8) -> Vec<Mir> {
+    if offset <= 8 {
+        vec![Mir::Increment(var); offset as usize]
+    } else {
+        vec![Mir::Decrement(var); 16 - offset as usize]
+    }
+}
+
+/// Replaces a run of `Set`, `Copy`, `Increment` and `Decrement` on the variable by its net
+/// effect
+fn fold(var: u32, run: &[Mir]) -> Vec<Mir> {
+    let mut base = Base::Unchanged;
+    let mut offset: u8 = 0;
+    for mir in run {
+        match mir {
+            Mir::Set(_, a) => {
+                base = Base::Value(*a);
+                offset = 0;
+            }
+            Mir::Copy(_, a) => {
+                base = Base::Copy(*a);
+                offset = 0;
+            }
+            Mir::Increment(_) => offset = (offset + 1) % 16,
+            Mir::Decrement(_) => offset = (offset + 15) % 16,
+            _ => unreachable!(),
+        }
+    }
+    match base {
+        Base::Value(a) => vec![Mir::Set(var, (a + offset) % 16)],
+        Base::Copy(a) => {
+            let mut out = vec![Mir::Copy(var, a)];
+            out.extend(add(var, offset));
+            out
+        }
+        Base::Unchanged => add(var, offset),
+    }
+}
+
+/// Folds the consecutive increments, decrements, sets and copies of a variable into the
+/// shortest code giving the same value
+pub fn fold_arithmetic(code: MirCodeBlock) -> MirCodeBlock {
+    let mut out = Vec::new();
+    let mut code = code.0.into_iter().peekable();
+    while let Some(mir) = code.next() {
+        if let Some(var) = target(&mir) {
+            let mut run = vec![mir];
+            // The locations inside the run are dropped with the code they point to
+            let mut locations = Vec::new();
+            while let Some(e) =
+                code.next_if(|x| target(x) == Some(var) || matches!(x, Mir::Location(..)))
+            {
+                if let Mir::Location(..) = e {
+                    locations.push(e);
+                } else {
+                    locations.clear();
+                    run.push(e);
+                }
+            }
+            out.extend(if run.len() > 1 { fold(var, &run) } else { run });
+            out.extend(locations);
+            continue;
+        }
+        out.push(match mir {
+            Mir::If0(a, b, c) => Mir::If0(a, fold_arithmetic(b), fold_arithmetic(c)),
+            Mir::Loop(a) => Mir::Loop(fold_arithmetic(a)),
+            Mir::Block(a) => Mir::Block(fold_arithmetic(a)),
+            Mir::Match(a, b) => Mir::Match(
+                a,
+                b.into_iter()
+                    .map(|(x, y)| (fold_arithmetic(x), y))
+                    .collect(),
+            ),
+            e => e,
+        });
+    }
+    MirCodeBlock(out)
+}
+
+#[test]
+fn test_fold_arithmetic() {
+    let code = MirCodeBlock(vec![
+        Mir::Increment(1),
+        Mir::Increment(1),
+        Mir::Decrement(1),
+        Mir::Increment(1),
+        Mir::Copy(2, 3),
+        Mir::Decrement(2),
+        Mir::Decrement(2),
+        Mir::Decrement(2),
+        Mir::Decrement(2),
+        Mir::Decrement(2),
+        Mir::Decrement(2),
+        Mir::Decrement(2),
+        Mir::Decrement(2),
+        Mir::Decrement(2),
+        Mir::Decrement(2),
+        Mir::Set(4, 14),
+        Mir::Increment(4),
+        Mir::Increment(4),
+        Mir::Increment(4),
+    ]);
+    assert_eq!(
+        fold_arithmetic(code).0,
+        vec![
+            Mir::Increment(1),
+            Mir::Increment(1),
+            Mir::Copy(2, 3),
+            Mir::Increment(2),
+            Mir::Increment(2),
+            Mir::Increment(2),
+            Mir::Increment(2),
+            Mir::Increment(2),
+            Mir::Increment(2),
+            Mir::Set(4, 1),
+        ]
+    );
+}
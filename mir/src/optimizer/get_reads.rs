@@ -23,6 +23,7 @@ impl MirCodeBlock {
                 Mir::Continue => (),
                 Mir::Stop => (),
                 Mir::Skip => (),
+                Mir::Location(..) | Mir::Debug(_) => (),
                 Mir::Set(_, _) => (),
                 Mir::Increment(_) => (),
                 Mir::Decrement(_) => (),
@@ -34,6 +35,8 @@ impl MirCodeBlock {
                         b.iter().for_each(|x| inner(x, muts));
                     });
                 }
+                // The increments of the procedure read its variables too
+                Mir::Call(_) => muts.extend(mir.get_acesses()),
             }
         }
         let mut set = HashSet::new();
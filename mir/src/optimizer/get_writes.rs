@@ -25,11 +25,13 @@ impl MirCodeBlock {
                 Mir::Stop => (),
                 Mir::WriteRegister(_, _) => (),
                 Mir::Skip => (),
+                Mir::Location(..) | Mir::Debug(_) => (),
                 Mir::Match(_, b) => {
                     b.iter().for_each(|(b, _)| {
                         b.iter().for_each(|x| inner(x, muts));
                     });
                 }
+                Mir::Call(a) => muts.extend(a.writes()),
             }
         }
         let mut set = HashSet::new();
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use crate::{Mir, MirCodeBlock};
+
+use super::{cfg::written, dataflow::State, new_opt::optimize_block};
+
+/// The maximum amount of iterations of an unrolled loop
+const MAX_TRIP_COUNT: usize = 17;
+/// The maximum amount of instructions an unrolled loop can be replaced by
+const UNROLL_BUDGET: usize = 256;
+
+/// Replaces a loop by the code of each of its iterations if the values known before it decide
+/// every condition of the loop, so the number of iterations is known. The state becomes the
+/// state after the loop.
+pub(crate) fn unroll_loop(body: &MirCodeBlock, context: &mut State) -> Option<Vec<Mir>> {
+    // Each copy of a call is a new call site, the front end only counted the one in the loop
+    if body.iter().any(Mir::calls) {
+        return None;
+    }
+    let mut out = Vec::new();
+    let mut current = context.clone();
+    for _ in 0..MAX_TRIP_COUNT {
+        let mut state = current.clone();
+        let mut iteration = optimize_block(body.clone(), &mut state, true);
+        let last = match iteration.last() {
+            Some(Mir::Break) | Some(Mir::Continue) => iteration.pop(),
+            _ => None,
+        };
+        // The iteration must not depend on the values that aren't known
+        if iteration.iter().any(|x| x.breaks() || x.continues()) {
+            return None;
+        }
+        if last.is_some() {
+            // The state at the `Break` or `Continue`
+            state = current.clone();
+            iteration = optimize_block(MirCodeBlock(iteration), &mut state, true);
+        }
+        out.extend(iteration);
+        if MirCodeBlock(out.clone()).instr_count() > UNROLL_BUDGET {
+            return None;
+        }
+        match last {
+            Some(Mir::Break) => {
+                *context = state;
+                return Some(out);
+            }
+            // The program stopped or left the block the loop is in
+            _ if !state.is_reachable() => {
+                *context = state;
+                return Some(out);
+            }
+            _ => current = state,
+        }
+    }
+    None
+}
+
+/// Unrolls the loops that run a small known number of times
+pub fn unroll_loops(code: MirCodeBlock) -> MirCodeBlock {
+    MirCodeBlock(optimize_block(code, &mut State::default(), true))
+}
+
+/// Moves the `Set` and `Copy` whose value is the same in every iteration before the loop
+pub fn hoist_invariants(code: MirCodeBlock) -> MirCodeBlock {
+    MirCodeBlock(
+        code.into_iter()
+            .flat_map(|mir| match mir {
+                Mir::Loop(a) => {
+                    let (mut hoisted, body) = hoist(hoist_invariants(a));
+                    hoisted.push(Mir::Loop(body));
+                    hoisted
+                }
+                Mir::If0(a, b, c) => vec![Mir::If0(a, hoist_invariants(b), hoist_invariants(c))],
+                Mir::Block(a) => vec![Mir::Block(hoist_invariants(a))],
+                Mir::Match(a, b) => vec![Mir::Match(
+                    a,
+                    b.into_iter()
+                        .map(|(x, y)| (hoist_invariants(x), y))
+                        .collect(),
+                )],
+                e => vec![e],
+            })
+            .collect(),
+    )
+}
+
+/// Splits the body of a loop in the instructions that can run once before it and the rest
+fn hoist(body: MirCodeBlock) -> (Vec<Mir>, MirCodeBlock) {
+    let writes = write_counts(&body);
+    let mut hoisted = Vec::new();
+    let mut rest: Vec<Mir> = Vec::new();
+    for mir in body.0 {
+        let (var, source) = match &mir {
+            Mir::Set(a, _) => (*a, None),
+            Mir::Copy(a, b) if a != b => (*a, Some(*b)),
+            _ => {
+                rest.push(mir);
+                continue;
+            }
+        };
+        // The variable is only written here and isn't used before in the iteration, the
+        // source of the copy is never written and nothing before can leave the iteration
+        let invariant = writes[&var] == 1
+            && !source.is_some_and(|x| writes.contains_key(&x))
+            && !rest.iter().any(|x| {
+                x.get_acesses().contains(&var) || x.breaks() || x.continues() || x.skips()
+            });
+        if invariant {
+            hoisted.push(mir);
+        } else {
+            rest.push(mir);
+        }
+    }
+    (hoisted, MirCodeBlock(rest))
+}
+
+/// How many instructions write each variable
+fn write_counts(code: &MirCodeBlock) -> HashMap<u32, usize> {
+    fn inner(code: &MirCodeBlock, counts: &mut HashMap<u32, usize>) {
+        for mir in code.iter() {
+            match mir {
+                Mir::If0(_, a, b) => {
+                    inner(a, counts);
+                    inner(b, counts);
+                }
+                Mir::Loop(a) | Mir::Block(a) => inner(a, counts),
+                Mir::Match(_, a) => a.iter().for_each(|(x, _)| inner(x, counts)),
+                Mir::Call(_) => {
+                    for a in MirCodeBlock(vec![mir.clone()]).get_writes() {
+                        *counts.entry(a).or_default() += 1;
+                    }
+                }
+                e => {
+                    if let Some(a) = written(e) {
+                        *counts.entry(a).or_default() += 1;
+                    }
+                }
+            }
+        }
+    }
+    let mut counts = HashMap::new();
+    inner(code, &mut counts);
+    counts
+}
+
+#[test]
+fn test_unroll_known_trip_count() {
+    use either::Either;
+    // v1 = 3; loop { if v1 { break }; 0 <@ 1; v1-- }
+    let code = MirCodeBlock(vec![
+        Mir::Set(1, 3),
+        Mir::Loop(MirCodeBlock(vec![
+            Mir::If0(1, Mir::Break.into(), MirCodeBlock(vec![])),
+            Mir::WriteRegister(0, Either::Left(1)),
+            Mir::Decrement(1),
+        ])),
+    ]);
+    let code = unroll_loops(code);
+    assert!(!code.iter().any(|x| matches!(x, Mir::Loop(_))));
+    let writes = code
+        .iter()
+        .filter(|x| matches!(x, Mir::WriteRegister(..)))
+        .count();
+    assert_eq!(writes, 3);
+}
+
+#[test]
+fn test_unknown_trip_count_is_kept() {
+    let code = MirCodeBlock(vec![
+        Mir::ReadRegister(1, 1),
+        Mir::Loop(MirCodeBlock(vec![
+            Mir::If0(1, Mir::Break.into(), MirCodeBlock(vec![])),
+            Mir::Decrement(1),
+        ])),
+    ]);
+    assert!(unroll_loops(code)
+        .iter()
+        .any(|x| matches!(x, Mir::Loop(_))));
+}
+
+#[test]
+fn test_hoist_invariants() {
+    // loop { v2 = 4; v3 = v1; if v4 { break }; v4--; v5 = v4 }
+    let code = MirCodeBlock(vec![Mir::Loop(MirCodeBlock(vec![
+        Mir::Set(2, 4),
+        Mir::Copy(3, 1),
+        Mir::If0(4, Mir::Break.into(), MirCodeBlock(vec![])),
+        Mir::Decrement(4),
+        Mir::Copy(5, 4),
+    ]))]);
+    let code = hoist_invariants(code);
+    assert_eq!(code[0], Mir::Set(2, 4));
+    assert_eq!(code[1], Mir::Copy(3, 1));
+    match &code[2] {
+        Mir::Loop(a) => assert_eq!(a.len(), 3),
+        _ => panic!(),
+    }
+}
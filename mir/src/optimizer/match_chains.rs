@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use crate::{Mir, MirCodeBlock};
+
+use super::cfg::written;
+
+/// The arms of a chain of conditions on one variable, and the code run for the other values
+struct Chain {
+    arms: Vec<(MirCodeBlock, u8)>,
+    default: Vec<Mir>,
+}
+
+fn falls_through(code: &MirCodeBlock) -> bool {
+    !matches!(
+        code.last(),
+        Some(Mir::Skip | Mir::Break | Mir::Continue | Mir::Stop)
+    )
+}
+
+/// Reads the conditions of `code` that compare `counter` with the values after it was
+/// decremented. `scrutinee` holds the value `counter` had before the chain.
+fn parse_chain(mut code: Vec<Mir>, scrutinee: u32, counter: u32) -> Chain {
+    let mut arms: Vec<(MirCodeBlock, u8)> = Vec::new();
+    let mut offset = 0;
+    loop {
+        let decrements = code
+            .iter()
+            .take_while(|x| **x == Mir::Decrement(counter))
+            .count();
+        let value = offset + decrements;
+        let test = match code.get(decrements) {
+            // The first condition can be on the scrutinee itself
+            Some(Mir::If0(a, _, _)) if *a == counter || (value == 0 && *a == scrutinee) => {
+                decrements
+            }
+            _ => break,
+        };
+        if value >= 16 {
+            break;
+        }
+        let rest = code.split_off(test + 1);
+        let (var, zero, other) = match code.pop() {
+            Some(Mir::If0(a, b, c)) => (a, b, c),
+            _ => unreachable!(),
+        };
+        // Every arm has to end where the chain ends
+        let next = if !falls_through(&zero) && other.is_empty() {
+            rest
+        } else if rest.is_empty() {
+            other.0
+        } else {
+            code.push(Mir::If0(var, zero, other));
+            code.extend(rest);
+            break;
+        };
+        let mut arm = Vec::new();
+        // The counter was 0 in the branch
+        if value != 0 {
+            arm.push(Mir::Set(counter, 0));
+        }
+        arm.extend(zero.0);
+        arms.push((MirCodeBlock(arm), value as u8));
+        offset = value;
+        code = next;
+    }
+    let mut default = vec![Mir::Decrement(counter); offset];
+    default.extend(code);
+    Chain { arms, default }
+}
+
+/// The variable compared by the chain of conditions `code` starts with and the variable that
+/// is decremented between the conditions
+fn find_chain(code: &[Mir], copies: &HashMap<u32, u32>) -> Option<(u32, u32)> {
+    match code {
+        [Mir::If0(a, b, c), rest @ ..] => {
+            let next = if !c.is_empty() || falls_through(b) {
+                c.first()
+            } else {
+                rest.first()
+            };
+            match next {
+                Some(Mir::Decrement(e)) if e == a || copies.get(e) == Some(a) => Some((*a, *e)),
+                _ => None,
+            }
+        }
+        [Mir::Decrement(a), ..] => Some((*a, *a)),
+        _ => None,
+    }
+}
+
+fn to_match(scrutinee: u32, chain: Chain) -> Mir {
+    let used: Vec<u8> = chain.arms.iter().map(|x| x.1).collect();
+    let others: Vec<u8> = (0..16).filter(|x| !used.contains(x)).collect();
+    let mut arms: Vec<(MirCodeBlock, Vec<u8>)> = chain
+        .arms
+        .into_iter()
+        .map(|(code, value)| (chains_to_match(code), vec![value]))
+        .collect();
+    if !others.is_empty() {
+        arms.push((chains_to_match(MirCodeBlock(chain.default)), others));
+    }
+    Mir::Match(scrutinee, arms)
+}
+
+/// Replaces the chains of `If0` that test a variable against successive values, after
+/// decrementing it or a copy of it, by a `Match` on the variable
+pub fn chains_to_match(code: MirCodeBlock) -> MirCodeBlock {
+    MirCodeBlock(replace_chains(code.0, HashMap::new()))
+}
+
+/// `copies` contains the variables that hold the same value as another one
+fn replace_chains(code: Vec<Mir>, mut copies: HashMap<u32, u32>) -> Vec<Mir> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < code.len() {
+        if let Some((scrutinee, counter)) = find_chain(&code[i..], &copies) {
+            // The chain can contain all the code after it or only the condition
+            let chain = parse_chain(code[i..].to_vec(), scrutinee, counter);
+            if chain.arms.len() >= 2 {
+                out.push(to_match(scrutinee, chain));
+                return out;
+            }
+            let chain = parse_chain(vec![code[i].clone()], scrutinee, counter);
+            if chain.arms.len() >= 2 {
+                out.push(to_match(scrutinee, chain));
+                i += 1;
+                continue;
+            }
+        }
+        let mir = match code[i].clone() {
+            Mir::If0(a, b, c) => Mir::If0(
+                a,
+                MirCodeBlock(replace_chains(b.0, copies.clone())),
+                MirCodeBlock(replace_chains(c.0, copies.clone())),
+            ),
+            Mir::Block(a) => Mir::Block(MirCodeBlock(replace_chains(a.0, copies.clone()))),
+            Mir::Loop(a) => {
+                // The copies have to hold at the start of every iteration
+                let writes = a.get_writes();
+                let mut inner = copies.clone();
+                inner.retain(|x, y| !writes.contains(x) && !writes.contains(y));
+                Mir::Loop(MirCodeBlock(replace_chains(a.0, inner)))
+            }
+            Mir::Match(a, b) => Mir::Match(
+                a,
+                b.into_iter()
+                    .map(|(x, y)| (MirCodeBlock(replace_chains(x.0, copies.clone())), y))
+                    .collect(),
+            ),
+            e => e,
+        };
+        let writes = match &mir {
+            Mir::If0(..) | Mir::Block(_) | Mir::Loop(_) | Mir::Match(..) | Mir::Call(_) => {
+                MirCodeBlock(vec![mir.clone()]).get_writes()
+            }
+            e => written(e).into_iter().collect(),
+        };
+        copies.retain(|x, y| !writes.contains(x) && !writes.contains(y));
+        if let Mir::Copy(a, b) = mir {
+            if a != b {
+                copies.insert(a, b);
+            }
+        }
+        out.push(mir);
+        i += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+fn assert_same_memory(code: &MirCodeBlock, optimized: &MirCodeBlock) {
+    for value in 0..16 {
+        let run = |code: &MirCodeBlock| {
+            let mut code = code.clone();
+            code.0.insert(0, Mir::Set(1, value));
+            let mut state = crate::MemoryState::new(16, 8);
+            state.execute_block(&code, &mut crate::StdIoContext);
+            state.memory
+        };
+        assert_eq!(run(code), run(optimized), "with v1 = {}", value);
+    }
+}
+
+#[test]
+fn test_nested_chain_to_match() {
+    // v2 = v1; if v1 { v3 = 1 } else { v2--; if v2 { v3 = 2 } else { v2--; if v2 { v3 = 3 } } }
+    let code = MirCodeBlock(vec![
+        Mir::Copy(2, 1),
+        Mir::If0(
+            1,
+            Mir::Set(3, 1).into(),
+            MirCodeBlock(vec![
+                Mir::Decrement(2),
+                Mir::If0(
+                    2,
+                    Mir::Set(3, 2).into(),
+                    MirCodeBlock(vec![
+                        Mir::Decrement(2),
+                        Mir::If0(2, Mir::Set(3, 3).into(), MirCodeBlock(vec![])),
+                    ]),
+                ),
+            ]),
+        ),
+    ]);
+    let optimized = chains_to_match(code.clone());
+    match &optimized[1] {
+        Mir::Match(1, arms) => assert_eq!(arms.len(), 4),
+        e => panic!("{}", e),
+    }
+    assert_same_memory(&code, &optimized);
+}
+
+#[test]
+fn test_skip_chain_to_match() {
+    // block { if v1 { v3 = 1; skip }; v1--; v1--; if v1 { v3 = 2; skip }; v3 = 5 }
+    let code = MirCodeBlock(vec![Mir::Block(MirCodeBlock(vec![
+        Mir::If0(
+            1,
+            MirCodeBlock(vec![Mir::Set(3, 1), Mir::Skip]),
+            MirCodeBlock(vec![]),
+        ),
+        Mir::Decrement(1),
+        Mir::Decrement(1),
+        Mir::If0(
+            1,
+            MirCodeBlock(vec![Mir::Set(3, 2), Mir::Skip]),
+            MirCodeBlock(vec![]),
+        ),
+        Mir::Set(3, 5),
+    ]))]);
+    let optimized = chains_to_match(code.clone());
+    match &optimized[0] {
+        Mir::Block(a) => assert!(matches!(a[0], Mir::Match(1, _))),
+        e => panic!("{}", e),
+    }
+    assert_same_memory(&code, &optimized);
+}
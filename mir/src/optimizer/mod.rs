@@ -1,7 +1,13 @@
 pub mod block_inliner;
+pub mod cfg;
+pub mod coalesce;
+pub mod cost;
+pub mod dataflow;
+pub mod fold_arith;
 pub mod get_reads;
 pub mod get_writes;
+pub mod loops;
+pub mod match_chains;
 pub mod new_opt;
-pub mod old;
-pub mod optimize;
-pub mod state;
+pub mod pass_manager;
+pub mod ssa;
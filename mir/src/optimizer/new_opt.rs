@@ -7,77 +7,63 @@ use either::Either;
 
 use crate::{Mir, MirCodeBlock};
 
-use super::old::does_break_in_all_cases;
-
-#[derive(Clone)]
-struct OptContext {
-    variables: HashMap<u32, VariableStatus>,
-}
+use super::{
+    dataflow::{does_break_in_all_cases, State, ValueSet, VarState},
+    loops::unroll_loop,
+};
 
+/// Finds the variables that are only ever set to one value. Their reads can be replaced by
+/// the value if the variable is set before being read or if the value is 0, the value every
+/// variable starts with.
 pub fn get_static_vars(cb: &MirCodeBlock) -> HashMap<u32, u8> {
-    fn inner(cb: &MirCodeBlock, vars: &mut HashMap<u32, u8>) {
+    fn inner(cb: &MirCodeBlock, vars: &mut HashMap<u32, Option<u8>>) {
         cb.iter().for_each(|x| match x {
             Mir::Set(a, b) => {
-                vars.insert(*a, *b);
+                let value = vars.entry(*a).or_insert(Some(*b));
+                if *value != Some(*b) {
+                    *value = None;
+                }
+            }
+            Mir::Copy(a, _) | Mir::Increment(a) | Mir::Decrement(a) | Mir::ReadRegister(a, _) => {
+                vars.insert(*a, None);
             }
             Mir::If0(_, b, c) => {
                 inner(b, vars);
                 inner(c, vars);
             }
-            Mir::Block(a) => {
+            Mir::Loop(a) | Mir::Block(a) => {
                 inner(a, vars);
             }
-            _ => (),
-        });
-    }
-    fn remove_inner(cb: &MirCodeBlock, vars: &mut HashMap<u32, u8>, in_rm: bool) {
-        cb.iter().for_each(|x| match x {
-            Mir::Set(a, b) => {
-                if in_rm {
-                    if vars.get(a) != Some(b) {
-                        vars.remove(a);
-                    }
-                }
-            }
-            Mir::Copy(a, _) => {
-                vars.remove(a);
-            }
-            Mir::Increment(a) => {
-                vars.remove(a);
-            }
-            Mir::Decrement(a) => {
-                vars.remove(a);
+            Mir::Match(_, b) => {
+                b.iter().for_each(|(a, _)| inner(a, vars));
             }
-            Mir::If0(_, b, c) => {
-                remove_inner(b, vars, in_rm);
-                remove_inner(c, vars, in_rm);
-            }
-            Mir::Loop(a) => {
-                remove_inner(a, vars, true);
-            }
-            Mir::Break => (),
-            Mir::Continue => (),
-            Mir::Stop => (),
-            Mir::ReadRegister(a, _) => {
-                vars.remove(a);
-            }
-            Mir::WriteRegister(_, _) => (),
-            Mir::Skip => (),
-            Mir::Block(a) => {
-                remove_inner(a, vars, in_rm);
-            }
-            Mir::Match(a, b) => {
-                vars.remove(a);
-                b.iter().for_each(|(a, _)| {
-                    remove_inner(a, vars, in_rm);
-                });
+            Mir::Call(_) => {
+                for a in MirCodeBlock(vec![x.clone()]).get_writes() {
+                    vars.insert(a, None);
+                }
             }
+            Mir::Break
+            | Mir::Continue
+            | Mir::Stop
+            | Mir::WriteRegister(_, _)
+            | Mir::Skip
+            | Mir::Location(..)
+            | Mir::Debug(_) => (),
         });
     }
     let mut vars = HashMap::new();
     inner(cb, &mut vars);
-    remove_inner(cb, &mut vars, false);
-    vars
+    vars.into_iter()
+        .filter_map(|(a, b)| Some((a, b?)))
+        .filter(|(a, b)| *b == 0 || is_set_first(cb, *a))
+        .collect()
+}
+
+/// Whether the first instruction of the block that accesses the variable sets it
+fn is_set_first(cb: &MirCodeBlock, var: u32) -> bool {
+    cb.iter()
+        .find(|x| x.get_acesses().contains(&var))
+        .is_some_and(|x| matches!(x, Mir::Set(a, _) if *a == var))
 }
 
 fn apply_static_vars(cb: MirCodeBlock, vars: &HashMap<u32, u8>) -> MirCodeBlock {
@@ -122,10 +108,37 @@ fn apply_static_vars(cb: MirCodeBlock, vars: &HashMap<u32, u8>) -> MirCodeBlock
                         Either::Left(b) => Mir::WriteRegister(a, Either::Left(b)),
                     },
                     Mir::Skip => Mir::Skip,
+                    Mir::Call(a) => Mir::Call(a),
+                    Mir::Location(a, b) => Mir::Location(a, b),
+                    Mir::Debug(a) => Mir::Debug(a),
                     Mir::Block(a) => Mir::Block(apply_static_vars(a, &vars)),
                     Mir::Match(a, b) => {
-                        // TODO
-                        Mir::Match(a, b)
+                        if let Some(e) = vars.get(&a) {
+                            // The values without arm continue after the match
+                            return b
+                                .into_iter()
+                                .find(|(_, x)| x.contains(e))
+                                .map(|(x, _)| apply_static_vars(x, vars).0)
+                                .unwrap_or_default();
+                        }
+                        Mir::Match(
+                            a,
+                            b.into_iter()
+                                .map(|(x, y)| {
+                                    // An arm taken for only one value knows the value of the
+                                    // variable until it is written
+                                    let code = match y.as_slice() {
+                                        [e] if !x.get_writes().contains(&a) => {
+                                            let mut vars = vars.clone();
+                                            vars.insert(a, *e);
+                                            apply_static_vars(x, &vars)
+                                        }
+                                        _ => apply_static_vars(x, vars),
+                                    };
+                                    (code, y)
+                                })
+                                .collect(),
+                        )
                     }
                 }]
             })
@@ -133,123 +146,34 @@ fn apply_static_vars(cb: MirCodeBlock, vars: &HashMap<u32, u8>) -> MirCodeBlock
     )
 }
 
-impl OptContext {
-    fn new() -> Self {
-        OptContext {
-            variables: HashMap::new(),
-        }
-    }
-
-    fn remove(&mut self, id: u32) {
-        let k: Vec<u32> = self
-            .variables
-            .iter()
-            .filter(|(_, v)| match v {
-                VariableStatus::Ref(a) => *a == id,
-                _ => false,
-            })
-            .map(|(a, _)| *a)
-            .collect();
-        let j = self.variables.remove(&id);
-        k.iter().for_each(|x| {
-            self.set_or_remove(*x, j);
-        });
-    }
-
-    fn set_or_remove(&mut self, id: u32, status: Option<VariableStatus>) {
-        if let Some(e) = status {
-            self.set(id, e);
-        } else {
-            self.remove(id);
-        }
-    }
-
-    fn get_raw(&mut self, id: u32) -> Option<VariableStatus> {
-        self.variables.get(&id).copied()
-    }
-
-    fn get_value(&mut self, id: u32) -> Option<u8> {
-        match self.get_raw(id) {
-            Some(VariableStatus::Ref(a)) => self.get_value(a),
-            Some(VariableStatus::Value(a)) => Some(a),
-            None => None,
-        }
-    }
-
-    fn get_flatten(&mut self, id: u32) -> Option<VariableStatus> {
-        match self.get_raw(id) {
-            Some(VariableStatus::Ref(a)) => Some(match self.get_flatten(a) {
-                Some(a) => a,
-                None => VariableStatus::Ref(a),
-            }),
-            e => e,
-        }
-    }
-
-    fn set(&mut self, id: u32, status: VariableStatus) {
-        let k: Vec<u32> = self
-            .variables
-            .iter()
-            .filter(|(_, v)| match v {
-                VariableStatus::Ref(a) => *a == id,
-                _ => false,
-            })
-            .map(|(a, _)| *a)
-            .collect();
-        let j = self.variables.remove(&id);
-        k.iter().for_each(|x| {
-            self.set_or_remove(*x, j);
-        });
-        self.variables.insert(id, status);
-    }
-
-    fn merge(&mut self, other: &mut Self) -> Self {
-        let mut variables = HashMap::new();
-        for a in other.variables.keys().copied().collect::<Vec<_>>() {
-            if let Some(aa) = other.get_flatten(a) {
-                if let Some(ab) = self.get_flatten(a) {
-                    if aa == ab {
-                        variables.insert(a, aa);
-                    }
-                }
-            }
-        }
-        OptContext { variables }
-    }
+/// Replaces the reads of the variables whose value is known by the value
+pub fn propagate(mir: MirCodeBlock) -> MirCodeBlock {
+    MirCodeBlock(optimize_block(mir, &mut State::default(), false))
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum VariableStatus {
-    Value(u8),
-    Ref(u32),
+pub fn static_vars(mir: MirCodeBlock) -> MirCodeBlock {
+    let statics = get_static_vars(&mir);
+    apply_static_vars(mir, &statics)
 }
 
-pub fn optimize_code(mir: MirCodeBlock, current_count: usize) -> MirCodeBlock {
-    let mut context = OptContext::new();
-    let k = mir;
-    let k = MirCodeBlock(optimize_block(k, &mut context));
-    let statics = get_static_vars(&k);
-    let k = apply_static_vars(k, &statics);
-    let j = k.get_reads();
-    let k = remove_unread(k, &j);
-    let k = set_in_if(k);
-    let k = opt_lower_interupts_calls(k);
-    let k = opt_not_read(k);
-    let k = unwrap_if(k);
-    let ic = k.instr_count();
-    if ic == current_count {
-        k
-    } else {
-        optimize_code(k, ic)
-    }
+/// Removes the writes to variables that are never read
+pub fn remove_unread_vars(mir: MirCodeBlock) -> MirCodeBlock {
+    let reads = mir.get_reads();
+    remove_unread(mir, &reads)
 }
 
-fn optimize_block(mir: MirCodeBlock, context: &mut OptContext) -> Vec<Mir> {
-    mir.0
-        .into_iter()
-        .map(|x| optimize(x, context))
-        .flatten()
-        .collect()
+/// Propagates the known values through the code, the loops with a known trip count are
+/// unrolled if `unroll` is set
+pub(crate) fn optimize_block(mir: MirCodeBlock, context: &mut State, unroll: bool) -> Vec<Mir> {
+    let mut out = Vec::new();
+    for i in mir {
+        // The rest of the block is never executed
+        if !context.is_reachable() {
+            break;
+        }
+        out.extend(optimize(i, context, unroll));
+    }
+    out
 }
 /*
 if v89 {
@@ -374,10 +298,26 @@ pub fn opt_not_read(mut code: MirCodeBlock) -> MirCodeBlock {
                 vec![Mir::Block(a)]
             }
             Mir::Match(a, b) => {
-                wrote.clear();
+                // The variables overwritten after the match still are if no arm reads them or
+                // leaves before the end of the match
+                if b.iter().any(|(x, _)| x.iter().any(|x| x.breaks() || x.continues() || x.skips())) {
+                    wrote.clear();
+                } else {
+                    for (x, _) in &b {
+                        let reads = x.get_reads();
+                        wrote.retain(|x| !reads.contains(x));
+                    }
+                    wrote.remove(&a);
+                }
                 let b: Vec<_> = b.into_iter().map(|(a, b)| (opt_not_read(a), b)).collect();
                 vec![Mir::Match(a, b)]
             }
+            Mir::Call(a) => {
+                wrote.clear();
+                vec![Mir::Call(a)]
+            }
+            Mir::Location(a, b) => vec![Mir::Location(a, b)],
+            Mir::Debug(a) => vec![Mir::Debug(a)],
         })
         .collect::<Vec<_>>();
     out.reverse();
@@ -390,7 +330,15 @@ pub fn opt_lower_interupts_calls(code: MirCodeBlock) -> MirCodeBlock {
 }
 fn lower_interupts_calls(code: MirCodeBlock, mut to_lower: Vec<Mir>) -> (MirCodeBlock, Vec<Mir>) {
     let mut out = Vec::new();
-    for i in code.into_iter() {
+    let mut code = code.into_iter();
+    while let Some(i) = code.next() {
+        // The code after a jump is never executed so it must not be lowered after the block
+        if matches!(i, Mir::Break | Mir::Continue | Mir::Stop | Mir::Skip) {
+            out.extend(to_lower);
+            out.push(i);
+            out.extend(code);
+            return (MirCodeBlock(out), Vec::new());
+        }
         let iboth = i.get_acesses();
         if to_lower.iter().any(|x| {
             let both = x.get_acesses();
@@ -425,6 +373,11 @@ fn lower_interupts_calls(code: MirCodeBlock, mut to_lower: Vec<Mir>) -> (MirCode
                     c.0.extend(to_lower2.into_iter());
                     out.push(Mir::If0(a, b, c));
                 }
+                // The procedure can use the registers
+                Mir::Call(a) => {
+                    out.append(&mut to_lower);
+                    out.push(Mir::Call(a));
+                }
                 Mir::Loop(a) => {
                     out.extend(to_lower.into_iter());
                     to_lower = Vec::new();
@@ -432,39 +385,52 @@ fn lower_interupts_calls(code: MirCodeBlock, mut to_lower: Vec<Mir>) -> (MirCode
                     a.extend(to_lower1.into_iter());
                     out.push(Mir::Loop(a));
                 }
-                Mir::Break => {
-                    out.extend(to_lower.into_iter());
-                    to_lower = Vec::new();
-                    out.push(Mir::Break);
-                }
-                Mir::Continue => {
-                    out.extend(to_lower.into_iter());
-                    to_lower = Vec::new();
-                    out.push(Mir::Continue);
-                }
-                Mir::Stop => {
-                    out.extend(to_lower.into_iter());
-                    to_lower = Vec::new();
-                    out.push(Mir::Stop);
-                }
-                Mir::Skip => {
-                    out.extend(to_lower.into_iter());
-                    to_lower = Vec::new();
-                    out.push(Mir::Skip);
-                }
                 Mir::Block(a) => {
-                    let (a, to_lower1) = lower_interupts_calls(a, to_lower.clone());
-                    to_lower = to_lower1;
+                    let skips = a.iter().any(Mir::skips);
+                    let (mut a, to_lower1) = lower_interupts_calls(a, to_lower.clone());
+                    // A skip jumps over the rest of the block, the calls can't be lowered after it
+                    if skips {
+                        a.extend(to_lower1);
+                        to_lower = Vec::new();
+                    } else {
+                        to_lower = to_lower1;
+                    }
                     out.push(Mir::Block(a));
                 }
                 Mir::Match(a, b) => {
-                    out.extend(to_lower.into_iter());
-                    to_lower = Vec::new();
+                    let exhaustive = (0..16).all(|x| b.iter().any(|(_, y)| y.contains(&x)));
+                    // The values without arm don't run the calls given to the arms
+                    if !exhaustive {
+                        out.extend(to_lower);
+                        to_lower = Vec::new();
+                    }
+                    let mut arms: Vec<_> = b
+                        .into_iter()
+                        .map(|(a, b)| {
+                            let (a, to_lower1) = lower_interupts_calls(a, to_lower.clone());
+                            (a, to_lower1, b)
+                        })
+                        .collect();
+                    // The calls left at the end of every arm are lowered after the match
+                    let mut common = VecDeque::new();
+                    while let Some(k) = arms
+                        .first()
+                        .filter(|_| exhaustive)
+                        .and_then(|x| x.1.last().cloned())
+                    {
+                        if !arms.iter().all(|x| x.1.last() == Some(&k)) {
+                            break;
+                        }
+                        arms.iter_mut().for_each(|x| {
+                            x.1.pop();
+                        });
+                        common.push_front(k);
+                    }
+                    to_lower = common.into_iter().collect();
                     out.push(Mir::Match(
                         a,
-                        b.into_iter()
-                            .map(|(a, b)| {
-                                let (mut a, to_lower1) = lower_interupts_calls(a, Vec::new());
+                        arms.into_iter()
+                            .map(|(mut a, to_lower1, b)| {
                                 a.extend(to_lower1.into_iter());
                                 (a, b)
                             })
@@ -489,11 +455,102 @@ fn test_set_in_if() {
         ),
         Mir::Copy(81, 77),
         Mir::Copy(82, 78),
+        Mir::WriteRegister(1, Either::Right(77)),
+    ]);
+    // The copies are merged in the branches, which still set the variables read later
+    assert_eq!(
+        set_in_if(code).0,
+        [
+            Mir::If0(
+                68,
+                MirCodeBlock(vec![
+                    Mir::Set(77, 15),
+                    Mir::Set(78, 4),
+                    Mir::Set(81, 15),
+                    Mir::Set(82, 4),
+                ]),
+                MirCodeBlock(vec![
+                    Mir::Set(77, 8),
+                    Mir::Set(78, 5),
+                    Mir::Set(81, 8),
+                    Mir::Set(82, 5),
+                ]),
+            ),
+            Mir::WriteRegister(1, Either::Right(77)),
+        ]
+    );
+    // v78 is read by the branch so the copy can't be moved before it
+    let code = MirCodeBlock(vec![
+        Mir::If0(68, Mir::Copy(77, 78).into(), Mir::Set(77, 2).into()),
+        Mir::Copy(78, 77),
+        Mir::WriteRegister(1, Either::Right(78)),
+    ]);
+    assert_eq!(set_in_if(code.clone()), code);
+}
+#[test]
+fn test_propagate_value_sets() {
+    let code = MirCodeBlock(vec![
+        Mir::ReadRegister(1, 1),
+        Mir::If0(
+            1,
+            MirCodeBlock(vec![Mir::Set(2, 1)]),
+            MirCodeBlock(vec![Mir::Set(2, 2)]),
+        ),
+        // v2 is either 1 or 2 so it can't be 0
+        Mir::If0(2, Mir::Stop.into(), Mir::WriteRegister(0, Either::Left(1)).into()),
+        Mir::Match(
+            2,
+            vec![
+                (Mir::Set(3, 4).into(), vec![0, 1]),
+                (Mir::Set(3, 5).into(), vec![2]),
+                (Mir::Set(3, 6).into(), vec![3]),
+            ],
+        ),
     ]);
-    let code = set_in_if(code);
-    println!("{:?}", code);
+    assert_eq!(
+        propagate(code).0[2..],
+        [
+            Mir::WriteRegister(0, Either::Left(1)),
+            Mir::Match(
+                2,
+                vec![
+                    (Mir::Set(3, 4).into(), vec![0, 1]),
+                    (Mir::Set(3, 5).into(), vec![2]),
+                ],
+            ),
+        ]
+    );
 }
-fn set_in_if(block: MirCodeBlock) -> MirCodeBlock {
+#[test]
+fn test_propagate_partial_match() {
+    let code = MirCodeBlock(vec![
+        Mir::Set(1, 5),
+        Mir::ReadRegister(2, 1),
+        Mir::Match(2, vec![(Mir::Set(1, 6).into(), vec![0])]),
+        // v1 is still 5 when v2 isn't 0
+        Mir::WriteRegister(0, Either::Right(1)),
+    ]);
+    assert_eq!(propagate(code.clone()), code);
+}
+#[test]
+fn test_propagate_copies_to_self() {
+    let code = MirCodeBlock(vec![
+        Mir::ReadRegister(1, 1),
+        Mir::Copy(1, 1),
+        Mir::Copy(2, 1),
+        Mir::Copy(1, 2),
+        Mir::WriteRegister(0, Either::Right(1)),
+    ]);
+    assert_eq!(
+        propagate(code).0,
+        [
+            Mir::ReadRegister(1, 1),
+            Mir::Copy(2, 1),
+            Mir::WriteRegister(0, Either::Right(1)),
+        ]
+    );
+}
+pub fn set_in_if(block: MirCodeBlock) -> MirCodeBlock {
     let mut out = Vec::new();
     let mut vecqueue = VecDeque::from(block.0);
 
@@ -620,33 +677,34 @@ fn set_in_if(block: MirCodeBlock) -> MirCodeBlock {
                         continue 'a;
                     }
                 }
-                if map2.is_empty() && map3.is_empty() {
-                    out.push(Mir::If0(
-                        *a,
-                        MirCodeBlock({
-                            let mut out = Vec::new();
-                            for (a, b) in retreived1 {
-                                match b {
-                                    Either::Left(b) => out.push(Mir::Set(a, b)),
-                                    Either::Right(b) => out.push(Mir::Copy(a, b)),
-                                }
-                            }
-                            out
-                        }),
-                        MirCodeBlock({
-                            let mut out = Vec::new();
-                            for (a, b) in retreived2 {
-                                match b {
-                                    Either::Left(b) => out.push(Mir::Set(a, b)),
-                                    Either::Right(b) => out.push(Mir::Copy(a, b)),
-                                }
+                // The variables set in the branches are still set since they could be read
+                // later, the copies must not change the values the branches read.
+                let reads: HashSet<u32> = b.get_reads().union(&c.get_reads()).copied().collect();
+                if map2.is_empty()
+                    && map3.is_empty()
+                    && retreived1
+                        .keys()
+                        .all(|x| !items.contains(x) && !reads.contains(x))
+                {
+                    let branch = |code: &MirCodeBlock, retreived: HashMap<u32, Either<u8, u32>>| {
+                        let mut retreived: Vec<_> = retreived.into_iter().collect();
+                        retreived.sort_by_key(|(a, _)| *a);
+                        let mut out = code.0.clone();
+                        for (a, b) in retreived {
+                            match b {
+                                Either::Left(b) => out.push(Mir::Set(a, b)),
+                                Either::Right(b) => out.push(Mir::Copy(a, b)),
                             }
-                            out
-                        }),
-                    ));
+                        }
+                        MirCodeBlock(out)
+                    };
+                    out.push(Mir::If0(*a, branch(b, retreived1), branch(c, retreived2)));
                     continue 'a;
                 } else {
                     out.push(item);
+                    for i in list.into_iter().rev() {
+                        vecqueue.push_front(i);
+                    }
                     continue 'a;
                 }
             }
@@ -656,130 +714,123 @@ fn set_in_if(block: MirCodeBlock) -> MirCodeBlock {
     MirCodeBlock(out)
 }
 
-fn optimize(mir: Mir, context: &mut OptContext) -> Vec<Mir> {
+/// Optimizes the instruction knowing the state of the variables before it and updates the
+/// state to the one after it
+fn optimize(mir: Mir, context: &mut State, unroll: bool) -> Vec<Mir> {
     match mir {
         Mir::Set(a, b) => {
             if context.get_value(a) == Some(b) {
                 return vec![];
             }
-            context.set(a, VariableStatus::Value(b));
+            context.set_values(a, ValueSet::single(b));
             vec![Mir::Set(a, b)]
         }
         Mir::Copy(a, b) => {
-            let flt = context.get_flatten(b);
-            if let Some(c) = flt {
-                context.set(a, c);
-                return vec![match c {
-                    VariableStatus::Value(c) => Mir::Set(a, c),
-                    VariableStatus::Ref(c) => Mir::Copy(a, c),
-                }];
-            } else {
-                context.set(a, VariableStatus::Ref(b));
+            let root = context.root(b);
+            // The variable already contains the value
+            if a == b || context.root(a) == root {
+                return vec![];
             }
-            vec![Mir::Copy(a, b)]
+            if let Some(e) = context.get_value(root) {
+                if context.get_value(a) == Some(e) {
+                    return vec![];
+                }
+                context.set_values(a, ValueSet::single(e));
+                return vec![Mir::Set(a, e)];
+            }
+            context.set(a, VarState::Ref(root));
+            vec![Mir::Copy(a, root)]
         }
         Mir::Increment(a) => {
-            if let Some(e) = context.get_value(a) {
-                context.set(a, VariableStatus::Value((e + 1) % 16));
-            } else {
-                context.remove(a);
-            }
+            let values = context.values(a).increment();
+            context.set_values(a, values);
             vec![Mir::Increment(a)]
         }
         Mir::Decrement(a) => {
-            if let Some(e) = context.get_value(a) {
-                context.set(a, VariableStatus::Value(e.wrapping_sub(1) % 16));
-            } else {
-                context.remove(a);
-            }
-
+            let values = context.values(a).decrement();
+            context.set_values(a, values);
             vec![Mir::Decrement(a)]
         }
-        Mir::If0(a, b, c) => match context.get_flatten(a) {
-            Some(VariableStatus::Ref(e)) => {
-                let mut cb = context.clone();
-                let mut cc = context.clone();
-                let b = MirCodeBlock(optimize_block(b, &mut cb));
-                let c = MirCodeBlock(optimize_block(c, &mut cc));
-                *context = cb.merge(&mut cc);
-                vec![Mir::If0(e, b, c)]
-            }
-            Some(VariableStatus::Value(e)) => {
-                if e == 0 {
-                    optimize_block(b, context)
-                } else {
-                    optimize_block(c, context)
-                }
+        Mir::If0(a, b, c) => {
+            let values = context.values(a);
+            if values == ValueSet::single(0) {
+                return optimize_block(b, context, unroll);
             }
-            None => {
-                let mut cb = context.clone();
-                let mut cc = context.clone();
-                let b = MirCodeBlock(optimize_block(b, &mut cb));
-                let c = MirCodeBlock(optimize_block(c, &mut cc));
-                *context = cb.merge(&mut cc);
-                vec![Mir::If0(a, b, c)]
-            }
-        },
-        Mir::Match(a, b) => match context.get_flatten(a) {
-            Some(VariableStatus::Ref(e)) => {
-                let mut cur_state: Option<OptContext> = None;
-                let m: Vec<_> = b
-                    .into_iter()
-                    .map(|(x, y)| {
-                        let mut cb = context.clone();
-                        let x = MirCodeBlock(optimize_block(x, &mut cb));
-                        cur_state = match &mut cur_state {
-                            Some(a) => Some(a.merge(&mut cb)),
-                            None => Some(cb),
-                        };
-                        (x, y)
-                    })
-                    .collect();
-
-                *context = cur_state.unwrap();
-                vec![Mir::Match(e, m)]
+            if !values.contains(0) {
+                return optimize_block(c, context, unroll);
             }
-            Some(VariableStatus::Value(e)) => {
-                for j in b {
-                    if j.1.contains(&e) {
-                        return optimize_block(j.0, context);
-                    }
-                }
+            let a = context.root(a);
+            let mut cb = context.clone();
+            let mut cc = context.clone();
+            cb.narrow(a, ValueSet::single(0));
+            cc.narrow(a, ValueSet::ALL.without(ValueSet::single(0)));
+            let b = MirCodeBlock(optimize_block(b, &mut cb, unroll));
+            let c = MirCodeBlock(optimize_block(c, &mut cc, unroll));
+            *context = cb.merge(&cc);
+            vec![Mir::If0(a, b, c)]
+        }
+        Mir::Match(a, arms) => {
+            let values = context.values(a);
+            let a = context.root(a);
+            let covered = arms
+                .iter()
+                .fold(ValueSet::EMPTY, |x, (_, y)| x.union(ValueSet::from_values(y)));
+            // The values without arm continue after the match
+            let mut after = context.clone();
+            after.narrow(a, ValueSet::ALL.without(covered));
+            let arms: Vec<_> = arms
+                .into_iter()
+                .filter(|(_, x)| !ValueSet::from_values(x).intersection(values).is_empty())
+                .collect();
+            if arms.is_empty() {
                 return vec![];
             }
-            None => {
-                let mut cur_state: Option<OptContext> = None;
-                let m: Vec<_> = b
-                    .into_iter()
-                    .map(|(x, y)| {
-                        let mut cb = context.clone();
-                        let x = MirCodeBlock(optimize_block(x, &mut cb));
-                        cur_state = match &mut cur_state {
-                            Some(a) => Some(a.merge(&mut cb)),
-                            None => Some(cb),
-                        };
-                        (x, y)
-                    })
-                    .collect();
-
-                *context = cur_state.unwrap();
-                vec![Mir::Match(a, m)]
-            }
-        },
+            // The only arm that can run always runs
+            if arms.len() == 1 && !after.is_reachable() {
+                let (code, x) = arms.into_iter().next().unwrap();
+                context.narrow(a, ValueSet::from_values(&x));
+                return optimize_block(code, context, unroll);
+            }
+            let arms = arms
+                .into_iter()
+                .map(|(code, x)| {
+                    let mut cb = context.clone();
+                    cb.narrow(a, ValueSet::from_values(&x));
+                    let code = MirCodeBlock(optimize_block(code, &mut cb, unroll));
+                    after = after.merge(&cb);
+                    (code, x)
+                })
+                .collect();
+            *context = after;
+            vec![Mir::Match(a, arms)]
+        }
         Mir::Loop(a) => {
+            if unroll {
+                if let Some(e) = unroll_loop(&a, context) {
+                    return e;
+                }
+            }
             for w in a.get_writes() {
                 context.remove(w);
             }
-            let a = MirCodeBlock(optimize_block(a, &mut context.clone()));
+            let breaks = a.iter().any(Mir::breaks);
+            let a = MirCodeBlock(optimize_block(a, &mut context.clone(), unroll));
+            // A loop without break can only be left by stopping the program
+            if !breaks {
+                *context = State::unreachable();
+            }
             vec![Mir::Loop(a)]
         }
-        Mir::Break => vec![Mir::Break],
-        Mir::Continue => vec![Mir::Continue],
-        Mir::Stop => vec![Mir::Stop],
+        Mir::Break | Mir::Continue | Mir::Stop | Mir::Skip => {
+            *context = State::unreachable();
+            vec![mir]
+        }
         Mir::ReadRegister(a, b) => {
             context.remove(a);
             vec![Mir::ReadRegister(a, b)]
         }
+        Mir::Location(a, b) => vec![Mir::Location(a, b)],
+        Mir::Debug(a) => vec![Mir::Debug(a)],
         Mir::WriteRegister(a, b) => match b {
             Either::Left(c) => vec![Mir::WriteRegister(a, Either::Left(c))],
             Either::Right(c) => {
@@ -790,14 +841,26 @@ fn optimize(mir: Mir, context: &mut OptContext) -> Vec<Mir> {
                 }
             }
         },
-        Mir::Skip => vec![Mir::Skip],
         Mir::Block(a) => {
+            let skips = a.iter().any(Mir::skips);
+            let writes = a.get_writes();
             let mut cb = context.clone();
-            let muts = a.get_writes();
-            let a = MirCodeBlock(optimize_block(a, &mut cb));
-            muts.iter().for_each(|x| context.remove(*x));
+            let a = MirCodeBlock(optimize_block(a, &mut cb, unroll));
+            if skips {
+                // The skips can come from any point of the block
+                writes.iter().for_each(|x| context.remove(*x));
+            } else {
+                *context = cb;
+            }
             vec![Mir::Block(a)]
         }
+        Mir::Call(a) => {
+            let call = Mir::Call(a);
+            for w in MirCodeBlock(vec![call.clone()]).get_writes() {
+                context.remove(w);
+            }
+            vec![call]
+        }
     }
 }
 
@@ -844,3 +907,82 @@ fn remove_unread(codeblock: MirCodeBlock, reads: &HashSet<u32>) -> MirCodeBlock
             .collect(),
     )
 }
+#[test]
+fn test_static_vars_in_match() {
+    let code = MirCodeBlock(vec![
+        Mir::Set(1, 2),
+        Mir::ReadRegister(2, 1),
+        Mir::Match(
+            1,
+            vec![
+                (Mir::Set(3, 4).into(), vec![0, 1]),
+                (Mir::Set(3, 5).into(), vec![2]),
+            ],
+        ),
+        Mir::Match(
+            2,
+            vec![
+                (Mir::WriteRegister(0, Either::Right(2)).into(), vec![6]),
+                (Mir::Set(3, 6).into(), vec![7, 8]),
+            ],
+        ),
+    ]);
+    assert_eq!(
+        static_vars(code).0[2..],
+        [
+            Mir::Set(3, 5),
+            Mir::Match(
+                2,
+                vec![
+                    (Mir::WriteRegister(0, Either::Left(6)).into(), vec![6]),
+                    (Mir::Set(3, 6).into(), vec![7, 8]),
+                ],
+            ),
+        ]
+    );
+}
+#[test]
+fn test_static_vars_set_in_branches() {
+    // v1 is 3 or 4 when it is printed
+    let code = MirCodeBlock(vec![
+        Mir::Set(1, 3),
+        Mir::ReadRegister(2, 1),
+        Mir::If0(2, Mir::Set(1, 4).into(), MirCodeBlock::default()),
+        Mir::WriteRegister(0, Either::Right(1)),
+        Mir::Set(3, 3),
+        Mir::Match(2, vec![(Mir::Set(3, 4).into(), vec![0])]),
+        Mir::WriteRegister(0, Either::Right(3)),
+    ]);
+    assert!(get_static_vars(&code).is_empty());
+    assert_eq!(static_vars(code.clone()), code);
+}
+#[test]
+fn test_lower_interrupts_through_match() {
+    let all: Vec<u8> = (1..16).collect();
+    let code = MirCodeBlock(vec![
+        Mir::WriteRegister(0, Either::Left(1)),
+        Mir::Match(
+            1,
+            vec![
+                (Mir::Set(2, 1).into(), vec![0]),
+                (Mir::Set(2, 2).into(), all),
+            ],
+        ),
+        Mir::WriteRegister(0, Either::Left(2)),
+    ]);
+    let code = opt_lower_interupts_calls(code);
+    assert!(matches!(code[0], Mir::Match(..)));
+    assert_eq!(code.len(), 3);
+}
+#[test]
+fn test_lower_interrupts_before_skips() {
+    let print = || Mir::WriteRegister(0, Either::Left(1));
+    // The print after the skip is never run
+    let code = MirCodeBlock(vec![Mir::Block(vec![Mir::Skip, print()].into())]);
+    assert_eq!(opt_lower_interupts_calls(code.clone()), code);
+    // The print is only run when v1 isn't 0
+    let code = MirCodeBlock(vec![Mir::Block(
+        vec![Mir::If0(1, Mir::Skip.into(), MirCodeBlock::default()), print()].into(),
+    )]);
+    assert_eq!(opt_lower_interupts_calls(code.clone()), code);
+}
@@ -0,0 +1,289 @@
+use std::{fmt::Display, time::Duration, time::Instant};
+
+use crate::MirCodeBlock;
+
+use super::{
+    cfg::dead_store_elimination,
+    fold_arith::fold_arithmetic,
+    loops::{hoist_invariants, unroll_loops},
+    match_chains::chains_to_match,
+    new_opt::{
+        opt_lower_interupts_calls, opt_not_read, propagate, remove_unread_vars, set_in_if,
+        static_vars, unwrap_if,
+    },
+    ssa::constant_propagation,
+};
+
+pub struct Pass {
+    pub name: &'static str,
+    pub description: &'static str,
+    run: fn(MirCodeBlock) -> MirCodeBlock,
+}
+
+/// Every pass in the order they run by default
+pub const PASSES: &[Pass] = &[
+    Pass {
+        name: "propagate",
+        description: "replaces the reads of variables with a known value or source",
+        run: propagate,
+    },
+    Pass {
+        name: "sccp",
+        description: "propagates the values of the variables across branches and loops",
+        run: constant_propagation,
+    },
+    Pass {
+        name: "unroll",
+        description: "unrolls the loops that run a small known number of times",
+        run: unroll_loops,
+    },
+    Pass {
+        name: "fold-arith",
+        description: "folds the increments and decrements of a variable into their net effect",
+        run: fold_arithmetic,
+    },
+    Pass {
+        name: "static-vars",
+        description: "replaces the variables that are only set to one value by this value",
+        run: static_vars,
+    },
+    Pass {
+        name: "remove-unread",
+        description: "removes the writes to variables that are never read",
+        run: remove_unread_vars,
+    },
+    Pass {
+        name: "dse",
+        description: "removes the writes that are never read on any path",
+        run: dead_store_elimination,
+    },
+    Pass {
+        name: "licm",
+        description: "moves the sets and copies that don't change between iterations out of loops",
+        run: hoist_invariants,
+    },
+    Pass {
+        name: "set-in-if",
+        description: "moves the sets done after a condition into its branches",
+        run: set_in_if,
+    },
+    Pass {
+        name: "lower-interrupts",
+        description: "delays register writes to group them",
+        run: opt_lower_interupts_calls,
+    },
+    Pass {
+        name: "dead-writes",
+        description: "removes the writes that are overwritten before being read",
+        run: opt_not_read,
+    },
+    Pass {
+        name: "unwrap-if",
+        description: "moves the branch that doesn't break out of the condition",
+        run: unwrap_if,
+    },
+    Pass {
+        name: "if-to-match",
+        description: "replaces the conditions testing a variable against successive values by a match",
+        run: chains_to_match,
+    },
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OptLevel {
+    /// No optimization
+    O0,
+    /// Every pass runs once
+    O1,
+    /// The passes run until the code stops changing
+    O2,
+}
+
+impl OptLevel {
+    pub fn parse(level: &str) -> Option<Self> {
+        match level {
+            "0" => Some(Self::O0),
+            "1" => Some(Self::O1),
+            "2" => Some(Self::O2),
+            _ => None,
+        }
+    }
+}
+
+fn find_pass(name: &str) -> Result<&'static Pass, String> {
+    PASSES.iter().find(|x| x.name == name).ok_or_else(|| {
+        format!(
+            "Unknown pass `{}`, expected one of: {}",
+            name,
+            PASSES.iter().map(|x| x.name).collect::<Vec<_>>().join(", ")
+        )
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct PassStats {
+    pub name: &'static str,
+    pub runs: usize,
+    /// Can be negative if the pass makes the code bigger
+    pub instructions_saved: isize,
+    pub steps_saved: isize,
+    /// Spent in all the runs of the pass
+    pub time: Duration,
+}
+
+impl Display for PassStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<20} {:>4} runs {:>8} instructions {:>8} steps",
+            self.name, self.runs, self.instructions_saved, self.steps_saved
+        )
+    }
+}
+
+/// The code as it was after a pass listed in `--print-after`
+pub struct PassDump {
+    pub pass: &'static str,
+    pub iteration: usize,
+    pub code: MirCodeBlock,
+}
+
+/// The maximum amount of times `O2` runs the passes, in case some of them undo each other
+const MAX_ITERATIONS: usize = 32;
+
+pub struct PassManager {
+    passes: Vec<&'static Pass>,
+    fixed_point: bool,
+    print_after: Vec<&'static str>,
+    /// Set by `--time-passes` to report how long each pass took
+    pub time_passes: bool,
+    pub stats: Vec<PassStats>,
+    pub dumps: Vec<PassDump>,
+}
+
+impl PassManager {
+    pub fn new(level: OptLevel) -> Self {
+        Self {
+            passes: if level == OptLevel::O0 {
+                Vec::new()
+            } else {
+                PASSES.iter().collect()
+            },
+            fixed_point: level == OptLevel::O2,
+            print_after: Vec::new(),
+            time_passes: false,
+            stats: Vec::new(),
+            dumps: Vec::new(),
+        }
+    }
+
+    /// Replaces the passes to run by a comma separated list of pass names
+    pub fn passes(mut self, names: &str) -> Result<Self, String> {
+        self.passes = names
+            .split(',')
+            .filter(|x| !x.is_empty())
+            .map(find_pass)
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    /// Keeps the code produced by this pass in `dumps` each time it runs
+    pub fn print_after(mut self, name: &str) -> Result<Self, String> {
+        self.print_after.push(find_pass(name)?.name);
+        Ok(self)
+    }
+
+    pub fn run(&mut self, mut code: MirCodeBlock) -> MirCodeBlock {
+        let mut iteration = 0;
+        loop {
+            let previous = code.clone();
+            for pass in &self.passes {
+                let (instructions, steps) = (code.instr_count(), code.estimated_steps());
+                let start = Instant::now();
+                code = (pass.run)(code).drop_dangling_locations();
+                let time = start.elapsed();
+                let stats = match self.stats.iter_mut().position(|x| x.name == pass.name) {
+                    Some(e) => &mut self.stats[e],
+                    None => {
+                        self.stats.push(PassStats {
+                            name: pass.name,
+                            runs: 0,
+                            instructions_saved: 0,
+                            steps_saved: 0,
+                            time: Duration::ZERO,
+                        });
+                        self.stats.last_mut().unwrap()
+                    }
+                };
+                stats.runs += 1;
+                stats.time += time;
+                stats.instructions_saved += instructions as isize - code.instr_count() as isize;
+                stats.steps_saved += steps as isize - code.estimated_steps() as isize;
+                if self.print_after.contains(&pass.name) {
+                    self.dumps.push(PassDump {
+                        pass: pass.name,
+                        iteration,
+                        code: code.clone(),
+                    });
+                }
+            }
+            iteration += 1;
+            if !self.fixed_point || iteration == MAX_ITERATIONS || code == previous {
+                return code;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_unknown_pass() {
+    assert!(PassManager::new(OptLevel::O2).passes("propagate,unwrap-if").is_ok());
+    assert!(PassManager::new(OptLevel::O2).passes("propagate,inline").is_err());
+}
+
+#[test]
+fn test_o0_keeps_code() {
+    let code = crate::generate_program(3, &crate::GeneratorConfig::default());
+    let mut manager = PassManager::new(OptLevel::O0);
+    assert_eq!(manager.run(code.clone()), code);
+    assert!(manager.stats.is_empty());
+}
+
+#[test]
+fn test_fixed_point() {
+    use crate::Mir;
+    // Changes the code without changing its size until the value is 5
+    fn count_up(mut code: MirCodeBlock) -> MirCodeBlock {
+        if let Some(Mir::Set(_, a)) = code.first_mut() {
+            *a = (*a + 1).min(5);
+        }
+        code
+    }
+    static COUNT_UP: Pass = Pass {
+        name: "count-up",
+        description: "",
+        run: count_up,
+    };
+    let mut manager = PassManager::new(OptLevel::O2);
+    manager.passes = vec![&COUNT_UP];
+    assert_eq!(manager.run(Mir::Set(1, 0).into()), Mir::Set(1, 5).into());
+    assert_eq!(manager.stats[0].runs, 6);
+}
+
+#[test]
+fn test_iteration_cap() {
+    use crate::Mir;
+    fn grow(mut code: MirCodeBlock) -> MirCodeBlock {
+        code.push(Mir::Increment(1));
+        code
+    }
+    static GROW: Pass = Pass {
+        name: "grow",
+        description: "",
+        run: grow,
+    };
+    let mut manager = PassManager::new(OptLevel::O2);
+    manager.passes = vec![&GROW];
+    assert_eq!(manager.run(MirCodeBlock::default()).len(), MAX_ITERATIONS);
+    assert_eq!(manager.stats[0].runs, MAX_ITERATIONS);
+}
@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+
+use either::Either;
+
+use crate::{Mir, MirCodeBlock};
+
+use super::{
+    cfg::{read, rewrite, written, BlockId, Cfg, InstrId, Rewrite, Terminator, ENTRY},
+    dataflow::ValueSet,
+};
+
+pub type ValueId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Definition {
+    /// The value of the variable at the start of the program (Variables start at 0)
+    Entry(u32),
+    Instruction(InstrId),
+    Phi(BlockId, u32),
+}
+
+#[derive(Clone, Debug)]
+pub struct Phi {
+    pub var: u32,
+    pub value: ValueId,
+    /// The value coming from each predecessor
+    pub args: Vec<(BlockId, ValueId)>,
+}
+
+/// The SSA form of a `Cfg`, every write to a variable defines a new value. The variables
+/// aren't renamed in the code: the values only describe which write each read sees so going
+/// back to MIR doesn't need to insert copies.
+pub struct Ssa {
+    pub cfg: Cfg,
+    pub definitions: Vec<Definition>,
+    /// The phi nodes at the start of each block
+    pub phis: Vec<Vec<Phi>>,
+    /// The value read by each instruction and terminator
+    pub uses: HashMap<InstrId, ValueId>,
+    /// The value defined by each instruction and the value the variable had before it
+    pub defs: HashMap<InstrId, (ValueId, ValueId)>,
+}
+
+impl Ssa {
+    pub fn new(cfg: Cfg) -> Self {
+        let idom = cfg.dominators();
+        let frontiers = cfg.dominance_frontiers(&idom);
+        let mut children = vec![Vec::new(); cfg.blocks.len()];
+        for (b, d) in idom.iter().enumerate() {
+            if let Some(d) = d {
+                children[*d].push(b);
+            }
+        }
+
+        // Phis are placed on the iterated dominance frontier of the writes
+        let mut sites: HashMap<u32, HashSet<BlockId>> = HashMap::new();
+        for b in cfg.reverse_postorder() {
+            for (_, mir) in &cfg.blocks[b].instructions {
+                if let Some(a) = written(mir) {
+                    sites.entry(a).or_default().insert(b);
+                }
+            }
+        }
+        let mut definitions = Vec::new();
+        let mut phis: Vec<Vec<Phi>> = vec![Vec::new(); cfg.blocks.len()];
+        let mut vars: Vec<_> = sites.into_iter().collect();
+        vars.sort_by_key(|x| x.0);
+        for (var, blocks) in vars {
+            let mut placed = HashSet::new();
+            let mut work: Vec<BlockId> = blocks.into_iter().collect();
+            while let Some(b) = work.pop() {
+                for &f in &frontiers[b] {
+                    if placed.insert(f) {
+                        definitions.push(Definition::Phi(f, var));
+                        phis[f].push(Phi {
+                            var,
+                            value: definitions.len() - 1,
+                            args: Vec::new(),
+                        });
+                        work.push(f);
+                    }
+                }
+            }
+        }
+
+        let mut ssa = Self {
+            cfg,
+            definitions,
+            phis,
+            uses: HashMap::new(),
+            defs: HashMap::new(),
+        };
+        ssa.rename(&children);
+        ssa
+    }
+
+    fn rename(&mut self, children: &[Vec<BlockId>]) {
+        let mut stacks: HashMap<u32, Vec<ValueId>> = HashMap::new();
+        let mut entries: HashMap<u32, ValueId> = HashMap::new();
+        let definitions = &mut self.definitions;
+        // The variables that aren't written before have their entry value
+        fn current(
+            definitions: &mut Vec<Definition>,
+            entries: &mut HashMap<u32, ValueId>,
+            stacks: &HashMap<u32, Vec<ValueId>>,
+            var: u32,
+        ) -> ValueId {
+            match stacks.get(&var).and_then(|x| x.last()) {
+                Some(e) => *e,
+                None => *entries.entry(var).or_insert_with(|| {
+                    definitions.push(Definition::Entry(var));
+                    definitions.len() - 1
+                }),
+            }
+        }
+        // The dominator tree is walked with a stack because it can be very deep
+        let mut work = vec![(ENTRY, false)];
+        let mut pushed: Vec<Vec<u32>> = vec![Vec::new(); self.cfg.blocks.len()];
+        while let Some((b, exit)) = work.pop() {
+            if exit {
+                for var in &pushed[b] {
+                    stacks.get_mut(var).unwrap().pop();
+                }
+                continue;
+            }
+            work.push((b, true));
+            for phi in &self.phis[b] {
+                stacks.entry(phi.var).or_default().push(phi.value);
+                pushed[b].push(phi.var);
+            }
+            for (id, mir) in &self.cfg.blocks[b].instructions {
+                if let Some(a) = read(mir) {
+                    self.uses.insert(*id, current(definitions, &mut entries, &stacks, a));
+                }
+                if let Some(a) = written(mir) {
+                    let before = current(definitions, &mut entries, &stacks, a);
+                    definitions.push(Definition::Instruction(*id));
+                    let value = definitions.len() - 1;
+                    self.defs.insert(*id, (value, before));
+                    stacks.entry(a).or_default().push(value);
+                    pushed[b].push(a);
+                }
+            }
+            let terminator = &self.cfg.blocks[b].terminator;
+            if let Some((id, a)) = terminator.read() {
+                self.uses.insert(id, current(definitions, &mut entries, &stacks, a));
+            }
+            for s in terminator.successors() {
+                for phi in &mut self.phis[s] {
+                    let value = current(definitions, &mut entries, &stacks, phi.var);
+                    phi.args.push((b, value));
+                }
+            }
+            for child in children[b].iter().rev() {
+                work.push((*child, false));
+            }
+        }
+    }
+
+    /// What the edge tells about the value read by the terminator of the block it starts from
+    fn condition(&self, from: BlockId, to: BlockId) -> Option<(ValueId, ValueSet)> {
+        let zero = ValueSet::single(0);
+        match &self.cfg.blocks[from].terminator {
+            Terminator::Branch(id, _, a, _) if *a == to => Some((self.uses[id], zero)),
+            Terminator::Branch(id, _, _, _) => Some((self.uses[id], ValueSet::ALL.without(zero))),
+            Terminator::Switch(id, _, arms, _) => Some((
+                self.uses[id],
+                match arms.iter().find(|x| x.1 == to) {
+                    Some(e) => e.0,
+                    None => ValueSet::ALL.without(
+                        arms.iter().fold(ValueSet::EMPTY, |x, y| x.union(y.0)),
+                    ),
+                },
+            )),
+            _ => None,
+        }
+    }
+
+    /// Finds the values each SSA value can hold and which edges of the graph can be taken,
+    /// the branches that can't be taken aren't followed (Sparse conditional constant
+    /// propagation on value sets). The values are narrowed in the blocks dominated by a
+    /// branch on them.
+    pub fn propagate(&self) -> Propagation {
+        let order = self.cfg.reverse_postorder();
+        let idom = self.cfg.dominators();
+        let predecessors = self.cfg.predecessors();
+        let mut result = Propagation {
+            values: vec![ValueSet::EMPTY; self.definitions.len()],
+            edges: HashSet::new(),
+            executable: vec![false; self.cfg.blocks.len()],
+            refinements: vec![Vec::new(); self.cfg.blocks.len()],
+        };
+        for &b in &order {
+            let mut refinements = idom[b]
+                .map(|x| result.refinements[x].clone())
+                .unwrap_or_default();
+            if let [p] = predecessors[b].as_slice() {
+                refinements.extend(self.condition(*p, b));
+            }
+            result.refinements[b] = refinements;
+        }
+        for (i, d) in self.definitions.iter().enumerate() {
+            if let Definition::Entry(_) = d {
+                result.values[i] = ValueSet::single(0);
+            }
+        }
+        result.executable[ENTRY] = true;
+        let update = |values: &mut Vec<ValueSet>, value: ValueId, new: ValueSet| {
+            let new = values[value].union(new);
+            let changed = new != values[value];
+            values[value] = new;
+            changed
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in &order {
+                if !result.executable[b] {
+                    continue;
+                }
+                for phi in &self.phis[b] {
+                    let mut new = ValueSet::EMPTY;
+                    for (p, value) in &phi.args {
+                        if !result.edges.contains(&(*p, b)) {
+                            continue;
+                        }
+                        let mut v = result.value(*p, *value);
+                        if let Some((tested, set)) = self.condition(*p, b) {
+                            if tested == *value {
+                                v = v.intersection(set);
+                            }
+                        }
+                        new = new.union(v);
+                    }
+                    changed |= update(&mut result.values, phi.value, new);
+                }
+                for (id, mir) in &self.cfg.blocks[b].instructions {
+                    let (value, _) = match self.defs.get(id) {
+                        Some(e) => *e,
+                        None => continue,
+                    };
+                    let used = self.uses.get(id).map(|x| result.value(b, *x));
+                    let new = match mir {
+                        Mir::Set(_, a) => ValueSet::single(*a),
+                        Mir::Copy(_, _) => used.unwrap(),
+                        Mir::Increment(_) => used.unwrap().increment(),
+                        Mir::Decrement(_) => used.unwrap().decrement(),
+                        _ => ValueSet::ALL,
+                    };
+                    changed |= update(&mut result.values, value, new);
+                }
+                let terminator = &self.cfg.blocks[b].terminator;
+                let tested = terminator
+                    .read()
+                    .map(|(id, _)| result.value(b, self.uses[&id]));
+                for s in terminator.successors() {
+                    let taken = match (self.condition(b, s), tested) {
+                        (Some((_, set)), Some(v)) => !set.intersection(v).is_empty(),
+                        _ => true,
+                    };
+                    if taken && result.edges.insert((b, s)) {
+                        result.executable[s] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+pub struct Propagation {
+    pub values: Vec<ValueSet>,
+    pub edges: HashSet<(BlockId, BlockId)>,
+    pub executable: Vec<bool>,
+    /// The values known to be in a set in each block because of the branches before it
+    pub refinements: Vec<Vec<(ValueId, ValueSet)>>,
+}
+
+impl Propagation {
+    /// The values the SSA value can hold in the block
+    pub fn value(&self, block: BlockId, value: ValueId) -> ValueSet {
+        self.refinements[block]
+            .iter()
+            .filter(|x| x.0 == value)
+            .fold(self.values[value], |x, y| x.intersection(y.1))
+    }
+}
+
+/// Replaces the reads of variables that can only hold one value by the value, folds the
+/// conditions that always take the same branch and removes the code that is never executed.
+/// The code calling procedures is kept as is since a call can write many variables.
+pub fn constant_propagation(code: MirCodeBlock) -> MirCodeBlock {
+    if code.iter().any(Mir::calls) {
+        return code;
+    }
+    let ssa = Ssa::new(Cfg::new(&code));
+    let result = ssa.propagate();
+    let mut rewrites = HashMap::new();
+    for (id, block) in ssa.cfg.location.iter().enumerate() {
+        if !result.executable[*block] {
+            rewrites.insert(id, Rewrite::Remove);
+        }
+    }
+    for (b, block) in ssa.cfg.blocks.iter().enumerate() {
+        if !result.executable[b] {
+            continue;
+        }
+        let single = |value: ValueId| result.value(b, value).single_value();
+        for (id, mir) in &block.instructions {
+            let rewrite = match (mir, ssa.defs.get(id)) {
+                (Mir::Set(_, a), Some((_, before))) if single(*before) == Some(*a) => {
+                    Rewrite::Remove
+                }
+                (Mir::Copy(a, _), Some((value, before))) => {
+                    let used = ssa.uses[id];
+                    match single(*value) {
+                        // The variable already holds the value
+                        _ if used == *before => Rewrite::Remove,
+                        Some(e) if single(*before) == Some(e) => Rewrite::Remove,
+                        Some(e) => Rewrite::Replace(Mir::Set(*a, e)),
+                        None => continue,
+                    }
+                }
+                (Mir::Increment(a) | Mir::Decrement(a), Some((value, _))) => match single(*value) {
+                    Some(e) => Rewrite::Replace(Mir::Set(*a, e)),
+                    None => continue,
+                },
+                (Mir::WriteRegister(r, Either::Right(_)), _) => match single(ssa.uses[id]) {
+                    Some(e) => Rewrite::Replace(Mir::WriteRegister(*r, Either::Left(e))),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            rewrites.insert(*id, rewrite);
+        }
+        let taken = |target: &BlockId| result.edges.contains(&(b, *target));
+        match &block.terminator {
+            Terminator::Branch(id, _, zero, other) => {
+                if !taken(other) {
+                    rewrites.insert(*id, Rewrite::Inline(0));
+                } else if !taken(zero) {
+                    rewrites.insert(*id, Rewrite::Inline(1));
+                }
+            }
+            Terminator::Switch(id, _, arms, default) => {
+                let kept: Vec<usize> = arms
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (_, x))| taken(x))
+                    .map(|(i, _)| i)
+                    .collect();
+                if kept.is_empty() {
+                    rewrites.insert(*id, Rewrite::Remove);
+                } else if kept.len() == 1 && !taken(default) {
+                    rewrites.insert(*id, Rewrite::Inline(kept[0]));
+                } else if kept.len() != arms.len() {
+                    rewrites.insert(*id, Rewrite::Arms(kept));
+                }
+            }
+            _ => (),
+        }
+    }
+    rewrite(code, &rewrites)
+}
+
+#[test]
+fn test_phi_placement() {
+    let code = MirCodeBlock(vec![
+        Mir::ReadRegister(1, 1),
+        Mir::If0(1, Mir::Set(2, 3).into(), Mir::Set(2, 4).into()),
+        Mir::WriteRegister(2, Either::Right(2)),
+    ]);
+    let ssa = Ssa::new(Cfg::new(&code));
+    let join = ssa.cfg.location[4];
+    assert_eq!(ssa.phis[join].len(), 1);
+    assert_eq!(ssa.phis[join][0].args.len(), 2);
+    assert_eq!(ssa.uses[&4], ssa.phis[join][0].value);
+}
+
+#[test]
+fn test_constant_propagation_through_loops() {
+    // The counter is 2 or 1 when the loop runs and 0 when it ends
+    let code = MirCodeBlock(vec![
+        Mir::Set(1, 2),
+        Mir::Set(2, 7),
+        Mir::Loop(MirCodeBlock(vec![
+            Mir::If0(1, Mir::Break.into(), MirCodeBlock::default()),
+            Mir::Decrement(1),
+            Mir::Copy(3, 2),
+            Mir::WriteRegister(2, Either::Right(3)),
+        ])),
+        Mir::If0(1, Mir::WriteRegister(1, Either::Right(1)).into(), Mir::Stop.into()),
+    ]);
+    let code = constant_propagation(code);
+    assert_eq!(
+        code.0[2],
+        Mir::Loop(MirCodeBlock(vec![
+            Mir::If0(1, Mir::Break.into(), MirCodeBlock::default()),
+            Mir::Decrement(1),
+            Mir::Set(3, 7),
+            Mir::WriteRegister(2, Either::Left(7)),
+        ]))
+    );
+    assert_eq!(code.0[3], Mir::WriteRegister(1, Either::Left(0)));
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    sync::{Arc, OnceLock},
+};
+
+use crate::{Mir, MirCodeBlock};
+
+/// The code of a procedure and what it does to the variables, including the procedures it
+/// calls
+pub struct ProcedureBody {
+    pub code: MirCodeBlock,
+    pub accesses: HashSet<u32>,
+    pub writes: HashSet<u32>,
+    pub steps: usize,
+}
+
+/// A method compiled once, whose calls jump to its code instead of repeating it
+#[derive(Clone)]
+pub struct Procedure {
+    pub name: String,
+    /// The two digits of the number of the call the procedure returns to. The first one also
+    /// identifies the procedure.
+    pub return_slot: (u32, u32),
+    /// Set once the method is compiled, the recursive calls are created before
+    pub body: Arc<OnceLock<ProcedureBody>>,
+}
+
+impl Procedure {
+    pub fn new(name: String, return_slot: (u32, u32)) -> Self {
+        Self {
+            name,
+            return_slot,
+            body: Arc::new(OnceLock::new()),
+        }
+    }
+
+    pub fn with_code(name: String, return_slot: (u32, u32), code: MirCodeBlock) -> Self {
+        let procedure = Self::new(name, return_slot);
+        procedure.set_code(code);
+        procedure
+    }
+
+    /// Gives the procedure its code, the calls inside it to procedures that aren't compiled yet
+    /// only count their return slot
+    pub fn set_code(&self, code: MirCodeBlock) {
+        let mut accesses: HashSet<u32> = code.iter().flat_map(Mir::get_acesses).collect();
+        let mut writes = code.get_writes();
+        accesses.extend([self.return_slot.0, self.return_slot.1]);
+        writes.extend([self.return_slot.0, self.return_slot.1]);
+        let steps = code.estimated_steps();
+        let body = ProcedureBody {
+            code,
+            accesses,
+            writes,
+            steps,
+        };
+        if self.body.set(body).is_err() {
+            panic!("Procedure {} was compiled twice", self.name);
+        }
+    }
+
+    pub fn code(&self) -> &MirCodeBlock {
+        &self.compiled().code
+    }
+
+    fn compiled(&self) -> &ProcedureBody {
+        self.body
+            .get()
+            .unwrap_or_else(|| panic!("Procedure {} isn't compiled", self.name))
+    }
+
+    pub fn accesses(&self) -> HashSet<u32> {
+        match self.body.get() {
+            Some(e) => e.accesses.clone(),
+            None => [self.return_slot.0, self.return_slot.1].iter().copied().collect(),
+        }
+    }
+
+    pub fn writes(&self) -> HashSet<u32> {
+        match self.body.get() {
+            Some(e) => e.writes.clone(),
+            None => [self.return_slot.0, self.return_slot.1].iter().copied().collect(),
+        }
+    }
+
+    pub fn steps(&self) -> usize {
+        self.body.get().map(|x| x.steps).unwrap_or_default()
+    }
+}
+
+// The code isn't compared or printed since it can call the procedure itself
+
+impl PartialEq for Procedure {
+    fn eq(&self, other: &Self) -> bool {
+        self.return_slot == other.return_slot
+    }
+}
+
+impl Hash for Procedure {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.return_slot.hash(state);
+    }
+}
+
+impl Debug for Procedure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Procedure")
+            .field("name", &self.name)
+            .field("return_slot", &self.return_slot)
+            .finish()
+    }
+}
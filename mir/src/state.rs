@@ -1,4 +1,17 @@
-use lir::{AsmValue, CompilableInstruction, Counter, Label, Number, Var};
+use errors::Span;
+use lir::{AsmValue, CompilableInstruction, Counter, Label, LabelType, Number, Origin, Var};
+
+use crate::{procedure::Procedure, Mir};
+
+/// The maximum amount of calls of a procedure, the call it returns to is stored in two digits
+pub const MAX_CALLS: usize = 256;
+
+/// Where a procedure starts and the places its calls return to
+pub struct CallSites {
+    pub start: Label,
+    pub procedure: Procedure,
+    pub returns: Vec<Label>,
+}
 
 #[derive(Default)]
 pub struct MirState {
@@ -6,9 +19,105 @@ pub struct MirState {
     pub instructions: Vec<CompilableInstruction>,
     pub loops: Vec<Label>,
     pub blocks: Vec<Label>,
+    /// The procedures called by the code, in the order they were first called
+    pub procedures: Vec<CallSites>,
+    /// Whether the instructions of each MIR node are preceded by where they come from
+    pub debug_info: bool,
+    /// The location of the code being lowered
+    pub span: Option<Span>,
+    /// The methods the code being lowered was inlined in, the outermost first
+    pub methods: Vec<String>,
 }
 
 impl MirState {
+    /// Registers a new call of the procedure and returns the label of the procedure, the number
+    /// of the call and the label the call returns to
+    pub fn call_site(&mut self, procedure: &Procedure) -> (Label, usize, Label) {
+        let index = match self
+            .procedures
+            .iter()
+            .position(|x| x.procedure == *procedure)
+        {
+            Some(e) => e,
+            None => {
+                self.procedures.push(CallSites {
+                    start: Label::alloc(&mut self.count, LabelType::ProcedureStart),
+                    procedure: procedure.clone(),
+                    returns: Vec::new(),
+                });
+                self.procedures.len() - 1
+            }
+        };
+        let ret = Label::alloc(&mut self.count, LabelType::Return);
+        let sites = &mut self.procedures[index];
+        assert!(
+            sites.returns.len() < MAX_CALLS,
+            "Procedure {} is called too many times",
+            procedure.name
+        );
+        sites.returns.push(ret.clone());
+        (sites.start.clone(), sites.returns.len() - 1, ret)
+    }
+
+    /// Emits the code of the called procedures after the program. Each procedure ends by
+    /// jumping back to the call written in its return slot.
+    pub fn emit_procedures(&mut self) {
+        if self.procedures.is_empty() {
+            return;
+        }
+        // The program must not run into the procedures
+        self.stop();
+        let mut done = 0;
+        // The procedures can call procedures that weren't called yet
+        while done < self.procedures.len() {
+            let start = self.procedures[done].start.clone();
+            let procedure = self.procedures[done].procedure.clone();
+            self.label(start.clone());
+            self.span = None;
+            self.methods = vec![procedure.name.clone()];
+            procedure.code().to_asm(self);
+            self.jump(start.derive(LabelType::Return));
+            done += 1;
+        }
+        // The calls are only all known once every procedure was emitted
+        for index in 0..self.procedures.len() {
+            let (lo, hi) = self.procedures[index].procedure.return_slot;
+            let returns = self.procedures[index].returns.clone();
+            self.label(self.procedures[index].start.derive(LabelType::Return));
+            if returns.len() <= 16 {
+                self.dispatch(lo, &returns);
+                continue;
+            }
+            let groups: Vec<Label> = returns
+                .chunks(16)
+                .map(|_| Label::alloc(&mut self.count, LabelType::Return))
+                .collect();
+            self.dispatch(hi, &groups);
+            for (label, chunk) in groups.into_iter().zip(returns.chunks(16)) {
+                self.label(label);
+                self.dispatch(lo, chunk);
+            }
+        }
+    }
+
+    /// Jumps to the label at the index of the value of the variable
+    fn dispatch(&mut self, variable: u32, labels: &[Label]) {
+        let mut table: [Option<Label>; 16] = Default::default();
+        for (slot, label) in table.iter_mut().zip(labels) {
+            *slot = Some(label.clone());
+        }
+        self.instructions
+            .push(CompilableInstruction::Match(Var(variable as usize), table));
+    }
+
+    pub fn origin(&mut self, mir: &Mir) {
+        self.instructions.push(CompilableInstruction::Origin(Origin {
+            mir: mir.head(),
+            span: self.span.clone(),
+            methods: self.methods.clone(),
+        }));
+    }
+
     pub fn opt_asm(&mut self) {
         self.instructions = CompilableInstruction::optimize(self.instructions.clone());
     }
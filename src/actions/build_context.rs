@@ -1,24 +1,15 @@
-use std::process::exit;
+use std::{process::exit, time::Instant};
 
-use errors::{report, Error, Span, SpannedObject};
-use mir::{Mir, MirCodeBlock};
+use mir::{MirCodeBlock, OptLevel, PassManager};
 
-use crate::{
-    actions::natives::load_natives,
-    compiler::{
-        class_loader::ClassLoader,
-        state::{code_manager::CodeManager, local_state::LocalState},
-    },
-    parser::ty::Type,
-    STACK_SIZE,
-};
+use crate::Compiler;
 
-pub fn compile(class_name: String, optimize: bool) -> MirCodeBlock {
-    let child = std::thread::Builder::new()
-        .stack_size(STACK_SIZE)
-        .spawn(move || generate_mir(&class_name))
-        .unwrap();
-    let k = child.join().unwrap();
+/// Generates the class with the locations of its statements when `debug_info` is set, and
+/// optimizes it
+pub fn compile(class_name: String, mut optimizer: PassManager, debug_info: bool) -> MirCodeBlock {
+    let start = Instant::now();
+    let k = generate(class_name, None, debug_info);
+    let front_end = start.elapsed();
     std::fs::write(
         "before.mir",
         k.0.iter()
@@ -28,7 +19,13 @@ pub fn compile(class_name: String, optimize: bool) -> MirCodeBlock {
     )
     .expect("Could not write file");
     let count = k.instr_count();
-    let k = if optimize { k.optimize_code_new() } else { k };
+    let k = optimizer.run(k);
+    for dump in &optimizer.dumps {
+        println!("// After {} (iteration {})", dump.pass, dump.iteration);
+        for i in dump.code.iter() {
+            println!("{}", i);
+        }
+    }
     std::fs::write(
         "after.mir",
         k.0.iter()
@@ -42,40 +39,81 @@ pub fn compile(class_name: String, optimize: bool) -> MirCodeBlock {
         "Optimized from {} to {} ({:.02}%)",
         count,
         ncount,
-        (count - ncount) as f64 / count as f64 * 100.
+        // Unrolling can make the code bigger than it was
+        (count as f64 - ncount as f64) / count as f64 * 100.
     );
+    if !optimizer.stats.is_empty() {
+        println!("Saved by each pass:");
+        for stats in &optimizer.stats {
+            println!("{}", stats);
+        }
+    }
+    if optimizer.time_passes {
+        println!("Time per pass:");
+        println!("{:<20} {:>10.3}ms", "front-end", front_end.as_secs_f64() * 1000.);
+        for stats in &optimizer.stats {
+            println!(
+                "{:<20} {:>10.3}ms",
+                stats.name,
+                stats.time.as_secs_f64() * 1000.
+            );
+        }
+    }
     k
 }
 
-fn generate_mir(class_name: &str) -> MirCodeBlock {
-    let r: Result<(), Error> = try {
-        let mut cl = ClassLoader::new();
-        for file in std::fs::read_dir("std").unwrap() {
-            cl.load_string(
-                &std::fs::read_to_string(file.as_ref().unwrap().path()).unwrap(),
-                &file
-                    .as_ref()
-                    .unwrap()
-                    .path()
-                    .as_os_str()
-                    .to_str()
-                    .unwrap()
-                    .to_owned(),
-            )?;
+/// Reads the optimization flags (`-O0`, `-O1`, `-O2`, `--passes=a,b`, `--print-after=a` and
+/// `--time-passes`),
+/// `default` is the level used when no level is given.
+pub fn optimizer_from_flags(flags: &[&str], default: OptLevel) -> Result<PassManager, String> {
+    let level = flags
+        .iter()
+        .rev()
+        .find_map(|x| x.strip_prefix("-O"))
+        .map(|x| OptLevel::parse(x).ok_or_else(|| format!("Invalid optimization level `{}`", x)))
+        .transpose()?;
+    let mut optimizer = PassManager::new(level.unwrap_or(default));
+    for flag in flags {
+        if let Some(e) = flag.strip_prefix("--passes=") {
+            // Passes given without level are run like with `-O2`
+            optimizer = PassManager::new(level.unwrap_or(OptLevel::O2)).passes(e)?;
+        }
+    }
+    for flag in flags {
+        if let Some(e) = flag.strip_prefix("--print-after=") {
+            optimizer = optimizer.print_after(e)?;
+        } else if *flag == "--time-passes" {
+            optimizer.time_passes = true;
+        } else if !flag.starts_with("-O") && !flag.starts_with("--passes=") {
+            return Err(format!("Unknown flag `{}`", flag));
         }
-        load_natives(&mut cl);
+    }
+    Ok(optimizer)
+}
+
+/// A compiler of the class with the classes of the `std` directory
+pub fn compiler(class_name: String) -> Compiler {
+    let mut compiler = Compiler::new().entry(class_name);
+    for file in std::fs::read_dir("std").unwrap() {
+        let path = file.unwrap().path();
+        compiler = compiler.add_source(
+            path.as_os_str().to_str().unwrap(),
+            std::fs::read_to_string(&path).unwrap(),
+        );
+    }
+    compiler
+}
 
-        let rs = cl
-            .view(&Type::simple(class_name, Span::default()))?
-            .method_view(&SpannedObject(Span::default(), "main".to_owned()), &None)?
-            .execute(&mut LocalState::new(), &mut CodeManager::new(cl), vec![])?;
-        let mut mir = rs.mir;
-        mir.add_mir(Mir::Stop);
-        return mir;
-    };
-    if let Err(e) = r {
-        report(e);
+/// Compiles the class and the classes of the `std` directory without optimizing it. If
+/// `return_register` is set the value returned by `main` is written to the registers starting at
+/// this one before the program stops.
+pub fn generate(class_name: String, return_register: Option<u8>, debug_info: bool) -> MirCodeBlock {
+    let mut compiler = compiler(class_name).debug_info(debug_info);
+    if let Some(register) = return_register {
+        compiler = compiler.return_register(register);
+    }
+    compiler.generate().unwrap_or_else(|e| {
+        eprint!("{}", e);
         exit(0);
-    };
-    panic!();
+    })
 }
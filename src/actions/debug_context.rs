@@ -0,0 +1,400 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fmt::Write,
+    rc::Rc,
+    sync::Mutex,
+};
+
+use cythan::{
+    format::{Container, DebugRange, FormatError},
+    Cythan, InterruptedCythan,
+};
+use mir::RunContext;
+
+use crate::actions::diff_context::ScriptedContext;
+
+/// How many steps `back` can undo
+const HISTORY: usize = 100_000;
+
+const HELP: &str = "step [n]            run n steps (1 by default)
+back [n]            undo n steps, with what they printed and read
+continue            run until a breakpoint, a watchpoint, the end or the end of the input
+break <location>    stop when the program counter reaches an address or a label
+delete <location>   remove a breakpoint
+watch <cell>        stop when a step changes the value of a cell
+unwatch <cell>      remove a watchpoint
+info                list the breakpoints and the watchpoints
+where               print the program counter and the code it points to
+x <cell> [n]        print n cells from a cell (8 by default)
+print <cell>        print the value of a cell, a variable or a register
+regs                print the registers '#return_0 to '#return_15
+symbols [prefix]    list the symbols starting with a prefix
+quit                leave the debugger
+Locations and cells are addresses or the names of the symbols, like 'start, 'lA12 or 'v3";
+
+/// The cells a step wrote with their previous values, to undo it
+struct Step {
+    writes: Vec<(usize, usize)>,
+    /// The size of the memory before the step, which grows when a cell past its end is written
+    len: usize,
+    /// The size of the output before the step
+    printed: usize,
+    /// The input before the step
+    inputs: usize,
+    exhausted: bool,
+}
+
+/// Why the machine stopped running
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    /// The requested number of steps ran
+    Done,
+    Breakpoint(usize),
+    Watchpoint {
+        cell: usize,
+        old: usize,
+        new: usize,
+    },
+    /// The program jumps to itself, which is how `stop` is compiled
+    Ended,
+    /// The program read more characters than the input has
+    InputExhausted,
+}
+
+/// Runs a binary step by step, going back up to `HISTORY` steps
+pub struct Debugger {
+    pub machine: InterruptedCythan,
+    context: Rc<Mutex<ScriptedContext>>,
+    container: Container,
+    pub breakpoints: BTreeSet<usize>,
+    pub watchpoints: BTreeSet<usize>,
+    history: VecDeque<Step>,
+    /// The number of steps run since the start
+    pub steps: usize,
+    /// The characters of the output already shown
+    shown: usize,
+    input: Vec<u8>,
+}
+
+impl Debugger {
+    /// Runs the binary on the machine its header describes, with `input` as the characters read
+    pub fn new(container: Container, input: &str) -> Result<Self, FormatError> {
+        let context = Rc::new(Mutex::new(ScriptedContext::new(input)));
+        let (context1, context2) = (context.clone(), context.clone());
+        let machine = InterruptedCythan::new(
+            container.memory.iter().map(|x| *x as usize).collect(),
+            container.header.base,
+            container.header.interrupt_place()?,
+            move |a| context1.lock().unwrap().print(a as char),
+            move || context2.lock().unwrap().input(),
+        );
+        Ok(Self {
+            machine,
+            context,
+            container,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            history: VecDeque::new(),
+            steps: 0,
+            shown: 0,
+            input: input.bytes().collect(),
+        })
+    }
+
+    pub fn pc(&self) -> usize {
+        self.machine.get_value(0)
+    }
+
+    /// Runs one step, and tells why the machine should stop after it if it should
+    pub fn step(&mut self) -> Option<Stop> {
+        let pc = self.pc();
+        let to = self.machine.get_value(pc + 1);
+        let mut cells = vec![0, to];
+        if to == self.machine.interrupt_place {
+            // Reading a character writes it after the interrupt
+            cells.extend([to + 1, to + 2]);
+        }
+        let (printed, inputs, exhausted) = {
+            let context = self.context.lock().unwrap();
+            (context.print.len(), context.inputs.len(), context.exhausted)
+        };
+        let step = Step {
+            writes: cells
+                .into_iter()
+                .map(|x| (x, self.machine.get_value(x)))
+                .collect(),
+            len: self.machine.cases.len(),
+            printed,
+            inputs,
+            exhausted,
+        };
+        self.machine.next();
+        self.steps += 1;
+        let changed = step
+            .writes
+            .iter()
+            .map(|(cell, old)| (*cell, *old, self.machine.get_value(*cell)))
+            .find(|(cell, old, new)| old != new && self.watchpoints.contains(cell));
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(step);
+        if self.pc() == pc {
+            Some(Stop::Ended)
+        } else if let Some((cell, old, new)) = changed {
+            Some(Stop::Watchpoint { cell, old, new })
+        } else if !exhausted && self.context.lock().unwrap().exhausted {
+            Some(Stop::InputExhausted)
+        } else if self.breakpoints.contains(&self.pc()) {
+            Some(Stop::Breakpoint(self.pc()))
+        } else {
+            None
+        }
+    }
+
+    /// Runs `count` steps or until the machine should stop, forever if there is no count
+    pub fn run(&mut self, count: Option<usize>) -> Stop {
+        for _ in 0..count.unwrap_or(usize::MAX) {
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+        }
+        Stop::Done
+    }
+
+    /// Undoes the last step, if it is still in the history
+    pub fn back(&mut self) -> bool {
+        let step = match self.history.pop_back() {
+            Some(e) => e,
+            None => return false,
+        };
+        for (cell, value) in step.writes.into_iter().rev() {
+            if let Some(e) = self.machine.cases.get_mut(cell) {
+                *e = value;
+            }
+        }
+        self.machine.cases.truncate(step.len);
+        let mut context = self.context.lock().unwrap();
+        context.print.truncate(step.printed);
+        self.shown = self.shown.min(step.printed);
+        // The input read by the step is the one before the remaining input
+        let read = self.input.len() - step.inputs;
+        if context.inputs.len() < step.inputs {
+            context.inputs.push_front(self.input[read]);
+        }
+        context.exhausted = step.exhausted;
+        self.steps -= 1;
+        true
+    }
+
+    /// An address or the name of a symbol, with or without its quote
+    pub fn resolve(&self, name: &str) -> Option<usize> {
+        if let Ok(e) = name.parse() {
+            return Some(e);
+        }
+        let name = format!("'{}", name.trim_start_matches('\''));
+        if let Some(symbols) = &self.container.symbols {
+            let symbol = symbols
+                .labels
+                .iter()
+                .chain(&symbols.variables)
+                .find(|x| x.name == name);
+            if let Some(e) = symbol {
+                return Some(e.address as usize);
+            }
+        }
+        // The registers follow the interrupt even without symbols
+        let register: u8 = name.strip_prefix("'#return_")?.parse().ok()?;
+        (register < 16).then(|| self.machine.interrupt_place + register as usize)
+    }
+
+    /// The symbols at an address
+    fn names(&self, address: usize) -> Vec<&str> {
+        let symbols = match &self.container.symbols {
+            Some(e) => e,
+            None => return Vec::new(),
+        };
+        symbols
+            .labels
+            .iter()
+            .chain(&symbols.variables)
+            .filter(|x| x.address as usize == address)
+            .map(|x| x.name.as_str())
+            .collect()
+    }
+
+    /// The range of the debug info holding the address
+    pub fn lookup(&self, address: usize) -> Option<&DebugRange> {
+        let ranges = self.container.debug_info.as_ref()?;
+        let i = ranges.partition_point(|x| x.end as usize <= address);
+        ranges.get(i).filter(|x| x.start as usize <= address)
+    }
+
+    /// The value of a cell, the zeros are stored as the base
+    fn digit(&self, value: usize) -> usize {
+        value % self.machine.base_as_pow
+    }
+
+    /// The program counter, the closest label before it and the code it comes from
+    pub fn location(&self) -> String {
+        let pc = self.pc();
+        let mut out = format!("{} after {} steps", pc, self.steps);
+        let label = self.container.symbols.as_ref().and_then(|x| {
+            x.labels
+                .iter()
+                .filter(|x| x.address as usize <= pc)
+                .max_by_key(|x| x.address)
+        });
+        if let Some(e) = label {
+            write!(out, " ({}+{})", e.name, pc - e.address as usize).unwrap();
+        }
+        let range = match self.lookup(pc) {
+            Some(e) => e,
+            None => return out,
+        };
+        write!(out, "\n{}\n{}", range.lir, range.mir).unwrap();
+        if let Some(location) = &range.location {
+            write!(
+                out,
+                "\n{}:{}..{}",
+                location.file, location.start, location.end
+            )
+            .unwrap();
+            let text = self
+                .container
+                .sources
+                .iter()
+                .flatten()
+                .find(|x| x.name == location.file)
+                .and_then(|x| x.text.get(location.start as usize..location.end as usize));
+            if let Some(text) = text {
+                write!(out, "\n    {}", text.lines().next().unwrap_or("")).unwrap();
+            }
+        }
+        out
+    }
+
+    fn describe(&self, stop: &Stop) -> String {
+        let reason = match stop {
+            Stop::Done => String::new(),
+            Stop::Breakpoint(_) => "Breakpoint at ".to_owned(),
+            Stop::Watchpoint { cell, old, new } => {
+                format!("Cell {} changed from {} to {} at ", cell, old, new)
+            }
+            Stop::Ended => "The program ended at ".to_owned(),
+            Stop::InputExhausted => "The input is exhausted at ".to_owned(),
+        };
+        format!("{}{}", reason, self.location())
+    }
+
+    fn cell(&self, cell: usize) -> String {
+        let value = self.machine.get_value(cell);
+        let mut out = format!("{} = {} (digit {})", cell, value, self.digit(value));
+        for name in self.names(cell) {
+            write!(out, " {}", name).unwrap();
+        }
+        out
+    }
+
+    /// Runs one line of the debugger, the errors are meant for the user
+    pub fn command(&mut self, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let argument = |i: usize| -> Result<usize, String> {
+            let word = words.get(i).ok_or("Missing argument")?;
+            self.resolve(word)
+                .ok_or_else(|| format!("Unknown address or symbol {}", word))
+        };
+        let count = |i: usize, default: usize| -> Result<usize, String> {
+            words.get(i).map_or(Ok(default), |x| {
+                x.parse().map_err(|_| format!("Invalid count {}", x))
+            })
+        };
+        let mut out = match words.first().copied().unwrap_or("") {
+            "step" | "s" => {
+                let stop = self.run(Some(count(1, 1)?));
+                self.describe(&stop)
+            }
+            "continue" | "c" => {
+                let stop = self.run(None);
+                self.describe(&stop)
+            }
+            "back" => {
+                let count = count(1, 1)?;
+                let undone = (0..count).take_while(|_| self.back()).count();
+                if undone < count {
+                    format!("Only {} steps in the history\n{}", undone, self.location())
+                } else {
+                    self.location()
+                }
+            }
+            "break" | "b" => {
+                let address = argument(1)?;
+                self.breakpoints.insert(address);
+                format!("Breakpoint at {}", address)
+            }
+            "delete" => {
+                let address = argument(1)?;
+                if !self.breakpoints.remove(&address) {
+                    return Err(format!("No breakpoint at {}", address));
+                }
+                format!("Removed the breakpoint at {}", address)
+            }
+            "watch" => {
+                let cell = argument(1)?;
+                self.watchpoints.insert(cell);
+                format!("Watching {}", self.cell(cell))
+            }
+            "unwatch" => {
+                let cell = argument(1)?;
+                if !self.watchpoints.remove(&cell) {
+                    return Err(format!("No watchpoint on {}", cell));
+                }
+                format!("Stopped watching {}", cell)
+            }
+            "info" => format!(
+                "Breakpoints: {:?}\nWatchpoints: {:?}",
+                self.breakpoints, self.watchpoints
+            ),
+            "where" | "pc" => self.location(),
+            "x" => {
+                let (start, count) = (argument(1)?, count(2, 8)?);
+                (start..start + count)
+                    .map(|x| self.cell(x))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            "print" | "p" => self.cell(argument(1)?),
+            "regs" => (0..16)
+                .map(|x| {
+                    let cell = self.resolve(&format!("'#return_{}", x)).unwrap();
+                    format!(
+                        "'#return_{} = {}",
+                        x,
+                        self.digit(self.machine.get_value(cell))
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "symbols" => {
+                let prefix = format!("'{}", words.get(1).unwrap_or(&"").trim_start_matches('\''));
+                let symbols = self.container.symbols.as_ref().ok_or("No symbols")?;
+                symbols
+                    .labels
+                    .iter()
+                    .chain(&symbols.variables)
+                    .filter(|x| x.name.starts_with(&prefix))
+                    .map(|x| format!("{} = {}", x.name, x.address))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            "help" | "" => HELP.to_owned(),
+            e => return Err(format!("Unknown command {}, try help", e)),
+        };
+        let print = &self.context.lock().unwrap().print;
+        if print.len() > self.shown {
+            write!(out, "\nOutput: {:?}", &print[self.shown..]).unwrap();
+            self.shown = print.len();
+        }
+        Ok(out)
+    }
+}
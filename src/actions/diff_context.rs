@@ -0,0 +1,316 @@
+use std::{collections::VecDeque, fmt::Display, rc::Rc, sync::Mutex};
+
+use cythan::{format::HeaderData, Cythan, InterruptedCythan};
+use lir::CompilableInstruction;
+use mir::{coalesce_variables, MemoryState, Mir, MirCodeBlock, RunContext, SkipStatus};
+
+use crate::{actions::build_context::generate, lower};
+
+/// The first register used to expose the value returned by `main`
+pub const RETURN_REGISTER: u8 = 3;
+/// The amount of registers that can hold the value returned by `main`
+const RETURN_REGISTERS: usize = 7;
+
+/// The amount of steps after which an execution is considered as never ending
+#[derive(Clone, Copy)]
+pub struct StepLimits {
+    pub interpreter: usize,
+    pub binary: usize,
+}
+
+impl Default for StepLimits {
+    fn default() -> Self {
+        Self {
+            interpreter: 100_000_000,
+            binary: 1_000_000_000,
+        }
+    }
+}
+
+/// A context fed with a fixed input that remembers when the program asked for more input
+/// than it was given, the execution is stopped at this point.
+pub struct ScriptedContext {
+    pub inputs: VecDeque<u8>,
+    pub print: String,
+    pub exhausted: bool,
+}
+
+impl ScriptedContext {
+    pub fn new(inputs: &str) -> Self {
+        Self {
+            inputs: inputs.bytes().collect(),
+            print: String::new(),
+            exhausted: false,
+        }
+    }
+}
+
+impl RunContext for ScriptedContext {
+    fn input(&mut self) -> u8 {
+        self.inputs.pop_front().unwrap_or_else(|| {
+            self.exhausted = true;
+            0
+        })
+    }
+
+    fn print(&mut self, i: char) {
+        self.print.push(i);
+    }
+
+    fn exhausted(&self) -> bool {
+        self.exhausted
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    Interpreter,
+    Binary,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Termination {
+    Stopped,
+    Timeout,
+    InputExhausted,
+}
+
+#[derive(Clone)]
+pub struct Execution {
+    pub name: &'static str,
+    pub backend: Backend,
+    pub output: Vec<char>,
+    pub return_value: Vec<u8>,
+    pub steps: usize,
+    pub termination: Termination,
+    /// The MIR instruction that printed each character (Only for the interpreter)
+    pub print_trace: Vec<Mir>,
+}
+
+impl Execution {
+    /// Whether the whole output is known (The program stopped by itself before running out of input)
+    fn complete(&self) -> bool {
+        self.termination == Termination::Stopped
+    }
+}
+
+pub fn interpret(name: &'static str, mir: &MirCodeBlock, input: &str, max_steps: usize) -> Execution {
+    let mut ctx = ScriptedContext::new(input);
+    let mut ms = MemoryState::new(2048, 16);
+    ms.max_instr = Some(max_steps);
+    ms.print_trace = Some(Vec::new());
+    let termination = match ms.execute_block(mir, &mut ctx) {
+        SkipStatus::Timeout => Termination::Timeout,
+        _ if ctx.exhausted() => Termination::InputExhausted,
+        _ => Termination::Stopped,
+    };
+    let base = RETURN_REGISTER as usize;
+    Execution {
+        name,
+        backend: Backend::Interpreter,
+        output: ctx.print.chars().collect(),
+        return_value: ms.registers[base..base + RETURN_REGISTERS].to_vec(),
+        steps: ms.instr_count,
+        termination,
+        print_trace: ms.print_trace.unwrap_or_default(),
+    }
+}
+
+pub fn execute_binary(
+    name: &'static str,
+    mir: &MirCodeBlock,
+    input: &str,
+    max_steps: usize,
+) -> Execution {
+    let binary = CompilableInstruction::compile_to_binary(lower(mir));
+    // The binary is run on the machine its files are written for
+    let header = HeaderData::default();
+    let interrupt_place = header
+        .interrupt_place()
+        .expect("The default machine is supported");
+
+    let ctx = Rc::new(Mutex::new(ScriptedContext::new(input)));
+    let ctx1 = ctx.clone();
+    let ctx2 = ctx.clone();
+    let mut machine = InterruptedCythan::new(
+        binary,
+        header.base,
+        interrupt_place,
+        move |a| ctx1.lock().unwrap().print(a as char),
+        move || ctx2.lock().unwrap().input(),
+    );
+    let mut steps = 0;
+    let termination = loop {
+        if steps >= max_steps {
+            break Termination::Timeout;
+        }
+        steps += 1;
+        let pc = machine.cases[0];
+        machine.next();
+        // The program counter only stays in place on a self jump which is how `stop` is compiled
+        if machine.cases[0] == pc {
+            break Termination::Stopped;
+        }
+        if ctx.lock().unwrap().exhausted() {
+            break Termination::InputExhausted;
+        }
+    };
+    let base = interrupt_place + RETURN_REGISTER as usize;
+    let return_value = (base..base + RETURN_REGISTERS)
+        .map(|x| (machine.get_value(x) % 16) as u8)
+        .collect();
+    let ctx = ctx.lock().unwrap();
+    Execution {
+        name,
+        backend: Backend::Binary,
+        output: ctx.print.chars().collect(),
+        return_value,
+        steps,
+        termination,
+        print_trace: Vec::new(),
+    }
+}
+
+pub enum DivergenceKind {
+    /// Both programs printed something different at this index
+    Output(usize),
+    /// One of the programs stopped while the other one continued printing at this index
+    MissingOutput(usize),
+    ReturnValue,
+}
+
+pub struct Divergence {
+    pub kind: DivergenceKind,
+    pub reference: Execution,
+    pub found: Execution,
+    /// The MIR instruction that printed the diverging character in the reference and in the tested program
+    pub instructions: (Option<Mir>, Option<Mir>),
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |e: &Execution, index: usize| {
+            e.output
+                .get(index)
+                .map(|x| format!("{:?}", x))
+                .unwrap_or_else(|| format!("nothing ({:?})", e.termination))
+        };
+        match self.kind {
+            DivergenceKind::Output(index) | DivergenceKind::MissingOutput(index) => {
+                writeln!(
+                    f,
+                    "{} diverges from {} at output character {}",
+                    self.found.name, self.reference.name, index
+                )?;
+                let start = index.saturating_sub(40);
+                writeln!(
+                    f,
+                    "  common output: {:?}",
+                    self.reference.output[start..index].iter().collect::<String>()
+                )?;
+                writeln!(
+                    f,
+                    "  {} printed {}",
+                    self.reference.name,
+                    show(&self.reference, index)
+                )?;
+                writeln!(f, "  {} printed {}", self.found.name, show(&self.found, index))?;
+            }
+            DivergenceKind::ReturnValue => {
+                writeln!(
+                    f,
+                    "{} returned {:?} but {} returned {:?}",
+                    self.found.name,
+                    self.found.return_value,
+                    self.reference.name,
+                    self.reference.return_value
+                )?;
+            }
+        }
+        if let Some(e) = &self.instructions.0 {
+            writeln!(f, "  MIR instruction in {}: {}", self.reference.name, e)?;
+        }
+        if let Some(e) = &self.instructions.1 {
+            writeln!(f, "  MIR instruction in {}: {}", self.found.name, e)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct DiffReport {
+    pub executions: Vec<Execution>,
+    pub divergences: Vec<Divergence>,
+}
+
+/// Runs the class through the MIR interpreter and the compiled binary, both with and without
+/// optimizations, and compares every run with the unoptimized interpreted one.
+pub fn differential(class_name: &str, input: &str) -> DiffReport {
+    let unoptimized = generate(class_name.to_owned(), Some(RETURN_REGISTER), false);
+    let optimized = unoptimized.clone().optimize_code_new();
+    differential_mir(&unoptimized, &optimized, input, StepLimits::default())
+}
+
+pub fn differential_mir(
+    unoptimized: &MirCodeBlock,
+    optimized: &MirCodeBlock,
+    input: &str,
+    limits: StepLimits,
+) -> DiffReport {
+    let executions = vec![
+        interpret("interpreter (unoptimized)", unoptimized, input, limits.interpreter),
+        interpret("interpreter (optimized)", optimized, input, limits.interpreter),
+        execute_binary("binary (unoptimized)", unoptimized, input, limits.binary),
+        execute_binary(
+            "binary (optimized)",
+            &coalesce_variables(optimized.clone()).0,
+            input,
+            limits.binary,
+        ),
+    ];
+    let mut divergences = Vec::new();
+    for (i, found) in executions.iter().enumerate().skip(1) {
+        let kind = if let Some(e) = compare(&executions[0], found) {
+            e
+        } else {
+            continue;
+        };
+        let index = match kind {
+            DivergenceKind::Output(e) | DivergenceKind::MissingOutput(e) => Some(e),
+            DivergenceKind::ReturnValue => None,
+        };
+        // A binary doesn't know about MIR so the instruction is taken from the interpretation of the same code
+        let counterpart = match found.backend {
+            Backend::Interpreter => i,
+            Backend::Binary => i - 2,
+        };
+        let instruction = |e: &Execution| index.and_then(|x| e.print_trace.get(x).cloned());
+        divergences.push(Divergence {
+            kind,
+            reference: executions[0].clone(),
+            found: found.clone(),
+            instructions: (
+                instruction(&executions[0]),
+                instruction(&executions[counterpart]),
+            ),
+        });
+    }
+    DiffReport {
+        executions,
+        divergences,
+    }
+}
+
+fn compare(reference: &Execution, found: &Execution) -> Option<DivergenceKind> {
+    let (a, b) = (&reference.output, &found.output);
+    if let Some(index) = a.iter().zip(b.iter()).position(|(x, y)| x != y) {
+        return Some(DivergenceKind::Output(index));
+    }
+    // One output is a prefix of the other, this is only an error if the shorter one is complete
+    if a.len() < b.len() && reference.complete() || b.len() < a.len() && found.complete() {
+        return Some(DivergenceKind::MissingOutput(a.len().min(b.len())));
+    }
+    if reference.complete() && found.complete() && reference.return_value != found.return_value {
+        return Some(DivergenceKind::ReturnValue);
+    }
+    None
+}
@@ -0,0 +1,47 @@
+use std::ops::Range;
+
+use mir::{generate_input, generate_program, shrink, GeneratorConfig, MirCodeBlock};
+
+use crate::actions::diff_context::{differential_mir, Divergence, StepLimits};
+
+pub struct FuzzFailure {
+    pub seed: u64,
+    pub input: String,
+    /// The smallest program found that still diverges
+    pub program: MirCodeBlock,
+    pub divergence: Divergence,
+}
+
+/// Generated programs are small, their loops can't run for long
+const LIMITS: StepLimits = StepLimits {
+    interpreter: 1_000_000,
+    binary: 20_000_000,
+};
+
+fn first_divergence(program: &MirCodeBlock, input: &str) -> Option<Divergence> {
+    differential_mir(program, &program.clone().optimize_code_new(), input, LIMITS)
+        .divergences
+        .into_iter()
+        .next()
+}
+
+/// Checks that random programs behave the same when interpreted, optimized and compiled.
+/// Failing programs are shrunk before being reported.
+pub fn fuzz(seeds: Range<u64>) -> Vec<FuzzFailure> {
+    let config = GeneratorConfig::default();
+    seeds
+        .filter_map(|seed| {
+            let input = generate_input(seed);
+            let program = generate_program(seed, &config);
+            first_divergence(&program, &input)?;
+            let program = shrink(program, |x| first_divergence(x, &input).is_some());
+            let divergence = first_divergence(&program, &input)?;
+            Some(FuzzFailure {
+                seed,
+                input,
+                program,
+                divergence,
+            })
+        })
+        .collect()
+}
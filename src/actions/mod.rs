@@ -1,4 +1,10 @@
 pub mod build_context;
+pub mod debug_context;
+pub mod diff_context;
+pub mod fuzz_context;
 pub mod natives;
+pub mod profile_context;
+pub mod report_context;
 pub mod run_context;
+pub mod source_debug_context;
 pub mod test_context;
@@ -0,0 +1,186 @@
+use std::{collections::HashMap, fmt::Display, rc::Rc, sync::Mutex};
+
+use cythan::{
+    format::{FormatError, HeaderData},
+    Cythan, InterruptedCythan, Profile,
+};
+use lir::{DebugEntry, DebugInfo};
+use mir::RunContext;
+
+use crate::actions::diff_context::ScriptedContext;
+
+/// How many rows of each table `Report` prints
+const ROWS: usize = 15;
+
+/// Runs the binary on the machine described by the header until it stops or reads past the end
+/// of the input, and returns the profile of the run with what it printed
+pub fn profile(
+    header: &HeaderData,
+    binary: &[usize],
+    input: &str,
+) -> Result<(Profile, String), FormatError> {
+    let context = Rc::new(Mutex::new(ScriptedContext::new(input)));
+    let (context1, context2) = (context.clone(), context.clone());
+    let mut machine = InterruptedCythan::new(
+        binary.to_vec(),
+        header.base,
+        header.interrupt_place()?,
+        move |a| context1.lock().unwrap().print(a as char),
+        move || context2.lock().unwrap().input(),
+    );
+    let mut profile = Profile::default();
+    loop {
+        let pc = machine.get_value(0);
+        machine.next_traced(&mut profile);
+        // `stop` is compiled to a jump to itself
+        if machine.get_value(0) == pc || context.lock().unwrap().exhausted {
+            break;
+        }
+    }
+    let print = context.lock().unwrap().print.clone();
+    Ok((profile, print))
+}
+
+/// Where the steps of a run were spent, the most expensive first
+pub struct Report {
+    pub steps: usize,
+    /// Steps by LIR instruction, as its address and its code
+    pub lir: Vec<(String, usize)>,
+    /// Steps by MIR node, with the statement it comes from
+    pub mir: Vec<(String, usize)>,
+    /// Steps by method, as the steps of its own code and with the methods it calls
+    pub methods: Vec<(String, usize, usize)>,
+    /// Steps by path of inlined methods separated by `;`, the outlined methods start their paths
+    pub stacks: Vec<(String, usize)>,
+    /// Writes by cell, as its address with its symbol if it has one
+    pub cells: Vec<(String, usize)>,
+}
+
+/// The counts summed by key, the largest first
+fn sorted(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+impl Report {
+    /// Attributes the steps of the profile to the code the debug info maps the addresses to,
+    /// the binary must be compiled with `Compiler::debug_info`
+    pub fn new(debug_info: &DebugInfo, profile: &Profile) -> Self {
+        let mut lir = HashMap::new();
+        let mut mir = HashMap::new();
+        let mut methods: HashMap<String, (usize, usize)> = HashMap::new();
+        let mut stacks = HashMap::new();
+        for (address, count) in profile.executed.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let entry: Option<&DebugEntry> = debug_info.lookup(address);
+            let origin = entry.and_then(|x| x.origin.as_ref());
+            let (lir_key, mir_key) = match (entry, origin) {
+                (Some(e), Some(o)) => (format!("{}\t{}", e.start, e.lir), o.to_string()),
+                (Some(e), None) => (format!("{}\t{}", e.start, e.lir), "<no origin>".to_owned()),
+                (None, _) => ("<unknown>".to_owned(), "<unknown>".to_owned()),
+            };
+            *lir.entry(lir_key).or_default() += count;
+            *mir.entry(mir_key).or_default() += count;
+            let path: &[String] = origin.map(|x| x.methods.as_slice()).unwrap_or_default();
+            let stack = if path.is_empty() {
+                "<unknown>".to_owned()
+            } else {
+                path.join(";")
+            };
+            *stacks.entry(stack).or_default() += count;
+            if let Some(method) = path.last() {
+                methods.entry(method.clone()).or_default().0 += count;
+            }
+            // A recursive path counts its steps once for each method
+            let mut seen: Vec<&String> = Vec::new();
+            for method in path {
+                if !seen.contains(&method) {
+                    seen.push(method);
+                    methods.entry(method.clone()).or_default().1 += count;
+                }
+            }
+        }
+        let names: HashMap<usize, &str> = debug_info
+            .labels
+            .iter()
+            .chain(&debug_info.variables)
+            .map(|(name, address)| (*address, name.as_str()))
+            .collect();
+        let cells = profile
+            .writes
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(cell, count)| match names.get(&cell) {
+                Some(e) => (format!("{} {}", cell, e), *count),
+                None => (cell.to_string(), *count),
+            })
+            .collect();
+        let mut methods: Vec<_> = methods.into_iter().map(|(x, (a, b))| (x, a, b)).collect();
+        methods.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+        Self {
+            steps: profile.steps,
+            lir: sorted(lir),
+            mir: sorted(mir),
+            methods,
+            stacks: sorted(stacks),
+            cells: sorted(cells),
+        }
+    }
+
+    /// The steps by path of methods in the folded stacks format read by the flamegraph tools
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| format!("{} {}\n", stack, count))
+            .collect();
+        stacks.sort();
+        stacks.concat()
+    }
+
+    fn percent(&self, count: usize) -> f64 {
+        count as f64 / self.steps.max(1) as f64 * 100.
+    }
+
+    fn table(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        title: &str,
+        rows: &[(String, usize)],
+    ) -> std::fmt::Result {
+        writeln!(f, "{}:", title)?;
+        for (name, count) in rows.iter().take(ROWS) {
+            writeln!(f, "{:>10} {:>6.2}%  {}", count, self.percent(*count), name)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} steps", self.steps)?;
+        writeln!(f, "Methods (self, total):")?;
+        for (name, own, total) in self.methods.iter().take(ROWS) {
+            writeln!(
+                f,
+                "{:>10} {:>6.2}% {:>10} {:>6.2}%  {}",
+                own,
+                self.percent(*own),
+                total,
+                self.percent(*total),
+                name
+            )?;
+        }
+        self.table(f, "MIR nodes", &self.mir)?;
+        self.table(f, "LIR instructions", &self.lir)?;
+        writeln!(f, "Writes by cell:")?;
+        for (name, count) in self.cells.iter().take(ROWS) {
+            writeln!(f, "{:>10}  {}", count, name)?;
+        }
+        Ok(())
+    }
+}
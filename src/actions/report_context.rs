@@ -0,0 +1,105 @@
+use std::{collections::HashMap, fmt::Display};
+
+use errors::Span;
+use mir::{coalesce_variables, Bound, Estimator, MethodSteps, Steps};
+
+use crate::{Compiler, Diagnostics};
+
+/// The memory and the steps of a program, known without running it
+pub struct BuildReport {
+    /// The variables allocated by the front end
+    pub variables: u32,
+    /// The variables left after the optimizations
+    pub optimized: usize,
+    /// The memory cells of these variables once the variables that are never alive at the same
+    /// time share them
+    pub cells: usize,
+    /// The cells of the binary, with the code and the constants
+    pub binary: usize,
+    /// The steps of the optimized program, `None` if it never stops
+    pub steps: Option<Steps>,
+    /// The steps of each method before the optimizations, the most expensive first
+    pub methods: Vec<(String, MethodSteps)>,
+    /// The amounts the worst cases depend on, `n1` being the first one
+    pub bounds: Vec<Bound>,
+    sources: HashMap<String, String>,
+}
+
+/// Estimates the steps of the methods on the code marked with the calls, and the steps of the
+/// program on the optimized code. The loops copied by the optimizations keep their bound.
+pub fn build_report(compiler: Compiler) -> Result<BuildReport, Diagnostics> {
+    let compiler = compiler.debug_info(true).debug_scopes(true);
+    let (scoped, variables) = compiler.generate_with_variables()?;
+    let sources = compiler.sources().iter().cloned().collect();
+    let mut estimator = Estimator::default();
+    estimator.estimate(&scoped);
+    let artifacts = compiler.debug_scopes(false).compile()?;
+    let steps = estimator.estimate(&artifacts.mir);
+    let (_, coalesced) = coalesce_variables(artifacts.mir);
+    let mut methods: Vec<_> = estimator.methods.into_iter().collect();
+    methods.sort_by(|a, b| {
+        let worst = |x: &MethodSteps| (!x.steps.worst.is_fixed(), x.steps.worst.fixed());
+        worst(&b.1).cmp(&worst(&a.1)).then_with(|| a.0.cmp(&b.0))
+    });
+    Ok(BuildReport {
+        variables,
+        optimized: coalesced.variables,
+        cells: coalesced.slots,
+        binary: artifacts.binary.len(),
+        steps,
+        methods,
+        bounds: estimator.bounds,
+        sources,
+    })
+}
+
+impl BuildReport {
+    fn describe(&self, bound: &Bound) -> String {
+        match bound {
+            Bound::Loop(Some(span)) => format!("iterations of the loop at {}", self.line(span)),
+            Bound::Loop(None) => "iterations of a loop".to_owned(),
+            Bound::Recursion(name) => format!("steps of the recursive calls of {}", name),
+        }
+    }
+
+    fn line(&self, span: &Span) -> String {
+        let text = self.sources.get(&span.file);
+        match text.and_then(|x| x.get(..span.start)) {
+            Some(e) => {
+                let number = e.matches('\n').count();
+                let line = text.unwrap().lines().nth(number).unwrap_or("");
+                format!("{}:{}: {}", span.file, number + 1, line.trim())
+            }
+            None => format!("{}:{}..{}", span.file, span.start, span.end),
+        }
+    }
+}
+
+impl Display for BuildReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Memory: {} variables allocated, {} after the optimizations in {} cells, {} cells in \
+             the binary",
+            self.variables, self.optimized, self.cells, self.binary
+        )?;
+        match &self.steps {
+            Some(e) => writeln!(f, "Steps: {}", e)?,
+            None => writeln!(f, "Steps: the program never stops")?,
+        }
+        writeln!(
+            f,
+            "Steps by call of each method, before the optimizations (sites, steps):"
+        )?;
+        for (name, method) in &self.methods {
+            writeln!(f, "{:>6}  {}  {}", method.sites, name, method.steps)?;
+        }
+        if !self.bounds.is_empty() {
+            writeln!(f, "Where:")?;
+        }
+        for (i, bound) in self.bounds.iter().enumerate() {
+            writeln!(f, "  n{} is the {}", i + 1, self.describe(bound))?;
+        }
+        Ok(())
+    }
+}
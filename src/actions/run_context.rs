@@ -1,10 +1,13 @@
 use std::{rc::Rc, sync::Mutex};
 
-use cythan::{Cythan, InterruptedCythan};
+use cythan::{
+    format::{FormatError, HeaderData},
+    Cythan, InterruptedCythan,
+};
 use lir::CompilableInstruction;
-use mir::{MemoryState, MirCodeBlock, MirState, RunContext};
+use mir::{coalesce_variables, MemoryState, MirCodeBlock, RunContext};
 
-use crate::MIR_MODE;
+use crate::{lower, MIR_MODE};
 
 pub fn run<T: RunContext + 'static>(mir: &MirCodeBlock, car: T) -> (usize, Rc<Mutex<T>>) {
     if MIR_MODE {
@@ -13,35 +16,47 @@ pub fn run<T: RunContext + 'static>(mir: &MirCodeBlock, car: T) -> (usize, Rc<Mu
         ms.execute_block(mir, &mut *car.lock().unwrap());
         (ms.instr_count, car)
     } else {
-        let mut mirstate = MirState::default();
-        mir.to_asm(&mut mirstate);
-        mirstate.opt_asm();
-        let k = CompilableInstruction::compile_to_binary(mirstate.instructions);
+        let (mir, _) = coalesce_variables(mir.clone());
+        let k = CompilableInstruction::compile_to_binary(lower(&mir));
         run_bin(&k, car)
     }
 }
 
-pub fn compute_max_bin(k: &[usize]) -> (usize, Vec<usize>) {
+/// Runs the binary until it waits for an input or stops, on the machine described by the header
+pub fn compute_max_bin(
+    header: &HeaderData,
+    k: &[usize],
+) -> Result<(usize, Vec<usize>), FormatError> {
     let mut machine =
-        InterruptedCythan::new_stdio(k.to_vec(), 4, 2 * 2_usize.pow(4 /* base */) + 3);
+        InterruptedCythan::new_stdio(k.to_vec(), header.base, header.interrupt_place()?);
     let mut k = 0;
     loop {
         k += 1;
         let a = machine.cases.clone();
         if machine.next_get_interupt() || a == machine.cases {
-            return (k, a);
+            return Ok((k, a));
         }
     }
 }
 
+/// Runs a binary made by the compiler
 pub fn run_bin<T: RunContext + 'static>(k: &[usize], car: T) -> (usize, Rc<Mutex<T>>) {
+    run_bin_with(&HeaderData::default(), k, car).expect("The compiler targets a supported machine")
+}
+
+/// Runs a binary on the machine described by its header
+pub fn run_bin_with<T: RunContext + 'static>(
+    header: &HeaderData,
+    k: &[usize],
+    car: T,
+) -> Result<(usize, Rc<Mutex<T>>), FormatError> {
     let car = Rc::new(Mutex::new(car));
     let car1 = car.clone();
     let car2 = car.clone();
     let mut machine = InterruptedCythan::new(
         k.to_vec(),
-        4,
-        2 * 2_usize.pow(4 /* base */) + 3,
+        header.base,
+        header.interrupt_place()?,
         move |a| {
             car.lock().unwrap().print(a as char);
         },
@@ -56,5 +71,5 @@ pub fn run_bin<T: RunContext + 'static>(k: &[usize], car: T) -> (usize, Rc<Mutex
             break;
         }
     }
-    (k, car2)
+    Ok((k, car2))
 }
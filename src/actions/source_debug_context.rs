@@ -0,0 +1,404 @@
+use std::{collections::HashMap, fmt::Write};
+
+use errors::Span;
+use mir::{DebugMarker, MemoryState, Mir, MirCodeBlock, RunContext};
+
+use crate::actions::diff_context::ScriptedContext;
+
+const HELP: &str = "step                run until the next statement
+next                run until the next statement of this method outside of the ifs, loops and
+                    matches of the current statement
+finish              run until the current method returns
+continue            run until a breakpoint or the end
+break <location>    stop at a line like Pendu.ct:12 or when a method like Val.print is called
+delete <n>          remove the breakpoint number n
+info                list the breakpoints
+where               print the methods being called, the inlined ones included
+print <variable>    print the value of a variable of the current method
+locals              print the variables of the current method
+list                print the lines around the current statement
+quit                leave the debugger";
+
+/// Shows its argument and reads the next command, `None` when there are no more commands
+type Prompt = Box<dyn FnMut(&str) -> Option<String>>;
+
+/// Where the execution stops
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    /// A line of a file, the file can be given without its directory
+    Line { file: String, line: usize },
+    /// A method, with or without its class
+    Method(String),
+}
+
+impl Breakpoint {
+    fn parse(word: &str) -> Result<Self, String> {
+        match word.rsplit_once(':') {
+            Some((file, line)) => Ok(Self::Line {
+                file: file.to_owned(),
+                line: line.parse().map_err(|_| format!("Invalid line {}", line))?,
+            }),
+            None => Ok(Self::Method(word.to_owned())),
+        }
+    }
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Line { file, line } => write!(f, "{}:{}", file, line),
+            Self::Method(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+struct Variable {
+    name: String,
+    ty: String,
+    cells: Vec<u32>,
+    /// The depth of the block declaring it, the variable is gone once the block ends
+    depth: usize,
+}
+
+/// A call of a method, inlined or not
+struct Frame {
+    method: String,
+    call: Span,
+    depth: usize,
+    variables: Vec<Variable>,
+    /// The statement being run
+    location: Option<Span>,
+}
+
+/// When the execution stops next
+#[derive(Clone, Copy)]
+enum Mode {
+    Step,
+    /// At a statement of a method at most `frames` deep and in at most `depth` blocks
+    Next {
+        frames: usize,
+        depth: usize,
+    },
+    /// At a statement of a method less than `frames` deep
+    Finish {
+        frames: usize,
+    },
+    Continue,
+    Quit,
+}
+
+/// Runs the MIR of a program with the debug markers of `Compiler::debug_scopes` and the locations
+/// of `Compiler::debug_info`, stopping at its statements to run the commands of the user
+pub struct SourceDebugger {
+    context: ScriptedContext,
+    sources: HashMap<String, String>,
+    prompt: Prompt,
+    pub breakpoints: Vec<Breakpoint>,
+    frames: Vec<Frame>,
+    mode: Mode,
+    /// Why the execution stops at the next statement, set when a method breakpoint is hit
+    pending: Option<String>,
+    /// The characters of the output already shown
+    shown: usize,
+}
+
+impl SourceDebugger {
+    /// Stops at the first statement, `input` holds the characters read by the program
+    pub fn new(
+        sources: &[(String, String)],
+        input: &str,
+        prompt: impl FnMut(&str) -> Option<String> + 'static,
+    ) -> Self {
+        Self {
+            context: ScriptedContext::new(input),
+            sources: sources.iter().cloned().collect(),
+            prompt: Box::new(prompt),
+            breakpoints: Vec::new(),
+            frames: Vec::new(),
+            mode: Mode::Step,
+            pending: None,
+            shown: 0,
+        }
+    }
+
+    /// Runs the program until it ends or the user quits, and tells how it ended
+    pub fn run(&mut self, mir: &MirCodeBlock) -> String {
+        let mut state = MemoryState::new(2048, 16);
+        state.execute_block(mir, self);
+        let mut out = match self.mode {
+            Mode::Quit => "Quit".to_owned(),
+            _ if self.context.exhausted => "The input is exhausted".to_owned(),
+            _ => "The program ended".to_owned(),
+        };
+        self.output(&mut out);
+        out
+    }
+
+    /// The line holding the start of the span, as its number from 1 and its text
+    fn line(&self, span: &Span) -> Option<(usize, &str)> {
+        let text = self.sources.get(&span.file)?.get(..span.start)?;
+        let number = text.matches('\n').count();
+        let line = self.sources[&span.file].lines().nth(number).unwrap_or("");
+        Some((number + 1, line))
+    }
+
+    fn describe(&self, span: &Span) -> String {
+        match self.line(span) {
+            Some((number, line)) => format!("{}:{}: {}", span.file, number, line.trim()),
+            None => format!("{}:{}..{}", span.file, span.start, span.end),
+        }
+    }
+
+    fn location(&self) -> Option<&Span> {
+        self.frames.last()?.location.as_ref()
+    }
+
+    fn hits(&self, span: &Span) -> bool {
+        let number = match self.line(span) {
+            Some((e, _)) => e,
+            None => return false,
+        };
+        self.breakpoints.iter().any(|x| match x {
+            Breakpoint::Line { file, line } => {
+                *line == number
+                    && (span.file == *file || span.file.ends_with(&format!("/{}", file)))
+            }
+            Breakpoint::Method(_) => false,
+        })
+    }
+
+    fn stops(&self, state: &MemoryState) -> bool {
+        match self.mode {
+            Mode::Step => true,
+            Mode::Next { frames, depth } => {
+                self.frames.len() < frames || (self.frames.len() == frames && state.depth <= depth)
+            }
+            Mode::Finish { frames } => self.frames.len() < frames,
+            Mode::Continue | Mode::Quit => false,
+        }
+    }
+
+    /// Appends what the program printed since the last time
+    fn output(&mut self, out: &mut String) {
+        if self.context.print.len() > self.shown {
+            write!(out, "\nOutput: {:?}", &self.context.print[self.shown..]).unwrap();
+            self.shown = self.context.print.len();
+        }
+    }
+
+    fn value(&self, variable: &Variable, state: &MemoryState) -> String {
+        let digits: Vec<u8> = variable.cells.iter().map(|x| state.get_mem(*x)).collect();
+        let value = match digits.as_slice() {
+            [e] => e.to_string(),
+            e => format!("{:?}", e),
+        };
+        format!("{} {} = {}", variable.ty, variable.name, value)
+    }
+
+    fn backtrace(&self) -> String {
+        let mut out = String::new();
+        for (i, frame) in self.frames.iter().rev().enumerate() {
+            write!(out, "#{} {}", i, frame.method).unwrap();
+            if let Some(e) = &frame.location {
+                write!(out, " at {}", self.describe(e)).unwrap();
+            }
+            // `main` and the methods called by the natives have no call in the sources
+            if self.sources.contains_key(&frame.call.file) {
+                write!(out, "\n   called from {}", self.describe(&frame.call)).unwrap();
+            }
+            out.push('\n');
+        }
+        out.pop();
+        out
+    }
+
+    fn list(&self) -> Result<String, String> {
+        let span = self.location().ok_or("No statement is running")?;
+        let (number, _) = self.line(span).ok_or("The source is unknown")?;
+        Ok(self.sources[&span.file]
+            .lines()
+            .enumerate()
+            .skip(number.saturating_sub(3))
+            .take(5)
+            .map(|(i, line)| {
+                let marker = if i + 1 == number { ">" } else { " " };
+                format!("{}{:>4} {}", marker, i + 1, line)
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Runs one line of the debugger, `Ok(None)` when the execution resumes
+    fn command(&mut self, line: &str, state: &MemoryState) -> Result<Option<String>, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let argument = |i: usize| -> Result<&str, String> {
+            words
+                .get(i)
+                .copied()
+                .ok_or_else(|| "Missing argument".to_owned())
+        };
+        let frames = self.frames.len();
+        self.mode = match words.first().copied().unwrap_or("") {
+            "step" | "s" => Mode::Step,
+            "next" | "n" => Mode::Next {
+                frames,
+                depth: state.depth,
+            },
+            "finish" | "f" => Mode::Finish { frames },
+            "continue" | "c" => Mode::Continue,
+            "quit" | "q" => Mode::Quit,
+            "break" | "b" => {
+                let breakpoint = Breakpoint::parse(argument(1)?)?;
+                self.breakpoints.push(breakpoint);
+                return Ok(Some(format!(
+                    "Breakpoint {} at {}",
+                    self.breakpoints.len(),
+                    self.breakpoints.last().unwrap()
+                )));
+            }
+            "delete" => {
+                let word = argument(1)?;
+                let i: usize = word
+                    .parse()
+                    .ok()
+                    .filter(|x| (1..=self.breakpoints.len()).contains(x))
+                    .ok_or_else(|| format!("No breakpoint {}", word))?;
+                let breakpoint = self.breakpoints.remove(i - 1);
+                return Ok(Some(format!("Removed the breakpoint at {}", breakpoint)));
+            }
+            "info" => {
+                return Ok(Some(
+                    self.breakpoints
+                        .iter()
+                        .enumerate()
+                        .map(|(i, x)| format!("{}: {}", i + 1, x))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                ))
+            }
+            "where" | "bt" => return Ok(Some(self.backtrace())),
+            "print" | "p" => {
+                let name = argument(1)?;
+                let variable = self
+                    .frames
+                    .last()
+                    .and_then(|x| x.variables.iter().rev().find(|x| x.name == name))
+                    .ok_or_else(|| format!("Unknown variable {}", name))?;
+                return Ok(Some(self.value(variable, state)));
+            }
+            "locals" => {
+                let frame = self.frames.last().ok_or("No method is running")?;
+                // The variables declared last shadow the ones with the same name
+                let mut names: Vec<&str> = Vec::new();
+                let mut out: Vec<String> = Vec::new();
+                for variable in frame.variables.iter().rev() {
+                    if !names.contains(&variable.name.as_str()) {
+                        names.push(&variable.name);
+                        out.push(self.value(variable, state));
+                    }
+                }
+                out.reverse();
+                return Ok(Some(out.join("\n")));
+            }
+            "list" | "l" => return self.list().map(Some),
+            "help" | "" => return Ok(Some(HELP.to_owned())),
+            e => return Err(format!("Unknown command {}, try help", e)),
+        };
+        Ok(None)
+    }
+
+    /// Shows why the execution stopped and runs the commands until one resumes it
+    fn pause(&mut self, reason: String, state: &MemoryState) {
+        let mut out = reason;
+        self.output(&mut out);
+        loop {
+            let line = match (self.prompt)(&out) {
+                Some(e) => e,
+                None => {
+                    self.mode = Mode::Quit;
+                    return;
+                }
+            };
+            match self.command(&line, state) {
+                Ok(Some(e)) | Err(e) => out = e,
+                Ok(None) => return,
+            }
+        }
+    }
+}
+
+impl RunContext for SourceDebugger {
+    fn input(&mut self) -> u8 {
+        self.context.input()
+    }
+
+    fn print(&mut self, i: char) {
+        self.context.print(i)
+    }
+
+    fn exhausted(&self) -> bool {
+        self.context.exhausted()
+    }
+
+    fn instruction(&mut self, mir: &Mir, state: &MemoryState) -> bool {
+        // The blocks left since the last instruction take their calls and variables with them
+        self.frames.retain(|x| x.depth <= state.depth);
+        if let Some(frame) = self.frames.last_mut() {
+            frame.variables.retain(|x| x.depth <= state.depth);
+        }
+        match mir {
+            Mir::Debug(DebugMarker::Enter { method, call }) => {
+                let hit = self.breakpoints.iter().any(|x| match x {
+                    Breakpoint::Method(e) => method == e || method.ends_with(&format!(".{}", e)),
+                    Breakpoint::Line { .. } => false,
+                });
+                if hit {
+                    self.pending = Some(format!("Breakpoint at {}", method));
+                }
+                self.frames.push(Frame {
+                    method: method.clone(),
+                    call: call.clone(),
+                    depth: state.depth,
+                    variables: Vec::new(),
+                    location: None,
+                });
+            }
+            Mir::Debug(DebugMarker::Leave) => {
+                self.frames.pop();
+            }
+            Mir::Debug(DebugMarker::Variable { name, ty, cells }) => {
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.variables.push(Variable {
+                        name: name.clone(),
+                        ty: ty.clone(),
+                        cells: cells.clone(),
+                        depth: state.depth,
+                    });
+                }
+            }
+            Mir::Location(span, _) => {
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.location = Some(span.clone());
+                }
+                let reason = if let Some(e) = self.pending.take() {
+                    Some(e)
+                } else if self.hits(span) {
+                    Some("Breakpoint".to_owned())
+                } else if self.stops(state) {
+                    Some(String::new())
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    let mut out = self.describe(span);
+                    if !reason.is_empty() {
+                        out = format!("{} at {}", reason, out);
+                    }
+                    self.pause(out, state);
+                }
+            }
+            _ => (),
+        }
+        !matches!(self.mode, Mode::Quit)
+    }
+}
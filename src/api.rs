@@ -0,0 +1,338 @@
+use std::{collections::HashMap, fmt::Display};
+
+use cythan::format::{
+    Container, DebugRange, HeaderData, SourceFile, SourceLocation, Symbol, Symbols,
+};
+use either::Either;
+use errors::{render, Error, Span, SpannedObject};
+use lir::{CompilableInstruction, DebugInfo};
+use mir::{coalesce_variables, Mir, MirCodeBlock, MirState, OptLevel, PassManager};
+
+use crate::{
+    actions::natives::load_natives,
+    compiler::{
+        class_loader::{ClassLoader, DEFAULT_MAX_NESTING},
+        state::{code_manager::CodeManager, local_state::LocalState},
+    },
+    parser::ty::Type,
+};
+
+/// The classes every program can use, loaded by `Compiler::new`
+const STD: &[(&str, &str)] = &[
+    ("std/Array.ct", include_str!("../std/Array.ct")),
+    ("std/Bool.ct", include_str!("../std/Bool.ct")),
+    ("std/Byte.ct", include_str!("../std/Byte.ct")),
+    ("std/DynArray.ct", include_str!("../std/DynArray.ct")),
+    ("std/Option.ct", include_str!("../std/Option.ct")),
+    ("std/System.ct", include_str!("../std/System.ct")),
+    ("std/Val.ct", include_str!("../std/Val.ct")),
+];
+
+/// Compiles programs from sources held in memory, without touching the filesystem
+///
+/// ```ignore
+/// let artifacts = Compiler::new()
+///     .add_source("Main.ct", "class Main { Val main() { return 0; } }")
+///     .entry("Main")
+///     .opt_level(OptLevel::O2)
+///     .compile()?;
+/// ```
+pub struct Compiler {
+    sources: Vec<(String, String)>,
+    entry: String,
+    optimizer: PassManager,
+    return_register: Option<u8>,
+    max_nesting: usize,
+    debug_info: bool,
+    debug_scopes: bool,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    /// A compiler with the standard classes, whose entry is the `main` method of `Main`
+    pub fn new() -> Self {
+        Self {
+            sources: STD
+                .iter()
+                .map(|(name, text)| (name.to_string(), text.to_string()))
+                .collect(),
+            entry: "Main".to_owned(),
+            optimizer: PassManager::new(OptLevel::O2),
+            return_register: None,
+            max_nesting: DEFAULT_MAX_NESTING,
+            debug_info: false,
+            debug_scopes: false,
+        }
+    }
+
+    /// Adds a file holding one class, replaces the source with the same name if there is one
+    pub fn add_source(mut self, name: impl Into<String>, text: impl Into<String>) -> Self {
+        let (name, text) = (name.into(), text.into());
+        match self.sources.iter_mut().find(|x| x.0 == name) {
+            Some(e) => e.1 = text,
+            None => self.sources.push((name, text)),
+        }
+        self
+    }
+
+    /// The class whose `main` method is the program
+    pub fn entry(mut self, class: impl Into<String>) -> Self {
+        self.entry = class.into();
+        self
+    }
+
+    pub fn opt_level(mut self, level: OptLevel) -> Self {
+        self.optimizer = PassManager::new(level);
+        self
+    }
+
+    /// Replaces the passes chosen by the optimization level
+    pub fn optimizer(mut self, optimizer: PassManager) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    /// Writes the value returned by `main` to the registers starting at this one before the
+    /// program stops
+    pub fn return_register(mut self, register: u8) -> Self {
+        self.return_register = Some(register);
+        self
+    }
+
+    /// How deeply the code can be nested before it is reported as an error
+    pub fn max_nesting(mut self, max_nesting: usize) -> Self {
+        self.max_nesting = max_nesting;
+        self
+    }
+
+    /// Maps the addresses of the binary back to the code, optimizations can move or merge the
+    /// locations of the statements
+    pub fn debug_info(mut self, debug_info: bool) -> Self {
+        self.debug_info = debug_info;
+        self
+    }
+
+    /// Marks the calls and the variables in the MIR for the source debugger, the optimizations
+    /// move the variables so the markers only hold for the code returned by `generate`
+    pub fn debug_scopes(mut self, debug_scopes: bool) -> Self {
+        self.debug_scopes = debug_scopes;
+        self
+    }
+
+    /// The files the classes are loaded from, as `(name, text)`
+    pub fn sources(&self) -> &[(String, String)] {
+        &self.sources
+    }
+
+    /// Compiles the entry class to MIR without optimizing it
+    pub fn generate(&self) -> Result<MirCodeBlock, Diagnostics> {
+        self.generate_with_variables().map(|x| x.0)
+    }
+
+    /// Like `generate`, also gives how many variables the front end allocated
+    pub fn generate_with_variables(&self) -> Result<(MirCodeBlock, u32), Diagnostics> {
+        self.generate_mir().map_err(|e| self.diagnostics(e))
+    }
+
+    fn generate_mir(&self) -> Result<(MirCodeBlock, u32), Error> {
+        let mut cl = ClassLoader::new();
+        cl.max_nesting = self.max_nesting;
+        for (name, text) in &self.sources {
+            cl.load_string(text, name)?;
+        }
+        load_natives(&mut cl);
+
+        let main = cl
+            .view(&Type::simple(&self.entry, Span::default()))?
+            .method_view(&SpannedObject(Span::default(), "main".to_owned()), &None)?;
+        let mut cm = CodeManager::new(cl);
+        cm.debug_info = self.debug_info;
+        cm.debug_scopes = self.debug_scopes;
+        let rs = main.execute(&mut LocalState::new(), &mut cm, vec![])?;
+        let mut mir = rs.mir;
+        if let (Some(register), Some(value)) = (self.return_register, rs.return_value) {
+            for (i, location) in value.locations.iter().enumerate() {
+                mir.add_mir(Mir::WriteRegister(
+                    register + i as u8,
+                    Either::Right(*location),
+                ));
+            }
+        }
+        mir.add_mir(Mir::Stop);
+        Ok((mir, cm.allocated()))
+    }
+
+    /// Compiles and optimizes the entry class down to the binary run by the machine
+    pub fn compile(mut self) -> Result<Artifacts, Diagnostics> {
+        let mir = self.optimizer.run(self.generate()?);
+        let lir = lower_with(&coalesce_variables(mir.clone()).0, self.debug_info);
+        let assembly = CompilableInstruction::compile_to_string(lir.clone());
+        let (binary, debug_info) = if self.debug_info {
+            let (binary, debug_info) = CompilableInstruction::compile_with_debug_info(lir.clone());
+            (binary, Some(debug_info))
+        } else {
+            (CompilableInstruction::compile_to_binary(lir.clone()), None)
+        };
+        let sources = match &debug_info {
+            Some(debug_info) => referenced_sources(debug_info, |name| {
+                self.sources
+                    .iter()
+                    .find(|(x, _)| x == name)
+                    .map(|(_, text)| text.clone())
+            }),
+            None => Vec::new(),
+        };
+        Ok(Artifacts {
+            mir,
+            lir,
+            assembly,
+            binary,
+            debug_info,
+            sources,
+        })
+    }
+
+    fn diagnostics(&self, error: Error) -> Diagnostics {
+        let sources: HashMap<String, String> = self.sources.iter().cloned().collect();
+        Diagnostics {
+            reports: vec![render(error, &sources)],
+        }
+    }
+}
+
+/// Everything a compilation produced
+pub struct Artifacts {
+    /// The optimized MIR
+    pub mir: MirCodeBlock,
+    /// The instructions the MIR is lowered to, after the variables share memory cells
+    pub lir: Vec<CompilableInstruction>,
+    /// The Cythan assembly (`.ct`) of the LIR
+    pub assembly: String,
+    /// The memory of the machine when the program starts
+    pub binary: Vec<usize>,
+    /// Where the code at each address comes from, when `Compiler::debug_info` is set
+    pub debug_info: Option<DebugInfo>,
+    /// The sources the debug info points to, as `(name, text)`
+    pub sources: Vec<(String, String)>,
+}
+
+impl Artifacts {
+    /// The binary as a `.cct` file, with the symbols, the debug info and the sources if there is
+    /// debug info
+    pub fn encode(&self) -> Vec<u8> {
+        container(&self.binary, self.debug_info.as_ref(), &self.sources)
+            .encode()
+            .expect("Could not create binary")
+    }
+}
+
+/// The sources the spans of the debug info point to, read with `read`
+pub fn referenced_sources(
+    debug_info: &DebugInfo,
+    read: impl Fn(&str) -> Option<String>,
+) -> Vec<(String, String)> {
+    let mut files: Vec<&str> = debug_info
+        .entries
+        .iter()
+        .filter_map(|x| Some(x.origin.as_ref()?.span.as_ref()?.file.as_str()))
+        .collect();
+    files.sort_unstable();
+    files.dedup();
+    files
+        .into_iter()
+        .filter_map(|name| Some((name.to_owned(), read(name)?)))
+        .collect()
+}
+
+/// The `.cct` container of a binary, with the sections made from the debug info if there is some
+pub fn container(
+    binary: &[usize],
+    debug_info: Option<&DebugInfo>,
+    sources: &[(String, String)],
+) -> Container {
+    let mut container = Container::new(
+        HeaderData::default(),
+        binary.iter().map(|x| *x as u32).collect(),
+    );
+    let debug_info = match debug_info {
+        Some(e) => e,
+        None => return container,
+    };
+    let symbols = |list: &[(String, usize)]| {
+        list.iter()
+            .map(|(name, address)| Symbol {
+                name: name.clone(),
+                address: *address as u32,
+            })
+            .collect()
+    };
+    container.symbols = Some(Symbols {
+        labels: symbols(&debug_info.labels),
+        variables: symbols(&debug_info.variables),
+    });
+    let ranges = debug_info.entries.iter().map(|x| DebugRange {
+        start: x.start as u32,
+        end: x.end as u32,
+        lir: x.lir.clone(),
+        mir: x.origin.as_ref().map(|x| x.mir.clone()).unwrap_or_default(),
+        location: x
+            .origin
+            .as_ref()
+            .and_then(|x| x.span.as_ref())
+            .map(|x| SourceLocation {
+                file: x.file.clone(),
+                start: x.start as u32,
+                end: x.end as u32,
+            }),
+    });
+    container.debug_info = Some(ranges.collect());
+    container.sources = Some(
+        sources
+            .iter()
+            .map(|(name, text)| SourceFile {
+                name: name.clone(),
+                text: text.clone(),
+            })
+            .collect(),
+    );
+    container
+}
+
+/// The errors that stopped a compilation, rendered with the sources they point to
+#[derive(Debug)]
+pub struct Diagnostics {
+    pub reports: Vec<String>,
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for report in &self.reports {
+            writeln!(f, "{}", report)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+/// Lowers the MIR to LIR with the outlined methods after the code
+pub fn lower(mir: &MirCodeBlock) -> Vec<CompilableInstruction> {
+    lower_with(mir, false)
+}
+
+/// Lowers the MIR to LIR, with the origin of the instructions if `debug_info` is set
+pub fn lower_with(mir: &MirCodeBlock, debug_info: bool) -> Vec<CompilableInstruction> {
+    let mut mirstate = MirState {
+        debug_info,
+        ..MirState::default()
+    };
+    mir.to_asm(&mut mirstate);
+    mirstate.emit_procedures();
+    mirstate.opt_asm();
+    mirstate.instructions
+}
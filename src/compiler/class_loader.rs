@@ -1,5 +1,7 @@
 use std::{
+    cell::RefCell,
     collections::{HashMap, VecDeque},
+    fmt::Debug,
     rc::Rc,
 };
 
@@ -8,6 +10,7 @@ use errors::{report_similar, Error, SpannedObject};
 
 use crate::parser::{
     class::{Class, ClassView},
+    expression::check_nesting,
     method::{Method, MethodView},
     parse,
     ty::Type,
@@ -16,10 +19,26 @@ use crate::parser::{
 
 use super::state::{code_manager::CodeManager, local_state::LocalState, output_data::OutputData};
 
-#[derive(Debug)]
+/// How deeply the blocks and expressions can be nested by default, a level takes up to about 30 KiB
+/// of stack in debug builds so the deepest code still fits in the 2 MiB of a new thread
+pub const DEFAULT_MAX_NESTING: usize = 48;
+
 pub struct ClassLoader {
     classes: Vec<Class>,
     pub constants: HashMap<String, (Type, Vec<u8>)>,
+    /// The views of the classes already resolved by type with the templates applied
+    views: RefCell<HashMap<Type, Rc<ClassView>>>,
+    /// Bounds the recursion of the parser and of the compiler
+    pub max_nesting: usize,
+}
+
+impl Debug for ClassLoader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClassLoader")
+            .field("classes", &self.classes)
+            .field("constants", &self.constants)
+            .finish()
+    }
 }
 
 impl ClassLoader {
@@ -27,6 +46,8 @@ impl ClassLoader {
         ClassLoader {
             classes: Vec::new(),
             constants: HashMap::new(),
+            views: RefCell::new(HashMap::new()),
+            max_nesting: DEFAULT_MAX_NESTING,
         }
     }
 
@@ -45,11 +66,17 @@ impl ClassLoader {
         let mut vdc = VecDeque::new();
         let mut k: VecDeque<char> = class.chars().filter(|x| *x != '\r').collect();
         let kl = k.len();
-        parse(&mut vdc, &mut k, kl, filename)?;
+        parse(&mut vdc, &mut k, kl, filename, self.max_nesting)?;
         /* for i in &vdc {
             display(i, class);
         } */
-        self.load(vdc.parse(&Type::native_simple("Self provider"))?);
+        let class: Class = vdc.parse(&Type::native_simple("Self provider"))?;
+        for method in &class.methods {
+            if let Either::Left(code) = &method.code {
+                check_nesting(code, self.max_nesting)?;
+            }
+        }
+        self.load(class);
         Ok(())
     }
 
@@ -87,6 +114,7 @@ impl ClassLoader {
             }
         }
         self.classes.push(class);
+        self.views.get_mut().clear();
     }
 
     pub fn get(&self, name: &SpannedObject<String>) -> Result<&Class, Error> {
@@ -109,12 +137,18 @@ impl ClassLoader {
             })
     }
 
-    pub fn view(&self, ty: &Type) -> Result<ClassView, Error> {
-        ClassView::new(self.get(&ty.name)?, ty)
+    pub fn view(&self, ty: &Type) -> Result<Rc<ClassView>, Error> {
+        if let Some(e) = self.views.borrow().get(ty) {
+            return Ok(e.clone());
+        }
+        let view = Rc::new(ClassView::new(self.get(&ty.name)?, ty)?);
+        self.views.borrow_mut().insert(ty.clone(), view.clone());
+        Ok(view)
     }
 
     #[allow(dead_code)]
     pub fn inject_method(&mut self, arg: &str, method: Method) {
+        self.views.get_mut().clear();
         self.classes
             .iter_mut()
             .find(|c| c.name.1 == arg)
@@ -124,6 +158,7 @@ impl ClassLoader {
     }
 
     pub fn get_class_mut(&mut self, arg: &str) -> &mut Class {
+        self.views.get_mut().clear();
         self.classes.iter_mut().find(|x| x.name.1 == arg).unwrap()
     }
 }
@@ -1,13 +1,13 @@
 use errors::{
-    in_method, invalid_type, method_return_type_invalid, report_similar, Error, Span,
-    SpannedObject, SpannedVector,
+    in_method, invalid_type, method_return_type_invalid, nesting_too_deep, report_similar, Error,
+    Span, SpannedObject, SpannedVector,
 };
 use mir::{Mir, MirCodeBlock};
 
 use crate::{
     compiler::state::typed_definition::{CheckAgainst, TypedMemory},
     parser::{
-        expression::{BooleanOperator, Expr},
+        expression::{BooleanOperator, CodeBlock, Expr},
         ty::Type,
         NumberType,
     },
@@ -21,16 +21,17 @@ pub fn compile_code_block(
     cm: &mut CodeManager,
     span: Span,
 ) -> Result<OutputData, Error> {
-    expr.1.iter().fold(
-        Ok(OutputData::new(MirCodeBlock::default(), span, None)),
-        |acc, expr| {
-            let mut acc = acc?;
-            let expr = compile(expr, ls, cm, None)?;
-            acc.mir.add(expr.mir);
-            acc.return_value = expr.return_value;
-            Ok(acc)
-        },
-    )
+    let mut out = OutputData::new(MirCodeBlock::default(), span, None);
+    for expr in &expr.1 {
+        let k = compile(expr, ls, cm, None)?;
+        if cm.debug_info && !k.mir.is_empty() {
+            out.mir
+                .add_mir(Mir::Location(expr.full_span(), cm.methods()));
+        }
+        out.mir.add(k.mir);
+        out.return_value = k.return_value;
+    }
+    Ok(out)
 }
 
 pub fn compile(
@@ -39,344 +40,74 @@ pub fn compile(
     cm: &mut CodeManager,
     expected_type: Option<Type>,
 ) -> Result<OutputData, Error> {
-    let mut mir = MirCodeBlock::default();
+    if cm.depth >= cm.cl.max_nesting {
+        return Err(nesting_too_deep(expr.span(), cm.cl.max_nesting));
+    }
+    cm.depth += 1;
+    let out = compile_expr(expr, ls, cm, expected_type);
+    cm.depth -= 1;
+    out
+}
+
+fn compile_expr(
+    expr: &Expr,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+    expected_type: Option<Type>,
+) -> Result<OutputData, Error> {
+    // Every kind is compiled in its own function, the frame of this one is on the stack once per
+    // nesting level so it must stay small
     match expr {
         Expr::New {
             span,
             class,
             fields,
-        } => {
-            // TODO: Set expected type to correct value.
-            let class = class.apply_expected(&expected_type);
-            let view = cm.cl.view(&class)?;
-            let fields = fields
-                .1
-                .iter()
-                .map(|(a, b)| {
-                    Ok((
-                        a,
-                        compile(b, ls, cm, Some(view.get_field_type(a, b.span())?))?,
-                    ))
-                })
-                .collect::<Result<Vec<_>, _>>()?;
-            // get_field
-
-            let instr = view
-                .fields
-                .iter()
-                .map(|x| {
-                    let field = if let Some(e) = fields.iter().find(|(a, _)| *a == &x.name.1) {
-                        e
-                    } else {
-                        return Err(report_similar(
-                            "field",
-                            "fields",
-                            span,
-                            &x.name.1,
-                            &view
-                                .fields
-                                .iter()
-                                .map(|x| x.name.1.clone())
-                                .collect::<Vec<_>>(),
-                            14,
-                        ));
-                    };
-                    Ok(field
-                        .1
-                        .return_value
-                        .as_ref()
-                        .expect("Argument is not a value")
-                        .locations
-                        .clone())
-                })
-                .collect::<Result<Vec<_>, _>>()?
-                .into_iter()
-                .flatten()
-                .collect();
-            Ok(OutputData::new(
-                MirCodeBlock::from(
-                    fields
-                        .into_iter()
-                        .map(|x| x.1.mir.0)
-                        .flatten()
-                        .collect::<Vec<_>>(),
-                ),
-                span.clone(),
-                Some(TypedMemory::new(class, instr, span.clone())),
-            ))
-        }
+        } => compile_new(span, &class.apply_expected(&expected_type), fields, ls, cm),
         Expr::If {
             span,
             condition,
             then,
             or_else,
-        } => {
-            let er = compile(condition, ls, cm, None)?;
-            let loc = er.check_against(&Type::native_simple("Bool"))?[0];
-            mir.add(er.mir);
-
-            let then_r = compile_code_block(then, &mut ls.shadow(), cm, span.clone())?;
-            let else_r = if let Some(x) = or_else.as_ref() {
-                Some(compile_code_block(x, &mut ls.shadow(), cm, span.clone())?)
-            } else {
-                None
-            };
-            let (output, tlr, elr) = if let Some(else_r) = else_r {
-                if let Some(b) = then_r.return_value {
-                    let a = else_r
-                        .return_value
-                        .expect("Else should have a return value");
-                    if a.ty != b.ty {
-                        panic!("If branches must have the same type {}", span.file);
-                    }
-                    let alc = cm.alloc_type(&a.ty)?;
-                    let mut tr = then_r.mir;
-                    tr.copy_bulk(&alc, &b.locations, &b.span)?;
-                    let mut er = else_r.mir;
-                    er.copy_bulk(&alc, &a.locations, &a.span)?;
-
-                    (Some(TypedMemory::new(a.ty, alc, span.clone())), tr, er)
-                } else {
-                    (None, then_r.mir, else_r.mir)
-                }
-            } else {
-                (None, then_r.mir, MirCodeBlock::default())
-            };
-            mir.add_mir(Mir::If0(loc, tlr, elr));
-            Ok(OutputData::new(mir, span.clone(), output))
-        }
-        Expr::Number(span, a, t) => {
-            // TODO add more options (type choice automatically)
-            let (tn, alc) = if t == &NumberType::Val
-                || (t == &NumberType::Auto && *a < 16 && *a >= 0)
-            {
-                let alc = cm.alloc();
-                mir.add_mir(Mir::Set(alc, *a as u8));
-                ("Val", vec![alc])
-            } else if t == &NumberType::Byte || (t == &NumberType::Auto && *a < 16 * 16 && *a > 0) {
-                let alc = cm.alloc();
-                let alc1 = cm.alloc();
-                mir.add_mir(Mir::Set(alc, *a as u8 % 16));
-                mir.add_mir(Mir::Set(alc1, *a as u8 / 16));
-                ("Byte", vec![alc, alc1])
-            } else if t == &NumberType::Short
-                || (t == &NumberType::Auto && *a < 16 * 16 * 16 * 16 && *a > 0)
-            {
-                let alc = cm.alloc();
-                let alc1 = cm.alloc();
-                let alc2 = cm.alloc();
-                let alc3 = cm.alloc();
-                let mut a = *a;
-                mir.add_mir(Mir::Set(alc, a as u8 % 16));
-                a /= 16;
-                mir.add_mir(Mir::Set(alc1, a as u8 % 16));
-                a /= 16;
-                mir.add_mir(Mir::Set(alc2, a as u8 % 16));
-                a /= 16;
-                mir.add_mir(Mir::Set(alc3, a as u8 % 16));
-                ("Short", vec![alc, alc1])
-            } else {
-                panic!("Number too big {}", a);
-            };
-            Ok(OutputData::new(
-                mir,
-                span.clone(),
-                Some(TypedMemory::new(
-                    Type::simple(tn, span.clone()),
-                    alc,
-                    span.clone(),
-                )),
-            ))
-        }
-        Expr::Variable(span, a) => {
-            if let Some(e) = cm.cl.constants.get(a).cloned() {
-                let kg: Vec<u32> =
-                    e.1.iter()
-                        .map(|x| {
-                            let alc = cm.alloc();
-                            mir.add_mir(Mir::Set(alc, *x));
-                            alc
-                        })
-                        .collect();
-                Ok(OutputData::new(
-                    mir,
-                    span.clone(),
-                    Some(TypedMemory::new(e.0.clone(), kg, e.0.span)),
-                ))
-            } else {
-                Ok(OutputData::new(
-                    mir,
-                    span.clone(),
-                    Some(ls.get_var(&SpannedObject(span.clone(), a.clone()))?.clone()),
-                ))
-            }
-        }
+        } => compile_if(span, condition, then, or_else, ls, cm),
+        Expr::Number(span, a, t) => compile_number(span, *a, t, cm),
+        Expr::Variable(span, a) => compile_variable(span, a, ls, cm),
         Expr::Type(_span, _a) => {
             panic!("Expected something else than Type in expression")
         }
-        Expr::Field { span, source, name } => {
-            let out = compile(&*source, ls, cm, None)?;
-            let rtv = out
-                .return_value
-                .as_ref()
-                .expect("Field source must be a value");
-            let (ty, locs) = cm.location_and_type_of_field(
-                &rtv.locations,
-                cm.cl.view(&rtv.ty)?,
-                &SpannedObject(span.clone(), name.clone()),
-            )?;
-            Ok(OutputData::new(
-                out.mir,
-                span.clone(),
-                Some(TypedMemory::new(ty, locs, span.clone())),
-            ))
-        }
+        Expr::Field { span, source, name } => compile_field(span, source, name, ls, cm),
         Expr::Method {
             span,
             source,
             name,
             arguments,
             template,
-        } => {
-            if let Expr::Type(_tspan, a) = &**source {
-                let a = a.apply_expected(&expected_type);
-                let arguments = arguments
-                    .1
-                    .iter()
-                    .map(|x| {
-                        let k = compile(x, ls, cm, None)?;
-                        mir.add(k.mir);
-                        Ok(k.return_value.expect("Argument must be a value"))
-                    })
-                    .collect::<Result<Vec<_>, _>>()?;
-                let k = cm
-                    .cl
-                    .view(&a)?
-                    .method_view(name, template)?
-                    .execute(ls, cm, arguments)
-                    .map_err(|r| in_method(&name.0, r))?;
-                mir.add(k.mir);
-                Ok(OutputData::new(
-                    mir,
-                    span.clone(),
-                    k.return_value.map(|mut x| {
-                        x.span = span.clone();
-                        x
-                    }),
-                ))
-            } else {
-                let aj = compile(source, ls, cm, None)?;
-                mir.add(aj.mir);
-                let ah = aj.return_value.expect("Method source must be a value");
-                let a = &ah.ty;
-                let mut arguments = arguments
-                    .1
-                    .iter()
-                    .map(|x| {
-                        let k = compile(x, ls, cm, None)?;
-                        mir.add(k.mir);
-                        Ok(k.return_value.expect("Argument must be a value"))
-                    })
-                    .collect::<Result<Vec<_>, _>>()?;
-                arguments.insert(0, ah.clone());
-                let k = cm
-                    .cl
-                    .view(a)?
-                    .method_view(name, template)?
-                    .execute(ls, cm, arguments)
-                    .map_err(|r| in_method(&name.0, r))?;
-                mir.add(k.mir);
-                Ok(OutputData::new(
-                    mir,
-                    span.clone(),
-                    k.return_value.map(|mut x| {
-                        x.span = span.clone();
-                        x
-                    }),
-                ))
-            }
-        }
+        } => match &**source {
+            Expr::Type(_tspan, a) => compile_static_call(
+                span,
+                &a.apply_expected(&expected_type),
+                name,
+                arguments,
+                template,
+                ls,
+                cm,
+            ),
+            _ => compile_method_call(span, source, name, arguments, template, ls, cm),
+        },
         Expr::NamedResource { span, vtype, name } => {
+            let mut mir = MirCodeBlock::default();
             let vtype = vtype.apply_expected(&expected_type);
             let k = ls.new_var(cm, &name.1, vtype, &mut mir, span.clone())?;
             Ok(OutputData::new(mir, span.clone(), Some(k)))
         }
-        Expr::Assignement { span, target, to } => {
-            let ret = compile(target, ls, cm, None)?;
-            let rt = ret
-                .return_value
-                .expect("Assignement target must be a value");
-            let ret1 = compile(to, ls, cm, Some(rt.ty.clone()))?;
-            let rt1 = ret1
-                .return_value
-                .expect("Assignement value must be a value");
-            if rt.ty != rt1.ty {
-                return Err(invalid_type(
-                    &rt.ty.span,
-                    &rt1.ty.span,
-                    &format!("{:?}", rt.ty),
-                    &format!("{:?}", rt1.ty),
-                ));
-            }
-            mir.add(ret1.mir);
-            mir.add(ret.mir);
-            mir.copy_bulk(&rt.locations, &rt1.locations, &span)?;
-            Ok(OutputData::new(mir, span.clone(), None))
-        }
+        Expr::Assignement { span, target, to } => compile_assignement(span, target, to, ls, cm),
         Expr::Block(span, a) => compile_code_block(a, &mut ls.shadow(), cm, span.clone()),
-        Expr::Return(span, a) => {
-            if let Some(e) = a {
-                let rl = ls
-                    .return_loc
-                    .as_ref()
-                    .expect("A return value wasn't expected")
-                    .clone();
-                let ret = compile(e, ls, cm, Some(rl.ty.clone()))?;
-                let rt = ret.return_value.expect("Return value must be a value");
-                if rl.ty != rt.ty {
-                    return Err(method_return_type_invalid(
-                        e.span(),
-                        &rl.ty.span,
-                        &format!("{:?}", rl.ty),
-                        &format!("{:?}", rt.ty),
-                    ));
-                }
-                mir.add(ret.mir);
-                mir.copy_bulk(&rl.locations, &rt.locations, &span)?;
-                mir.add_mir(Mir::Skip);
-                Ok(OutputData::new(mir, span.clone(), None))
-            } else if ls.return_loc.is_some() {
-                panic!("A return value was expected");
-            } else {
-                mir.add_mir(Mir::Skip);
-                Ok(OutputData::new(mir, span.clone(), None))
-            }
-        }
+        Expr::Return(span, a) => compile_return(span, a, ls, cm),
         Expr::Cast {
             span,
             source,
             target,
-        } => {
-            let target = target.apply_expected(&expected_type);
-            let ret = compile(source, ls, cm, Some(target.clone()))?;
-            mir.add(ret.mir);
-            let rt = ret.return_value.expect("Cast source must be a value");
-            let source_view = cm.cl.view(&rt.ty)?;
-            let target_view = cm.cl.view(&target)?;
-            if source_view.size(&cm.cl)? != target_view.size(&cm.cl)? {
-                panic!("Type size cast must match, {:?} and {:?}", source, target);
-            }
-            Ok(OutputData::new(
-                mir,
-                span.clone(),
-                Some(TypedMemory::new(target, rt.locations, span.clone())),
-            ))
-        }
-        Expr::Loop(span, a) => {
-            let k = compile_code_block(a, &mut ls.shadow(), cm, span.clone())?;
-            mir.add_mir(Mir::Loop(k.mir));
-            Ok(OutputData::new(mir, span.clone(), None))
-        }
+        } => compile_cast(span, source, &target.apply_expected(&expected_type), ls, cm),
+        Expr::Loop(span, a) => compile_loop(span, a, ls, cm),
         Expr::Break(span) => Ok(OutputData::new(
             MirCodeBlock::from(vec![Mir::Break]),
             span.clone(),
@@ -388,101 +119,519 @@ pub fn compile(
             None,
         )),
         Expr::BooleanExpression(span, a, bo, c) => {
-            let alp = cm.alloc();
-            let a = compile(a, ls, cm, None)?;
-            let loca = if let Some(a) = a.return_value {
-                if a.ty.name.1 != "Bool" {
-                    panic!("Boolean expression must be a bool");
-                }
-                a.locations[0]
-            } else {
-                panic!("Boolean expression must return a value");
-            };
-            let b = compile(c, ls, cm, None)?;
-            let locb = if let Some(b) = b.return_value {
-                if b.ty.name.1 != "Bool" {
-                    panic!("Boolean expression must be a bool");
-                }
-                b.locations[0]
-            } else {
-                panic!("Boolean expression must return a value");
-            };
-            mir.add(a.mir);
-            let mut cb = MirCodeBlock::default();
-            cb.add(b.mir);
-            cb.copy(alp, locb);
-            if bo == &BooleanOperator::And {
-                mir.add_mir(Mir::If0(
-                    loca,
-                    cb,
-                    MirCodeBlock::from(vec![Mir::Set(alp, 1)]),
-                ));
+            compile_boolean_expression(span, a, bo, c, ls, cm)
+        }
+        Expr::ArrayDefinition(a, b) => compile_array(a, b, ls, cm),
+    }
+}
+
+fn compile_new(
+    span: &Span,
+    class: &Type,
+    fields: &SpannedVector<(String, Expr)>,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    // TODO: Set expected type to correct value.
+    let view = cm.cl.view(class)?;
+    let fields = fields
+        .1
+        .iter()
+        .map(|(a, b)| {
+            Ok((
+                a,
+                compile(b, ls, cm, Some(view.get_field_type(a, b.span())?))?,
+            ))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // get_field
+
+    let instr = view
+        .fields
+        .iter()
+        .map(|x| {
+            let field = if let Some(e) = fields.iter().find(|(a, _)| *a == &x.name.1) {
+                e
             } else {
-                mir.add_mir(Mir::If0(
-                    loca,
-                    MirCodeBlock::from(vec![Mir::Set(alp, 0)]),
-                    cb,
+                return Err(report_similar(
+                    "field",
+                    "fields",
+                    span,
+                    &x.name.1,
+                    &view
+                        .fields
+                        .iter()
+                        .map(|x| x.name.1.clone())
+                        .collect::<Vec<_>>(),
+                    14,
                 ));
-            }
-            Ok(OutputData::new(
-                mir,
-                span.clone(),
-                Some(TypedMemory::new(
-                    Type::simple("Bool", span.clone()),
-                    vec![alp],
-                    span.clone(),
-                )),
-            ))
-        }
-        Expr::ArrayDefinition(a, b) => {
-            let out1 =
-                b.1.iter()
-                    .map(|x| compile(x, ls, cm, None))
-                    .collect::<Result<Vec<_>, _>>()?;
-            let fe = out1.first().expect("Array must have at least one element");
-            let rv = fe
+            };
+            Ok(field
+                .1
                 .return_value
                 .as_ref()
-                .expect("Array must have at least one element")
-                .ty
-                .clone();
-            let mut alloc_block = Vec::new();
-            let m = out1.len();
-            for i in out1 {
-                let mut rv1 = i
-                    .return_value
-                    .expect("Array must have at least one element");
-                if rv1.ty != rv {
-                    panic!("Array elements must be the same type");
-                }
-                mir.add(i.mir);
-                alloc_block.append(&mut rv1.locations);
+                .expect("Argument is not a value")
+                .locations
+                .clone())
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    Ok(OutputData::new(
+        MirCodeBlock::from(
+            fields
+                .into_iter()
+                .flat_map(|x| x.1.mir.0)
+                .collect::<Vec<_>>(),
+        ),
+        span.clone(),
+        Some(TypedMemory::new(class.clone(), instr, span.clone())),
+    ))
+}
+
+fn compile_if(
+    span: &Span,
+    condition: &Expr,
+    then: &CodeBlock,
+    or_else: &Option<CodeBlock>,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    let er = compile(condition, ls, cm, None)?;
+    let loc = er.check_against(&Type::native_simple("Bool"))?[0];
+    mir.add(er.mir);
+
+    let then_r = compile_code_block(then, &mut ls.shadow(), cm, span.clone())?;
+    let else_r = if let Some(x) = or_else.as_ref() {
+        Some(compile_code_block(x, &mut ls.shadow(), cm, span.clone())?)
+    } else {
+        None
+    };
+    let (output, tlr, elr) = merge_branches(span, then_r, else_r, cm)?;
+    mir.add_mir(Mir::If0(loc, tlr, elr));
+    Ok(OutputData::new(mir, span.clone(), output))
+}
+
+/// Copies the values of the branches of an if to the same location, which is the value of the if
+fn merge_branches(
+    span: &Span,
+    then_r: OutputData,
+    else_r: Option<OutputData>,
+    cm: &mut CodeManager,
+) -> Result<(Option<TypedMemory>, MirCodeBlock, MirCodeBlock), Error> {
+    Ok(if let Some(else_r) = else_r {
+        if let Some(b) = then_r.return_value {
+            let a = else_r
+                .return_value
+                .expect("Else should have a return value");
+            if a.ty != b.ty {
+                panic!("If branches must have the same type {}", span.file);
             }
-            let az = cm.alloc_block(alloc_block.len());
-            mir.copy_bulk(&az, &alloc_block, a)?;
-            Ok(OutputData::new(
-                mir,
-                a.clone(),
-                Some(TypedMemory::new(
-                    Type::new(
-                        "Array",
-                        Some(SpannedVector(
-                            a.clone(),
-                            vec![
-                                rv,
-                                Type::simple(&m.to_string(), a.clone()),
-                                Type::simple(
-                                    if b.1.len() < 15 { "Val" } else { "Byte" },
-                                    a.clone(),
-                                ),
-                            ],
-                        )),
-                        a.clone(),
-                    ),
-                    az,
-                    a.clone(),
-                )),
-            ))
+            let alc = cm.alloc_type(&a.ty)?;
+            let mut tr = then_r.mir;
+            tr.copy_bulk(&alc, &b.locations, &b.span)?;
+            let mut er = else_r.mir;
+            er.copy_bulk(&alc, &a.locations, &a.span)?;
+
+            (Some(TypedMemory::new(a.ty, alc, span.clone())), tr, er)
+        } else {
+            (None, then_r.mir, else_r.mir)
+        }
+    } else {
+        (None, then_r.mir, MirCodeBlock::default())
+    })
+}
+
+fn compile_number(
+    span: &Span,
+    a: i32,
+    t: &NumberType,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    // TODO add more options (type choice automatically)
+    let (tn, alc) = if t == &NumberType::Val || (t == &NumberType::Auto && (0..16).contains(&a)) {
+        let alc = cm.alloc();
+        mir.add_mir(Mir::Set(alc, a as u8));
+        ("Val", vec![alc])
+    } else if t == &NumberType::Byte || (t == &NumberType::Auto && a < 16 * 16 && a > 0) {
+        let alc = cm.alloc();
+        let alc1 = cm.alloc();
+        mir.add_mir(Mir::Set(alc, a as u8 % 16));
+        mir.add_mir(Mir::Set(alc1, a as u8 / 16));
+        ("Byte", vec![alc, alc1])
+    } else if t == &NumberType::Short || (t == &NumberType::Auto && a < 16 * 16 * 16 * 16 && a > 0)
+    {
+        let alc = cm.alloc();
+        let alc1 = cm.alloc();
+        let alc2 = cm.alloc();
+        let alc3 = cm.alloc();
+        let mut a = a;
+        mir.add_mir(Mir::Set(alc, a as u8 % 16));
+        a /= 16;
+        mir.add_mir(Mir::Set(alc1, a as u8 % 16));
+        a /= 16;
+        mir.add_mir(Mir::Set(alc2, a as u8 % 16));
+        a /= 16;
+        mir.add_mir(Mir::Set(alc3, a as u8 % 16));
+        ("Short", vec![alc, alc1])
+    } else {
+        panic!("Number too big {}", a);
+    };
+    Ok(OutputData::new(
+        mir,
+        span.clone(),
+        Some(TypedMemory::new(
+            Type::simple(tn, span.clone()),
+            alc,
+            span.clone(),
+        )),
+    ))
+}
+
+fn compile_variable(
+    span: &Span,
+    a: &str,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    if let Some(e) = cm.cl.constants.get(a).cloned() {
+        let kg: Vec<u32> =
+            e.1.iter()
+                .map(|x| {
+                    let alc = cm.alloc();
+                    mir.add_mir(Mir::Set(alc, *x));
+                    alc
+                })
+                .collect();
+        Ok(OutputData::new(
+            mir,
+            span.clone(),
+            Some(TypedMemory::new(e.0.clone(), kg, e.0.span)),
+        ))
+    } else {
+        Ok(OutputData::new(
+            mir,
+            span.clone(),
+            Some(
+                ls.get_var(&SpannedObject(span.clone(), a.to_owned()))?
+                    .clone(),
+            ),
+        ))
+    }
+}
+
+fn compile_field(
+    span: &Span,
+    source: &Expr,
+    name: &str,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let out = compile(source, ls, cm, None)?;
+    let rtv = out
+        .return_value
+        .as_ref()
+        .expect("Field source must be a value");
+    let (ty, locs) = cm.location_and_type_of_field(
+        &rtv.locations,
+        &*cm.cl.view(&rtv.ty)?,
+        &SpannedObject(span.clone(), name.to_owned()),
+    )?;
+    Ok(OutputData::new(
+        out.mir,
+        span.clone(),
+        Some(TypedMemory::new(ty, locs, span.clone())),
+    ))
+}
+
+/// A call to a method of a type, like `Val.new()`
+fn compile_static_call(
+    span: &Span,
+    ty: &Type,
+    name: &SpannedObject<String>,
+    arguments: &SpannedVector<Expr>,
+    template: &Option<SpannedVector<Type>>,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    let arguments = arguments
+        .1
+        .iter()
+        .map(|x| {
+            let k = compile(x, ls, cm, None)?;
+            mir.add(k.mir);
+            Ok(k.return_value.expect("Argument must be a value"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let k = cm
+        .cl
+        .view(ty)?
+        .method_view(name, template)?
+        .execute(ls, cm, arguments)
+        .map_err(|r| in_method(&name.0, r))?;
+    mir.add(k.mir);
+    Ok(OutputData::new(
+        mir,
+        span.clone(),
+        k.return_value.map(|mut x| {
+            x.span = span.clone();
+            x
+        }),
+    ))
+}
+
+fn compile_method_call(
+    span: &Span,
+    source: &Expr,
+    name: &SpannedObject<String>,
+    arguments: &SpannedVector<Expr>,
+    template: &Option<SpannedVector<Type>>,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    let aj = compile(source, ls, cm, None)?;
+    mir.add(aj.mir);
+    let ah = aj.return_value.expect("Method source must be a value");
+    let a = &ah.ty;
+    let mut arguments = arguments
+        .1
+        .iter()
+        .map(|x| {
+            let k = compile(x, ls, cm, None)?;
+            mir.add(k.mir);
+            Ok(k.return_value.expect("Argument must be a value"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    arguments.insert(0, ah.clone());
+    let k = cm
+        .cl
+        .view(a)?
+        .method_view(name, template)?
+        .execute(ls, cm, arguments)
+        .map_err(|r| in_method(&name.0, r))?;
+    mir.add(k.mir);
+    Ok(OutputData::new(
+        mir,
+        span.clone(),
+        k.return_value.map(|mut x| {
+            x.span = span.clone();
+            x
+        }),
+    ))
+}
+
+fn compile_assignement(
+    span: &Span,
+    target: &Expr,
+    to: &Expr,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    let ret = compile(target, ls, cm, None)?;
+    let rt = ret
+        .return_value
+        .expect("Assignement target must be a value");
+    let ret1 = compile(to, ls, cm, Some(rt.ty.clone()))?;
+    let rt1 = ret1
+        .return_value
+        .expect("Assignement value must be a value");
+    if rt.ty != rt1.ty {
+        return Err(invalid_type(
+            &rt.ty.span,
+            &rt1.ty.span,
+            &format!("{:?}", rt.ty),
+            &format!("{:?}", rt1.ty),
+        ));
+    }
+    mir.add(ret1.mir);
+    mir.add(ret.mir);
+    mir.copy_bulk(&rt.locations, &rt1.locations, span)?;
+    Ok(OutputData::new(mir, span.clone(), None))
+}
+
+fn compile_return(
+    span: &Span,
+    a: &Option<Box<Expr>>,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    if let Some(e) = a {
+        let rl = ls
+            .return_loc
+            .as_ref()
+            .expect("A return value wasn't expected")
+            .clone();
+        let ret = compile(e, ls, cm, Some(rl.ty.clone()))?;
+        let rt = ret.return_value.expect("Return value must be a value");
+        if rl.ty != rt.ty {
+            return Err(method_return_type_invalid(
+                e.span(),
+                &rl.ty.span,
+                &format!("{:?}", rl.ty),
+                &format!("{:?}", rt.ty),
+            ));
         }
+        mir.add(ret.mir);
+        mir.copy_bulk(&rl.locations, &rt.locations, span)?;
+        mir.add_mir(Mir::Skip);
+        Ok(OutputData::new(mir, span.clone(), None))
+    } else if ls.return_loc.is_some() {
+        panic!("A return value was expected");
+    } else {
+        mir.add_mir(Mir::Skip);
+        Ok(OutputData::new(mir, span.clone(), None))
     }
 }
+
+fn compile_cast(
+    span: &Span,
+    source: &Expr,
+    target: &Type,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    let ret = compile(source, ls, cm, Some(target.clone()))?;
+    mir.add(ret.mir);
+    let rt = ret.return_value.expect("Cast source must be a value");
+    let source_view = cm.cl.view(&rt.ty)?;
+    let target_view = cm.cl.view(target)?;
+    if source_view.size(&cm.cl)? != target_view.size(&cm.cl)? {
+        panic!("Type size cast must match, {:?} and {:?}", source, target);
+    }
+    Ok(OutputData::new(
+        mir,
+        span.clone(),
+        Some(TypedMemory::new(target.clone(), rt.locations, span.clone())),
+    ))
+}
+
+fn compile_loop(
+    span: &Span,
+    a: &CodeBlock,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    let k = compile_code_block(a, &mut ls.shadow(), cm, span.clone())?;
+    mir.add_mir(Mir::Loop(k.mir));
+    Ok(OutputData::new(mir, span.clone(), None))
+}
+
+fn compile_boolean_expression(
+    span: &Span,
+    a: &Expr,
+    bo: &BooleanOperator,
+    c: &Expr,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    let alp = cm.alloc();
+    let a = compile(a, ls, cm, None)?;
+    let loca = if let Some(a) = a.return_value {
+        if a.ty.name.1 != "Bool" {
+            panic!("Boolean expression must be a bool");
+        }
+        a.locations[0]
+    } else {
+        panic!("Boolean expression must return a value");
+    };
+    let b = compile(c, ls, cm, None)?;
+    let locb = if let Some(b) = b.return_value {
+        if b.ty.name.1 != "Bool" {
+            panic!("Boolean expression must be a bool");
+        }
+        b.locations[0]
+    } else {
+        panic!("Boolean expression must return a value");
+    };
+    mir.add(a.mir);
+    let mut cb = MirCodeBlock::default();
+    cb.add(b.mir);
+    cb.copy(alp, locb);
+    if bo == &BooleanOperator::And {
+        mir.add_mir(Mir::If0(
+            loca,
+            cb,
+            MirCodeBlock::from(vec![Mir::Set(alp, 1)]),
+        ));
+    } else {
+        mir.add_mir(Mir::If0(
+            loca,
+            MirCodeBlock::from(vec![Mir::Set(alp, 0)]),
+            cb,
+        ));
+    }
+    Ok(OutputData::new(
+        mir,
+        span.clone(),
+        Some(TypedMemory::new(
+            Type::simple("Bool", span.clone()),
+            vec![alp],
+            span.clone(),
+        )),
+    ))
+}
+
+fn compile_array(
+    a: &Span,
+    b: &SpannedVector<Expr>,
+    ls: &mut LocalState,
+    cm: &mut CodeManager,
+) -> Result<OutputData, Error> {
+    let mut mir = MirCodeBlock::default();
+    let out1 =
+        b.1.iter()
+            .map(|x| compile(x, ls, cm, None))
+            .collect::<Result<Vec<_>, _>>()?;
+    let fe = out1.first().expect("Array must have at least one element");
+    let rv = fe
+        .return_value
+        .as_ref()
+        .expect("Array must have at least one element")
+        .ty
+        .clone();
+    let mut alloc_block = Vec::new();
+    let m = out1.len();
+    for i in out1 {
+        let mut rv1 = i
+            .return_value
+            .expect("Array must have at least one element");
+        if rv1.ty != rv {
+            panic!("Array elements must be the same type");
+        }
+        mir.add(i.mir);
+        alloc_block.append(&mut rv1.locations);
+    }
+    let az = cm.alloc_block(alloc_block.len());
+    mir.copy_bulk(&az, &alloc_block, a)?;
+    Ok(OutputData::new(
+        mir,
+        a.clone(),
+        Some(TypedMemory::new(
+            Type::new(
+                "Array",
+                Some(SpannedVector(
+                    a.clone(),
+                    vec![
+                        rv,
+                        Type::simple(&m.to_string(), a.clone()),
+                        Type::simple(if b.1.len() < 15 { "Val" } else { "Byte" }, a.clone()),
+                    ],
+                )),
+                a.clone(),
+            ),
+            az,
+            a.clone(),
+        )),
+    ))
+}
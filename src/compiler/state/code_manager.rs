@@ -1,24 +1,99 @@
-use errors::{report_similar, Error, SpannedObject};
+use std::collections::HashMap;
+
+use errors::{report_similar, Error, Span, SpannedObject};
+use mir::{DebugMarker, Mir, MirCodeBlock, Procedure};
 
 use crate::{
     compiler::class_loader::ClassLoader,
     parser::{class::ClassView, ty::Type},
 };
 
+use super::typed_definition::TypedMemory;
+
+/// A method compiled once whose calls jump to its code
+pub struct Outlined {
+    pub procedure: Procedure,
+    /// Where the calls copy the arguments
+    pub arguments: Vec<TypedMemory>,
+    pub return_value: Option<TypedMemory>,
+    /// The depth of the stack of a `@Recursive` method
+    pub stack_pointer: Option<u32>,
+    pub calls: usize,
+}
+
+/// A call of a method being compiled
+pub struct StackFrame {
+    pub method: String,
+    pub call: Span,
+    /// The last variable allocated before the call
+    pub start: u32,
+    /// Whether the method is being compiled once for all its calls
+    pub outlined: bool,
+}
+
 pub struct CodeManager {
     pub cl: ClassLoader,
     calloc: u32,
+    /// The methods that were compiled once, `None` for the ones that are inlined
+    pub procedures: HashMap<String, Option<Outlined>>,
+    /// How many times each method was inlined
+    pub inlined: HashMap<String, usize>,
+    pub call_stack: Vec<StackFrame>,
+    /// How many expressions are being compiled inside each other
+    pub depth: usize,
+    /// Whether the code of each statement is preceded by its location
+    pub debug_info: bool,
+    /// Whether the code tells the source debugger about the calls and the variables
+    pub debug_scopes: bool,
 }
 
 impl CodeManager {
     pub fn new(cl: ClassLoader) -> CodeManager {
-        CodeManager { cl, calloc: 0 }
+        CodeManager {
+            cl,
+            calloc: 0,
+            procedures: HashMap::new(),
+            inlined: HashMap::new(),
+            call_stack: Vec::new(),
+            depth: 0,
+            debug_info: false,
+            debug_scopes: false,
+        }
+    }
+
+    /// The methods the code being compiled is inlined in, from `main` or the method being
+    /// compiled once
+    pub fn methods(&self) -> Vec<String> {
+        let start = self
+            .call_stack
+            .iter()
+            .rposition(|x| x.outlined)
+            .unwrap_or(0);
+        self.call_stack[start..]
+            .iter()
+            .map(|x| x.method.clone())
+            .collect()
+    }
+
+    /// Tells the source debugger where the variable `name` is stored
+    pub fn declare(&self, code: &mut MirCodeBlock, name: &str, var: &TypedMemory) {
+        if self.debug_scopes {
+            code.add_mir(Mir::Debug(DebugMarker::Variable {
+                name: name.to_owned(),
+                ty: format!("{:?}", var.ty),
+                cells: var.locations.clone(),
+            }));
+        }
     }
 
     pub fn alloc(&mut self) -> u32 {
         self.calloc += 1;
         self.calloc
     }
+    /// The last variable allocated
+    pub fn allocated(&self) -> u32 {
+        self.calloc
+    }
     pub fn alloc_block(&mut self, size: usize) -> Vec<u32> {
         (0..size).map(|_| self.alloc()).collect()
     }
@@ -29,7 +104,7 @@ impl CodeManager {
     pub fn location_and_type_of_field(
         &self,
         locations: &[u32],
-        view: ClassView,
+        view: &ClassView,
         name: &SpannedObject<String>,
     ) -> Result<(Type, Vec<u32>), Error> {
         let mut offset = 0;
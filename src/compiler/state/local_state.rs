@@ -80,6 +80,7 @@ impl LocalState {
             &tm.locations,
             &tm.locations.iter().map(|_| 0).collect::<Vec<_>>(),
         );
+        cm.declare(code, name, &tm);
         self.vars.insert(name.to_string(), tm.clone());
         Ok(tm)
     }
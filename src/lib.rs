@@ -0,0 +1,10 @@
+pub mod actions;
+mod api;
+mod compiler;
+mod parser;
+#[cfg(test)]
+mod tests;
+
+pub use api::{container, lower, lower_with, referenced_sources, Artifacts, Compiler, Diagnostics};
+
+const MIR_MODE: bool = false;
@@ -1,28 +1,63 @@
-#![feature(try_blocks)]
-
 use cythan::format;
 use lir::CompilableInstruction;
-use mir::{MirState, StdIoContext};
+use mir::{coalesce_variables, OptLevel, PassManager, StdIoContext};
 
-use crate::actions::{
-    build_context::compile,
-    run_context::{run, run_bin, compute_max_bin},
+use cythan_v4::{
+    actions::{
+        build_context::{self, compile, optimizer_from_flags},
+        debug_context::Debugger,
+        diff_context::differential,
+        fuzz_context::fuzz,
+        profile_context::{profile, Report},
+        report_context::build_report,
+        run_context::{run, run_bin_with, compute_max_bin},
+        source_debug_context::SourceDebugger,
+    },
+    container, lower_with, referenced_sources,
 };
 
-mod actions;
-mod compiler;
-mod parser;
-#[cfg(test)]
-mod tests;
-
-const STACK_SIZE: usize = 1024 * 1024 * 1024;
+/// Whether the argument is one of the flags, the other arguments starting with `-` are
+/// positional like the inputs of the programs
+fn is_flag(arg: &str) -> bool {
+    matches!(arg, "-g" | "--compress" | "--report" | "--time-passes")
+        || ["-O", "--passes=", "--print-after="]
+            .iter()
+            .any(|x| arg.starts_with(x))
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    // The arguments after `--` are never flags
+    let rest = match args.iter().position(|x| x == "--") {
+        Some(e) => args.split_off(e).split_off(1),
+        None => Vec::new(),
+    };
+    let (flags, mut args): (Vec<String>, Vec<String>) =
+        args.into_iter().partition(|x| is_flag(x));
+    args.extend(rest);
+    // `-g` writes the debug info of the built binary next to it
+    let debug_info = flags.iter().any(|x| x == "-g");
+    // `--compress` compresses the memory of the built binary with zlib
+    let compress = flags.iter().any(|x| x == "--compress");
+    // `--report` prints the memory and the steps the built program is estimated to take
+    let report = flags.iter().any(|x| x == "--report");
+    let flags: Vec<&str> = flags
+        .iter()
+        .map(|x| x.as_str())
+        .filter(|x| *x != "-g" && *x != "--compress" && *x != "--report")
+        .collect();
+    // The programs are optimized with `-O2` when the legacy optimize argument is given
+    let optimizer = |optimize: bool| -> PassManager {
+        let default = if optimize { OptLevel::O2 } else { OptLevel::O0 };
+        optimizer_from_flags(&flags, default).unwrap_or_else(|e| {
+            println!("{}", e);
+            std::process::exit(1);
+        })
+    };
     match args.get(1).map(|x| x.as_str()) {
         Some("run") => {
             let fname = args.get(2).expect("No file name given");
-            let pg: mir::MirCodeBlock = compile(fname.to_owned(), args.get(3).is_some());
+            let pg: mir::MirCodeBlock = compile(fname.to_owned(), optimizer(args.get(3).is_some()), false);
             println!("Compiled successfully!");
             println!("Now running...");
             run(&pg, StdIoContext);
@@ -30,8 +65,12 @@ fn main() {
         Some("inspect") => {
             let fname = args.get(2).expect("No file name given");
             let foname = args.get(3).expect("No output file name given");
-            let pg = format::decode_bytes(&std::fs::read(fname).unwrap())
-                .unwrap()
+            let bytes = std::fs::read(fname).unwrap();
+            let pg = format::decode_bytes(&bytes)
+                .unwrap_or_else(|e| {
+                    println!("{}", e);
+                    std::process::exit(1);
+                })
                 .1;
             std::fs::write(
                 foname,
@@ -40,43 +79,87 @@ fn main() {
                     .collect::<Vec<_>>()
                     .join(" "),
             ).unwrap();
+            if let Ok(mut container) = format::Container::decode(&bytes) {
+                if container.header.compressed {
+                    container.header.compressed = false;
+                    println!(
+                        "Compressed: {} bytes, uncompressed: {} bytes",
+                        bytes.len(),
+                        container.encode().expect("Could not create binary").len()
+                    );
+                }
+                if let Some(symbols) = &container.symbols {
+                    println!(
+                        "Symbols: {} labels, {} variables",
+                        symbols.labels.len(),
+                        symbols.variables.len()
+                    );
+                }
+                if let Some(debug_info) = &container.debug_info {
+                    println!("Debug info: {} ranges", debug_info.len());
+                    std::fs::write(
+                        format!("{}.map", foname),
+                        debug_info
+                            .iter()
+                            .map(|x| x.to_string())
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                    )
+                    .expect("Could not write file");
+                }
+                for source in container.sources.iter().flatten() {
+                    println!("Source: {} ({} bytes)", source.name, source.text.len());
+                }
+            }
             println!("Decoded successfully!");
         }
         Some("precomp") => {
             let fname = args.get(2).expect("No file name given");
             let foname = args.get(3).expect("No output file name given");
-            let pg = format::decode_bytes(&std::fs::read(fname).unwrap())
-                .unwrap()
-                .1;
+            let (header, pg) = format::decode_bytes(&std::fs::read(fname).unwrap())
+                .unwrap_or_else(|e| {
+                    println!("{}", e);
+                    std::process::exit(1);
+                });
 
             println!("Now running...");
             let output = compute_max_bin(
+                &header,
                 &pg.into_iter().map(|x| x as usize).collect::<Vec<_>>(),
-            );
+            ).unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1);
+            });
             println!("Advanced machine by: {}steps", output.0);
             std::fs::write(
                 foname,
-                cythan::format::encode_to_bytes(cythan::format::HeaderData::default(), &output.1.iter().map(|x| *x as _).collect::<Vec<_>>())
+                cythan::format::encode_to_bytes(header, &output.1.iter().map(|x| *x as _).collect::<Vec<_>>())
                     .expect("Could not create binary"),
             )
             .expect("Could not write file");
         }
         Some("exe") => {
             let fname = args.get(2).expect("No file name given");
-            let pg = format::decode_bytes(&std::fs::read(fname).unwrap())
-                .unwrap()
-                .1;
+            let (header, pg) = format::decode_bytes(&std::fs::read(fname).unwrap())
+                .unwrap_or_else(|e| {
+                    println!("{}", e);
+                    std::process::exit(1);
+                });
             println!("Now running...");
-            let (k, _) = run_bin(
+            let (k, _) = run_bin_with(
+                &header,
                 &pg.into_iter().map(|x| x as usize).collect::<Vec<_>>(),
                 StdIoContext,
-            );
+            ).unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1);
+            });
             println!("Took {}steps", k);
         }
         Some("build") => {
             let fname = args.get(2).expect("No file name given");
             let oname = args.get(3).expect("No file name given");
-            let compiled = compile(fname.to_owned(), args.get(4).is_some());
+            let compiled = compile(fname.to_owned(), optimizer(args.get(4).is_some()), debug_info);
             if let Some(e) = args.get(4) {
                 std::fs::write(
                     e,
@@ -89,28 +172,135 @@ fn main() {
                 )
                 .expect("Could not write file");
             }
-            let mut mirstate = MirState::default();
-            compiled.to_asm(&mut mirstate);
-            mirstate.opt_asm();
-            let k: Vec<u32> = CompilableInstruction::compile_to_binary(mirstate.instructions)
-                .into_iter()
-                .map(|x| x as u32)
-                .collect();
-            std::fs::write(
-                oname,
-                cythan::format::encode_to_bytes(cythan::format::HeaderData::default(), &k)
-                    .expect("Could not create binary"),
-            )
-            .expect("Could not write file");
-            println!("Compiled successfully!")
+            let (compiled, coalesced) = coalesce_variables(compiled);
+            println!(
+                "Reused memory cells: {} variables in {} cells ({} saved)",
+                coalesced.variables,
+                coalesced.slots,
+                coalesced.saved()
+            );
+            let lir = lower_with(&compiled, debug_info);
+            let mut container = if debug_info {
+                let (binary, info) = CompilableInstruction::compile_with_debug_info(lir);
+                let sources = referenced_sources(&info, |x| std::fs::read_to_string(x).ok());
+                container(&binary, Some(&info), &sources)
+            } else {
+                container(&CompilableInstruction::compile_to_binary(lir), None, &[])
+            };
+            container.header.compressed = compress;
+            std::fs::write(oname, container.encode().expect("Could not create binary"))
+                .expect("Could not write file");
+            println!("Compiled successfully!");
+            if report {
+                let compiler = build_context::compiler(fname.to_owned())
+                    .optimizer(optimizer(args.get(4).is_some()));
+                match build_report(compiler) {
+                    Ok(e) => print!("{}", e),
+                    Err(e) => eprint!("{}", e),
+                }
+            }
+        }
+        Some("diff") => {
+            let fname = args.get(2).expect("No file name given");
+            let input = args.get(3).map(|x| x.as_str()).unwrap_or("");
+            let report = differential(fname, input);
+            for e in &report.executions {
+                println!(
+                    "{}: {} characters printed in {}steps ({:?})",
+                    e.name,
+                    e.output.len(),
+                    e.steps,
+                    e.termination
+                );
+            }
+            for e in &report.divergences {
+                println!("{}", e);
+            }
+            if report.divergences.is_empty() {
+                println!("No divergence found");
+            } else {
+                std::process::exit(1);
+            }
+        }
+        Some("fuzz") => {
+            let count = args.get(2).map(|x| x.parse().expect("Invalid count")).unwrap_or(100);
+            let start = args.get(3).map(|x| x.parse().expect("Invalid seed")).unwrap_or(0);
+            let failures = fuzz(start..start + count);
+            for e in &failures {
+                println!("Seed {} with input {:?} failed:", e.seed, e.input);
+                for i in e.program.iter() {
+                    println!("{}", i);
+                }
+                println!("{}", e.divergence);
+            }
+            println!("{} failures out of {} programs", failures.len(), count);
+            if !failures.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Some("debug") => {
+            let fname = args.get(2).expect("No file name given");
+            let input = args.get(3).map(|x| x.as_str()).unwrap_or("");
+            let bytes = std::fs::read(fname).expect("Could not read file");
+            // The files of the first version have no symbols nor debug info
+            let container = format::Container::decode(&bytes)
+                .or_else(|_| format::decode_bytes(&bytes).map(|(h, m)| format::Container::new(h, m)))
+                .and_then(|x| Debugger::new(x, input));
+            let mut debugger = container.unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1);
+            });
+            println!("{}", debugger.location());
+            let stdin = std::io::stdin();
+            loop {
+                print!("(cythan) ");
+                std::io::Write::flush(&mut std::io::stdout()).unwrap();
+                let mut line = String::new();
+                if stdin.read_line(&mut line).unwrap() == 0 || line.trim() == "quit" {
+                    break;
+                }
+                match debugger.command(&line) {
+                    Ok(e) | Err(e) => println!("{}", e),
+                }
+            }
+        }
+        Some("profile") => {
+            let class = args.get(2).expect("No class name given");
+            let input = args.get(3).map(|x| x.as_str()).unwrap_or("");
+            let (compiled, _) = coalesce_variables(compile(class.to_owned(), optimizer(true), true));
+            let (binary, info) = CompilableInstruction::compile_with_debug_info(lower_with(&compiled, true));
+            let (profile, output) = profile(&format::HeaderData::default(), &binary, input)
+                .expect("The compiler targets a supported machine");
+            println!("Output: {:?}", output);
+            let report = Report::new(&info, &profile);
+            print!("{}", report);
+            std::fs::write("profile.folded", report.folded()).expect("Could not write file");
+        }
+        Some("debug-source") => {
+            let class = args.get(2).expect("No class name given");
+            let input = args.get(3).map(|x| x.as_str()).unwrap_or("");
+            let compiler = build_context::compiler(class.to_owned())
+                .debug_info(true)
+                .debug_scopes(true);
+            let mir = compiler.generate().unwrap_or_else(|e| {
+                eprint!("{}", e);
+                std::process::exit(1);
+            });
+            let stdin = std::io::stdin();
+            let mut debugger = SourceDebugger::new(compiler.sources(), input, move |out| {
+                println!("{}", out);
+                print!("(cythan) ");
+                std::io::Write::flush(&mut std::io::stdout()).unwrap();
+                let mut line = String::new();
+                (stdin.read_line(&mut line).unwrap() != 0).then_some(line)
+            });
+            println!("{}", debugger.run(&mir));
         }
         Some("test") => {
             unimplemented!()
         }
         _ => {
-            println!("Invalid command expected run, test or build");
+            println!("Invalid command expected run, diff, fuzz, debug, debug-source, profile, test or build");
         }
     }
 }
-
-const MIR_MODE: bool = false;
@@ -21,18 +21,23 @@ impl TokenExtracter<Vec<Annotation>> for VecDeque<Token> {
                     if let Some(Token::Literal(_, name) | Token::TypeName(_, name)) =
                         self.get_token()
                     {
-                        if let Some(Token::Block(_, ClosableType::Parenthesis, inside)) =
-                            self.get_token()
-                        {
-                            annotations.push(Annotation {
-                                name,
-                                arguments: inside,
-                            });
-                        } else {
-                            annotations.push(Annotation {
-                                name,
-                                arguments: VecDeque::new(),
-                            });
+                        match self.get_token() {
+                            Some(Token::Block(_, ClosableType::Parenthesis, inside)) => {
+                                annotations.push(Annotation {
+                                    name,
+                                    arguments: inside,
+                                });
+                            }
+                            e => {
+                                // The annotation has no arguments
+                                if let Some(e) = e {
+                                    self.push_front(e);
+                                }
+                                annotations.push(Annotation {
+                                    name,
+                                    arguments: VecDeque::new(),
+                                });
+                            }
                         }
                     } else {
                         panic!("Expected annotation name");
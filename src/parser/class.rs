@@ -1,4 +1,7 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+};
 
 use either::Either;
 use errors::{invalid_type_template, report_similar, Error, Span, SpannedObject, SpannedVector};
@@ -40,12 +43,18 @@ impl Class {
     }
 }
 
+/// The name of a method and the template it is resolved with
+type MethodKey = (String, Option<Vec<Type>>);
+
 pub struct ClassView {
     pub ty: Type,
     pub name: SpannedObject<String>,
     pub fields: Vec<Field>,
     pub methods: Vec<Method>,
     pub superclass: Option<Type>,
+    /// The views of the methods already resolved by name and template
+    method_views: RefCell<HashMap<MethodKey, MethodView>>,
+    size: Cell<Option<u32>>,
 }
 
 impl ClassView {
@@ -94,6 +103,8 @@ impl ClassView {
                 .collect(),
             superclass: class.superclass.clone().map(|x| tmp_map.ty(x)),
             name: class.name.clone(),
+            method_views: RefCell::new(HashMap::new()),
+            size: Cell::new(None),
         })
     }
 
@@ -125,15 +136,23 @@ impl ClassView {
         name: &SpannedObject<String>,
         template: &Option<SpannedVector<Type>>,
     ) -> Result<MethodView, Error> {
-        if let Some(e) = self
+        let key = (name.1.clone(), template.as_ref().map(|x| x.1.clone()));
+        if let Some(e) = self.method_views.borrow().get(&key) {
+            return Ok(MethodView {
+                reference: name.0.clone(),
+                template: template.clone(),
+                ..e.clone()
+            });
+        }
+        let view = if let Some(e) = self
             .methods
             .iter()
             .find(|x| x.name.1 == name.1)
-            .map(|x| MethodView::new(x, &name.0, template))
+            .map(|x| MethodView::new(x, &self.ty, &name.0, template))
         {
-            e
+            e?
         } else {
-            Err(report_similar(
+            return Err(report_similar(
                 "method",
                 "methods",
                 &name.0,
@@ -144,33 +163,37 @@ impl ClassView {
                     .map(|x| x.name.1.clone())
                     .collect::<Vec<_>>(),
                 13,
-            ))
-        }
+            ));
+        };
+        self.method_views.borrow_mut().insert(key, view.clone());
+        Ok(view)
     }
 
     pub fn size(&self, cl: &ClassLoader) -> Result<u32, Error> {
-        if *self.name == "Val" {
-            return Ok(1);
+        if let Some(e) = self.size.get() {
+            return Ok(e);
         }
-        if *self.name == "Array" {
-            return Ok({
-                match self.ty.template.as_ref() {
-                    Some(x) => {
-                        let item_size = cl.view(&x.1[0])?.size(cl)?;
-                        let number = x.1[1].name.1.parse::<u32>().unwrap();
-                        item_size * number
-                    }
-                    None => 0,
+        let size = if *self.name == "Val" {
+            1
+        } else if *self.name == "Array" {
+            match self.ty.template.as_ref() {
+                Some(x) => {
+                    let item_size = cl.view(&x.1[0])?.size(cl)?;
+                    let number = x.1[1].name.1.parse::<u32>().unwrap();
+                    item_size * number
                 }
-            });
-        }
-        Ok(self
-            .fields
-            .iter()
-            .map(|x| ClassView::new(cl.get(&x.ty.name)?, &x.ty)?.size(cl))
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
-            .sum::<u32>())
+                None => 0,
+            }
+        } else {
+            self.fields
+                .iter()
+                .map(|x| cl.view(&x.ty)?.size(cl))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .sum::<u32>()
+        };
+        self.size.set(Some(size));
+        Ok(size)
     }
 }
 
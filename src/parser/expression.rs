@@ -1,6 +1,7 @@
 use std::collections::VecDeque;
 
-use errors::{invalid_token_after, Error, Span, SpannedObject, SpannedVector};
+use either::Either;
+use errors::{invalid_token_after, nesting_too_deep, Error, Span, SpannedObject, SpannedVector};
 
 use crate::parser::{
     token_utils::{split_complex, take_until, SplitAction},
@@ -90,112 +91,112 @@ pub enum BooleanOperator {
     Or,
 }
 
-fn chain_expression(tokens: &mut VecDeque<Token>, exp: Expr, types: &Type) -> Result<Expr, Error> {
-    let k = match tokens.get_token() {
-        None | Some(Token::SemiColon(_)) => return Ok(exp),
-        Some(Token::Equals(span)) => Expr::Assignement {
-            span,
-            target: Box::new(exp),
-            to: Box::new(tokens.drain(0..).collect::<VecDeque<_>>().parse(types)?),
-        },
-        Some(Token::Keyword(span, Keyword::As)) => {
-            let t = tokens.extract(types)?;
-            Expr::Cast {
-                span,
-                source: Box::new(exp),
-                target: t,
+/// An operator whose right side is the rest of the expression
+enum Pending {
+    Return(Span),
+    Assignement(Span, Expr),
+    Boolean(Span, Expr, BooleanOperator),
+}
+
+/// Applies the method calls, field accesses and casts that follow `exp`, stops at the end of the
+/// expression or at an operator whose right side is the rest of the tokens
+fn chain_expression(
+    tokens: &mut VecDeque<Token>,
+    mut exp: Expr,
+    types: &Type,
+) -> Result<Either<Expr, Pending>, Error> {
+    loop {
+        exp = match tokens.get_token() {
+            None | Some(Token::SemiColon(_)) => return Ok(Either::Left(exp)),
+            Some(Token::Equals(span)) => {
+                return Ok(Either::Right(Pending::Assignement(span, exp)))
             }
-        }
-        Some(Token::BooleanOperator(span, e)) => Expr::BooleanExpression(
-            span,
-            Box::new(exp),
-            e,
-            Box::new(tokens.drain(0..).collect::<VecDeque<_>>().parse(types)?),
-        ),
-        Some(Token::Dot(_)) => {
-            if let Some(Token::Literal(name_span, name)) = tokens.get_token() {
-                match tokens.get_token() {
-                    Some(Token::Block(arguments_span, ClosableType::Parenthesis, arguments)) => {
-                        Expr::Method {
-                            name: SpannedObject(name_span, name),
-                            span: exp.span().merge(&arguments_span),
-                            source: Box::new(exp),
-                            arguments: SpannedVector(
-                                arguments_span,
-                                split_complex(arguments, |a| {
-                                    if matches!(a, Token::Comma(_)) {
-                                        SplitAction::SplitConsume
-                                    } else {
-                                        SplitAction::None
-                                    }
-                                })
-                                .into_iter()
-                                .map(|a| a.parse(types))
-                                .collect::<Result<_, _>>()?,
-                            ),
-                            template: None,
-                        }
-                    }
-                    Some(Token::Block(template_span, ClosableType::Type, template)) => {
-                        if let Some(Token::Block(
-                            arguments_span,
-                            ClosableType::Parenthesis,
-                            arguments,
-                        )) = tokens.get_token()
-                        {
-                            Expr::Method {
-                                span: exp.span().merge(&arguments_span),
-                                source: Box::new(exp),
-                                name: SpannedObject(name_span, name),
-                                arguments: SpannedVector(
-                                    arguments_span,
-                                    split_complex(arguments, |a| {
-                                        if matches!(a, Token::Comma(_)) {
-                                            SplitAction::SplitConsume
-                                        } else {
-                                            SplitAction::None
-                                        }
-                                    })
-                                    .into_iter()
-                                    .map(|a| a.parse(types))
-                                    .collect::<Result<_, _>>()?,
-                                ),
-                                template: Some(SpannedVector(
-                                    template_span,
-                                    template.parse(types)?,
-                                )),
-                            }
-                        } else {
-                            panic!("Expected brackets after method call")
-                        }
-                    }
-                    e => {
-                        if let Some(e) = e {
-                            tokens.push_front(e);
-                        }
-                        Expr::Field {
-                            source: Box::new(exp),
-                            name,
-                            span: name_span,
-                        }
-                    }
+            Some(Token::Keyword(span, Keyword::As)) => {
+                let t = tokens.extract(types)?;
+                Expr::Cast {
+                    span,
+                    source: Box::new(exp),
+                    target: t,
+                }
+            }
+            Some(Token::BooleanOperator(span, e)) => {
+                return Ok(Either::Right(Pending::Boolean(span, exp, e)))
+            }
+            Some(Token::Dot(_)) => {
+                if let Some(Token::Literal(name_span, name)) = tokens.get_token() {
+                    parse_member(tokens, exp, SpannedObject(name_span, name), types)?
+                } else {
+                    panic!("Expected literal after dot")
                 }
-            } else {
-                panic!("Expected literal after dot")
             }
+            Some(e) => {
+                return Err(invalid_token_after(
+                    e.span(),
+                    exp.span(),
+                    "expression",
+                    &e.name(),
+                    &[";", "||", "&&", "=", ".", "as"],
+                    true,
+                ));
+            }
+        };
+    }
+}
+
+/// Parses the method call or the field access of `exp` after the dot and the name
+fn parse_member(
+    tokens: &mut VecDeque<Token>,
+    exp: Expr,
+    name: SpannedObject<String>,
+    types: &Type,
+) -> Result<Expr, Error> {
+    let template = match tokens.get_token() {
+        Some(Token::Block(template_span, ClosableType::Type, template)) => {
+            Some(SpannedVector(template_span, template.parse(types)?))
         }
         Some(e) => {
-            return Err(invalid_token_after(
-                e.span(),
-                exp.span(),
-                "expression",
-                &e.name(),
-                &[";", "||", "&&", "=", ".", "as"],
-                true,
-            ));
+            tokens.push_front(e);
+            None
         }
+        None => None,
     };
-    chain_expression(tokens, k, types)
+    match tokens.get_token() {
+        Some(Token::Block(arguments_span, ClosableType::Parenthesis, arguments)) => {
+            Ok(Expr::Method {
+                span: exp.span().merge(&arguments_span),
+                source: Box::new(exp),
+                name,
+                arguments: SpannedVector(arguments_span, parse_arguments(arguments, types)?),
+                template,
+            })
+        }
+        _ if template.is_some() => panic!("Expected brackets after method call"),
+        e => {
+            if let Some(e) = e {
+                tokens.push_front(e);
+            }
+            Ok(Expr::Field {
+                source: Box::new(exp),
+                name: name.1,
+                span: name.0,
+            })
+        }
+    }
+}
+
+/// Parses the expressions separated by commas of the arguments of a call
+fn parse_arguments(arguments: VecDeque<Token>, types: &Type) -> Result<Vec<Expr>, Error> {
+    let mut exprs = Vec::new();
+    for a in split_complex(arguments, |a| {
+        if matches!(a, Token::Comma(_)) {
+            SplitAction::SplitConsume
+        } else {
+            SplitAction::None
+        }
+    }) {
+        exprs.push(a.parse(types)?);
+    }
+    Ok(exprs)
 }
 
 fn parse_if(
@@ -203,238 +204,341 @@ fn parse_if(
     if_token_span: Span,
     types: &Type,
 ) -> Result<Expr, Error> {
-    let k = Box::new( take_until(tokens, |e| {
-        matches!(e, Token::Block(_, ClosableType::Brace, _))
-    })
-    .parse(types)?);
-    let if_b = match tokens.get_token() {
-        Some(Token::Block(span, ClosableType::Brace, e)) => SpannedVector(span, e.parse(types)?),
-        Some(e) => {
-            return Err(invalid_token_after(
-                e.span(),
-                e.span(),
-                "if",
-                &e.name(),
-                &["Literal", "TypeName", "Number"],
-                false,
-            ));
-        }
-        None => {
-            return Err(invalid_token_after(
-                &if_token_span,
-                &if_token_span,
-                "if",
-                "",
-                &["Literal", "TypeName", "Number"],
-                false,
-            ));
+    // The `if` and `else if` branches, nested from the last one once all are parsed
+    let mut branches = Vec::new();
+    let mut if_token_span = if_token_span;
+    let mut or_else = loop {
+        let k = Box::new(
+            take_until(tokens, |e| {
+                matches!(e, Token::Block(_, ClosableType::Brace, _))
+            })
+            .parse(types)?,
+        );
+        let if_b = match tokens.get_token() {
+            Some(Token::Block(span, ClosableType::Brace, e)) => {
+                SpannedVector(span, e.parse(types)?)
+            }
+            Some(e) => {
+                return Err(invalid_token_after(
+                    e.span(),
+                    e.span(),
+                    "if",
+                    &e.name(),
+                    &["Literal", "TypeName", "Number"],
+                    false,
+                ));
+            }
+            None => {
+                return Err(invalid_token_after(
+                    &if_token_span,
+                    &if_token_span,
+                    "if",
+                    "",
+                    &["Literal", "TypeName", "Number"],
+                    false,
+                ));
+            }
+        };
+        branches.push((if_token_span.clone(), k, if_b));
+        if !matches!(tokens.front(), Some(Token::Keyword(_, Keyword::Else))) {
+            break None;
         }
-    };
-    let else_b = if matches!(tokens.front(), Some(Token::Keyword(_, Keyword::Else))) {
         tokens.remove(0);
         match tokens.get_token() {
             Some(Token::Block(span, ClosableType::Brace, e)) => {
-                Some(SpannedVector(span, e.parse(types)?))
-            }
-            Some(Token::Keyword(span, Keyword::If)) => {
-                let ifb = parse_if(tokens, span, types)?;
-                Some(SpannedVector(ifb.span().clone(), vec![ifb]))
+                break Some(SpannedVector(span, e.parse(types)?));
             }
+            Some(Token::Keyword(span, Keyword::If)) => if_token_span = span,
             _ => panic!("Expected brackets after else"),
         }
-    } else {
-        None
     };
-    Ok(Expr::If {
-        condition: k,
-        span: if_token_span.merge(else_b.as_ref().map(|e| &e.0).unwrap_or_else(|| &if_b.0)),
-        then: if_b,
-        or_else: else_b,
-    })
+    let mut out = None;
+    while let Some((if_token_span, condition, if_b)) = branches.pop() {
+        if let Some(ifb) = out.take() {
+            let ifb: Expr = ifb;
+            or_else = Some(SpannedVector(ifb.span().clone(), vec![ifb]));
+        }
+        out = Some(Expr::If {
+            condition,
+            span: if_token_span.merge(or_else.as_ref().map(|e| &e.0).unwrap_or(&if_b.0)),
+            then: if_b,
+            or_else: or_else.take(),
+        });
+    }
+    Ok(out.expect("An if has at least one branch"))
 }
 
 impl TokenParser<Expr> for VecDeque<Token> {
     fn parse(mut self, types: &Type) -> Result<Expr, Error> {
-        let tk = if let Some(e) = self.get_token() {
-            e
-        } else {
-            panic!("Expected expression")
-        };
-        let j = match tk {
-            Token::Comma(_) => panic!("Unexpected comma"),
-            Token::At(_) => panic!("Unexpected Annotation"),
-            Token::Dot(_) => panic!("Unexpected comma"),
-            Token::DoubleDot(_) => panic!("Unexpected comma"),
-            Token::SemiColon(_) => panic!("Unexpected comma"),
-            Token::BooleanOperator(_, _) => panic!("Unexpected boolean operator"),
-            Token::Equals(_) => {
-                println!("{:?}", self);
-                panic!("Unexpected equals")
-            }
-            Token::Literal(span, a) => Expr::Variable(span, a),
-            Token::Keyword(span, a) => match a {
-                Keyword::Return => {
-                    let out: Expr = self.parse(types)?;
-                    return Ok(Expr::Return(span.merge(out.span()), Some(Box::new(out))));
+        // The operators are applied once their right side is parsed
+        let mut pending = Vec::new();
+        let mut exp = loop {
+            let exp = match parse_operand(&mut self, types)? {
+                Either::Left(e) => e,
+                Either::Right(e) => {
+                    pending.push(e);
+                    continue;
                 }
-                Keyword::If => parse_if(&mut self, span, types)?,
-                Keyword::Else => panic!("Unexpected else"),
-                Keyword::Class => panic!("Unexpected class"),
-                Keyword::Extends => panic!("Unexpected extends"),
-                Keyword::As => panic!("Unexpected as"),
-                Keyword::Loop => {
-                    let cb = if let Some(Token::Block(span, ClosableType::Brace, e)) =
-                        self.get_token()
-                    {
-                        SpannedVector(span, e.parse(types)?)
-                    } else {
-                        panic!("Expected brackets after loop")
-                    };
-                    Expr::Loop(span.merge(&cb.0), cb)
+            };
+            match chain_expression(&mut self, exp, types)? {
+                Either::Left(e) => break e,
+                Either::Right(e) => pending.push(e),
+            }
+        };
+        while let Some(e) = pending.pop() {
+            exp = match e {
+                Pending::Return(span) => Expr::Return(span.merge(exp.span()), Some(Box::new(exp))),
+                Pending::Assignement(span, target) => Expr::Assignement {
+                    span,
+                    target: Box::new(target),
+                    to: Box::new(exp),
+                },
+                Pending::Boolean(span, left, e) => {
+                    Expr::BooleanExpression(span, Box::new(left), e, Box::new(exp))
                 }
-                Keyword::Continue => Expr::Continue(span),
-                Keyword::Break => Expr::Break(span),
-                Keyword::While => panic!("Not yet implemented"),
-                Keyword::For => panic!("Not yet implemented"),
-                Keyword::In => panic!("Unexpected in"),
-            },
-            Token::Number(span, a, t) => Expr::Number(span, a, t),
-            Token::TypeName(span, a) => {
-                let template: Option<SpannedVector<Type>> = match self.get_token() {
-                    Some(Token::Block(tspan, ClosableType::Type, e)) => {
-                        Some(SpannedVector(tspan, e.parse(types)?))
-                    }
-                    Some(e) => {
-                        self.push_front(e);
-                        None
-                    }
-                    None => None,
-                };
-                let (a, template) = if a == "Self" {
-                    if template.is_none() {
-                        (types.name.1.clone(), types.template.clone())
-                    } else {
-                        (types.name.1.clone(), template)
-                    }
+            };
+        }
+        Ok(exp)
+    }
+}
+
+/// Parses an expression up to the first method call, field access, cast or operator
+fn parse_operand(
+    tokens: &mut VecDeque<Token>,
+    types: &Type,
+) -> Result<Either<Expr, Pending>, Error> {
+    let tk = if let Some(e) = tokens.get_token() {
+        e
+    } else {
+        panic!("Expected expression")
+    };
+    Ok(Either::Left(match tk {
+        Token::Comma(_) => panic!("Unexpected comma"),
+        Token::At(_) => panic!("Unexpected Annotation"),
+        Token::Dot(_) => panic!("Unexpected comma"),
+        Token::DoubleDot(_) => panic!("Unexpected comma"),
+        Token::SemiColon(_) => panic!("Unexpected comma"),
+        Token::BooleanOperator(_, _) => panic!("Unexpected boolean operator"),
+        Token::Equals(_) => {
+            println!("{:?}", tokens);
+            panic!("Unexpected equals")
+        }
+        Token::Literal(span, a) => Expr::Variable(span, a),
+        Token::Keyword(span, a) => match a {
+            Keyword::Return => return Ok(Either::Right(Pending::Return(span))),
+            Keyword::If => parse_if(tokens, span, types)?,
+            Keyword::Else => panic!("Unexpected else"),
+            Keyword::Class => panic!("Unexpected class"),
+            Keyword::Extends => panic!("Unexpected extends"),
+            Keyword::As => panic!("Unexpected as"),
+            Keyword::Loop => {
+                let cb = if let Some(Token::Block(span, ClosableType::Brace, e)) =
+                    tokens.get_token()
+                {
+                    SpannedVector(span, e.parse(types)?)
                 } else {
-                    (a, template)
+                    panic!("Expected brackets after loop")
                 };
-                match self.get_token() {
-                    Some(Token::Block(span_block, ClosableType::Brace, inside)) => Expr::New {
-                        span: span.merge(&span_block),
-                        class: Type::new(&a, template, span),
-                        fields: SpannedVector(
-                            span_block,
-                            split_complex(inside, |a| {
-                                if matches!(a, Token::Comma(_)) {
-                                    SplitAction::SplitConsume
-                                } else {
-                                    SplitAction::None
-                                }
-                            })
-                            .into_iter()
-                            .map(|mut a| {
-                                let name = if let Some(Token::Literal(_, name)) = a.get_token() {
-                                    name
-                                } else {
-                                    panic!("Expected argument name");
-                                };
-                                if !matches!(a.get_token(), Some(Token::Equals(_))) {
-                                    panic!("Expected equals");
-                                };
-                                let value = a.parse(types)?;
-                                Ok((name, value))
-                            })
-                            .collect::<Result<_, _>>()?,
-                        ),
-                    },
-                    Some(Token::Literal(lspan, literal)) => Expr::NamedResource {
-                        span: span.merge(&lspan),
-                        vtype: Type::new(&a, template, span),
-                        name: SpannedObject(lspan, literal),
-                    },
-                    Some(e) => {
-                        self.push_front(e);
-                        let ty = Type::new(&a, template, span);
-                        Expr::Type(ty.span.clone(), ty)
-                    }
-                    None => {
-                        let ty = Type::new(&a, template, span);
-                        Expr::Type(ty.span.clone(), ty)
-                    }
-                }
-            }
-            Token::Block(span, ClosableType::Brace, b) => {
-                Expr::Block(span.clone(), SpannedVector(span, b.parse(types)?))
+                Expr::Loop(span.merge(&cb.0), cb)
             }
-            Token::Block(_, ClosableType::Parenthesis, b) => b.parse(types)?,
-            Token::Block(_, ClosableType::Type, _) => panic!("Unexpected template"),
-            Token::Char(span, a) => Expr::Number(
-                span,
-                a.chars().fold(0, |acc, c| acc * 255 + c as u8 as i32),
-                NumberType::Byte,
-            ),
-            Token::Comment(_, _) => return self.parse(types),
-            Token::String(a, b) => Expr::ArrayDefinition(
+            Keyword::Continue => Expr::Continue(span),
+            Keyword::Break => Expr::Break(span),
+            Keyword::While => panic!("Not yet implemented"),
+            Keyword::For => panic!("Not yet implemented"),
+            Keyword::In => panic!("Unexpected in"),
+        },
+        Token::Number(span, a, t) => Expr::Number(span, a, t),
+        Token::TypeName(span, a) => parse_type_name(tokens, span, a, types)?,
+        Token::Block(span, ClosableType::Brace, b) => {
+            Expr::Block(span.clone(), SpannedVector(span, b.parse(types)?))
+        }
+        Token::Block(_, ClosableType::Parenthesis, b) => b.parse(types)?,
+        Token::Block(_, ClosableType::Type, _) => panic!("Unexpected template"),
+        Token::Char(span, a) => Expr::Number(
+            span,
+            a.chars().fold(0, |acc, c| acc * 255 + c as u8 as i32),
+            NumberType::Byte,
+        ),
+        Token::Comment(_, _) => return parse_operand(tokens, types),
+        Token::String(a, b) => Expr::ArrayDefinition(
+            a.clone(),
+            SpannedVector(
                 a.clone(),
-                SpannedVector(
-                    a.clone(),
-                    b.chars()
-                        .enumerate()
-                        .map(|(i, x)| {
-                            Expr::Number(
-                                Span {
-                                    file: a.file.clone(),
-                                    start: a.start + i,
-                                    end: a.start + i + 1,
-                                },
-                                x as i32,
-                                NumberType::Byte,
-                            )
-                        })
-                        .collect(),
-                ),
-            ),
-            Token::Block(b, ClosableType::Bracket, inside) => Expr::ArrayDefinition(
-                b.clone(),
-                SpannedVector(
-                    b,
-                    split_complex(inside, |a| {
-                        if matches!(a, Token::Comma(_)) {
-                            SplitAction::SplitConsume
-                        } else {
-                            SplitAction::None
-                        }
+                b.chars()
+                    .enumerate()
+                    .map(|(i, x)| {
+                        Expr::Number(
+                            Span {
+                                file: a.file.clone(),
+                                start: a.start + i,
+                                end: a.start + i + 1,
+                            },
+                            x as i32,
+                            NumberType::Byte,
+                        )
                     })
-                    .into_iter()
-                    .map(|x| x.parse(types))
-                    .collect::<Result<_, _>>()?,
-                ),
+                    .collect(),
             ),
-        };
-        chain_expression(&mut self, j, types)
-    }
+        ),
+        Token::Block(b, ClosableType::Bracket, inside) => {
+            Expr::ArrayDefinition(b.clone(), SpannedVector(b, parse_arguments(inside, types)?))
+        }
+    }))
+}
+
+/// Parses what follows a type name: an instance with its fields, a variable declaration or the
+/// type itself
+fn parse_type_name(
+    tokens: &mut VecDeque<Token>,
+    span: Span,
+    a: String,
+    types: &Type,
+) -> Result<Expr, Error> {
+    let template: Option<SpannedVector<Type>> = match tokens.get_token() {
+        Some(Token::Block(tspan, ClosableType::Type, e)) => {
+            Some(SpannedVector(tspan, e.parse(types)?))
+        }
+        Some(e) => {
+            tokens.push_front(e);
+            None
+        }
+        None => None,
+    };
+    let (a, template) = if a == "Self" {
+        if template.is_none() {
+            (types.name.1.clone(), types.template.clone())
+        } else {
+            (types.name.1.clone(), template)
+        }
+    } else {
+        (a, template)
+    };
+    Ok(match tokens.get_token() {
+        Some(Token::Block(span_block, ClosableType::Brace, inside)) => Expr::New {
+            span: span.merge(&span_block),
+            class: Type::new(&a, template, span),
+            fields: SpannedVector(
+                span_block,
+                split_complex(inside, |a| {
+                    if matches!(a, Token::Comma(_)) {
+                        SplitAction::SplitConsume
+                    } else {
+                        SplitAction::None
+                    }
+                })
+                .into_iter()
+                .map(|mut a| {
+                    let name = if let Some(Token::Literal(_, name)) = a.get_token() {
+                        name
+                    } else {
+                        panic!("Expected argument name");
+                    };
+                    if !matches!(a.get_token(), Some(Token::Equals(_))) {
+                        panic!("Expected equals");
+                    };
+                    let value = a.parse(types)?;
+                    Ok((name, value))
+                })
+                .collect::<Result<_, _>>()?,
+            ),
+        },
+        Some(Token::Literal(lspan, literal)) => Expr::NamedResource {
+            span: span.merge(&lspan),
+            vtype: Type::new(&a, template, span),
+            name: SpannedObject(lspan, literal),
+        },
+        Some(e) => {
+            tokens.push_front(e);
+            let ty = Type::new(&a, template, span);
+            Expr::Type(ty.span.clone(), ty)
+        }
+        None => {
+            let ty = Type::new(&a, template, span);
+            Expr::Type(ty.span.clone(), ty)
+        }
+    })
 }
 
 pub type CodeBlock = SpannedVector<Expr>;
 
+/// Checks that the expressions of the code aren't nested more than `max_nesting` times, without
+/// recursing so that the check itself can't overflow the stack
+pub fn check_nesting(code: &CodeBlock, max_nesting: usize) -> Result<(), Error> {
+    let mut stack: Vec<(&Expr, usize)> = code.1.iter().map(|x| (x, 1)).collect();
+    while let Some((expr, depth)) = stack.pop() {
+        if depth > max_nesting {
+            return Err(nesting_too_deep(expr.span(), max_nesting));
+        }
+        stack.extend(expr.children().into_iter().map(|x| (x, depth + 1)));
+    }
+    Ok(())
+}
+
 impl TokenParser<Vec<Expr>> for VecDeque<Token> {
     fn parse(self, types: &Type) -> Result<Vec<Expr>, Error> {
-        split_complex(self, |a| {
+        // A loop rather than an iterator chain, the adapters would add frames to every nested block
+        let mut exprs = Vec::new();
+        for a in split_complex(self, |a| {
             if matches!(a, Token::SemiColon(_)) {
                 SplitAction::SplitConsume
             } else {
                 SplitAction::None
             }
-        })
-        .into_iter()
-        .filter(|a| a.length() != 0)
-        .map(|a| a.parse(types))
-        .collect::<Result<_, _>>()
+        }) {
+            if a.length() != 0 {
+                exprs.push(a.parse(types)?);
+            }
+        }
+        Ok(exprs)
     }
 }
 
 impl Expr {
+    /// The expressions directly inside this one
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::New { fields, .. } => fields.1.iter().map(|(_, x)| x).collect(),
+            Expr::If {
+                condition,
+                then,
+                or_else,
+                ..
+            } => std::iter::once(&**condition)
+                .chain(then.1.iter())
+                .chain(or_else.iter().flat_map(|x| x.1.iter()))
+                .collect(),
+            Expr::Cast { source, .. } | Expr::Field { source, .. } => vec![source],
+            Expr::Method {
+                source, arguments, ..
+            } => std::iter::once(&**source)
+                .chain(arguments.1.iter())
+                .collect(),
+            Expr::Assignement { target, to, .. } => vec![target, to],
+            Expr::Loop(_, a) | Expr::Block(_, a) | Expr::ArrayDefinition(_, a) => {
+                a.1.iter().collect()
+            }
+            Expr::Return(_, a) => a.iter().map(|x| &**x).collect(),
+            Expr::BooleanExpression(_, a, _, b) => vec![a, b],
+            Expr::Number(..)
+            | Expr::Variable(..)
+            | Expr::Type(..)
+            | Expr::NamedResource { .. }
+            | Expr::Break(_)
+            | Expr::Continue(_) => Vec::new(),
+        }
+    }
+
+    /// The span from the first to the last token of the expression
+    pub fn full_span(&self) -> Span {
+        let mut span = self.span().clone();
+        let mut stack = self.children();
+        while let Some(e) = stack.pop() {
+            span = span.merge(e.span());
+            stack.extend(e.children());
+        }
+        span
+    }
+
     pub fn span(&self) -> &Span {
         match self {
             Expr::New { span, .. }
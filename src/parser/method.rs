@@ -6,15 +6,18 @@ use std::{
 
 use either::Either;
 use errors::{
-    invalid_argument_type, invalid_type_template, Error, Span, SpannedObject, SpannedVector,
+    invalid_argument_type, invalid_recursion_depth, invalid_type_template, recursive_method, Error,
+    Span, SpannedObject, SpannedVector,
 };
-use mir::{need_block, remove_skips, Mir, MirCodeBlock};
+use mir::{need_block, remove_skips, DebugMarker, Mir, MirCodeBlock, Procedure, MAX_CALLS};
 
 use crate::{
     compiler::{
         compiler::compile_code_block,
         state::{
-            code_manager::CodeManager, local_state::LocalState, output_data::OutputData,
+            code_manager::{CodeManager, Outlined, StackFrame},
+            local_state::LocalState,
+            output_data::OutputData,
             typed_definition::TypedMemory,
         },
     },
@@ -56,12 +59,29 @@ impl Debug for Method {
     }
 }
 
+/// The methods called more than once whose code has at least this many instructions are
+/// compiled once and called instead of being inlined, unless they are annotated with `@Inline`
+const OUTLINE_THRESHOLD: usize = 2000;
+/// How many times bigger than the values copied by a call the code of an outlined method must be
+const OUTLINE_RATIO: usize = 8;
+/// How many calls of a `@Recursive` method can run at the same time when it doesn't say
+const DEFAULT_RECURSION_DEPTH: u8 = 8;
+/// The stack pointer of a `@Recursive` method is a single variable
+const MAX_RECURSION_DEPTH: u8 = 15;
+
+#[derive(Clone)]
 pub struct MethodView {
+    /// The class the method is called on
+    pub class: Type,
+    /// Where the method is called
+    pub reference: Span,
     pub name: SpannedObject<String>,
+    pub annotations: Vec<Annotation>,
     pub return_type: Option<Type>,
     pub arguments: Vec<(Type, String)>,
     pub template: Option<SpannedVector<Type>>,
-    pub code: Either<CodeBlock, NativeMethod>,
+    /// Shared by the views of the method with the same template
+    pub code: Either<Rc<CodeBlock>, NativeMethod>,
 }
 
 pub type NativeMethod =
@@ -70,6 +90,7 @@ pub type NativeMethod =
 impl MethodView {
     pub fn new(
         method: &Method,
+        class: &Type,
         namerefspan: &Span,
         template: &Option<SpannedVector<Type>>,
     ) -> Result<Self, Error> {
@@ -98,8 +119,11 @@ impl MethodView {
         };
 
         Ok(Self {
+            class: class.clone(),
+            reference: namerefspan.clone(),
             template: template.clone(),
             name: method.name.clone(),
+            annotations: method.annotations.clone(),
             return_type: method.return_type.as_ref().map(|x| tmp_map.ty(x.clone())),
             arguments: method
                 .arguments
@@ -107,10 +131,10 @@ impl MethodView {
                 .map(|(x, y)| (tmp_map.ty(x.clone()), y.clone()))
                 .collect(),
             code: match &method.code {
-                Either::Left(a) => Either::Left(SpannedVector(
+                Either::Left(a) => Either::Left(Rc::new(SpannedVector(
                     a.0.clone(),
                     a.1.iter().map(|x| tmp_map.expr(x.clone())).collect(),
-                )),
+                ))),
                 Either::Right(a) => Either::Right(a.clone()),
             },
         })
@@ -122,21 +146,67 @@ impl MethodView {
             .ok_or_else(|| invalid_type_template(&self.name.0, &self.name.0))
     }
 
-    pub fn execute(
-        &self,
-        ls: &mut LocalState,
-        cm: &mut CodeManager,
-        arguments: Vec<TypedMemory>,
-    ) -> Result<OutputData, Error> {
-        let return_loc = match self.return_type.as_ref() {
+    fn has_annotation(&self, name: &str) -> bool {
+        self.annotations.iter().any(|x| x.name == name)
+    }
+
+    /// Identifies this version of the method, every call of it can share the same code
+    fn key(&self) -> String {
+        match &self.template {
+            Some(e) => format!(
+                "{:?}.{}<{}>",
+                self.class,
+                self.name.1,
+                e.1.iter()
+                    .map(|x| format!("{:?}", x))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            None => format!("{:?}.{}", self.class, self.name.1),
+        }
+    }
+
+    fn return_span(&self) -> Span {
+        self.return_type
+            .as_ref()
+            .map(|x| x.span.clone())
+            .unwrap_or_else(|| self.name.0.clone())
+    }
+
+    fn return_location(&self, cm: &mut CodeManager) -> Result<Option<TypedMemory>, Error> {
+        Ok(match self.return_type.as_ref() {
             Some(x) => Some(TypedMemory::new(
                 x.clone(),
                 cm.alloc_type(x)?,
                 self.name.0.clone(),
             )),
             None => None,
-        };
-        let mut ls = ls.shadow_method(return_loc.clone());
+        })
+    }
+
+    /// How many calls of a `@Recursive` method can run at the same time
+    fn recursion_depth(&self) -> Result<u8, Error> {
+        let arguments = self
+            .annotations
+            .iter()
+            .find(|x| x.name == "Recursive")
+            .map(|x| x.arguments.iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        match arguments.as_slice() {
+            [] => Ok(DEFAULT_RECURSION_DEPTH),
+            [Token::Number(_, a, _)] if (1..=MAX_RECURSION_DEPTH as i32).contains(a) => {
+                Ok(*a as u8)
+            }
+            [e, ..] => Err(invalid_recursion_depth(e.span(), MAX_RECURSION_DEPTH)),
+        }
+    }
+
+    pub fn execute(
+        &self,
+        ls: &mut LocalState,
+        cm: &mut CodeManager,
+        arguments: Vec<TypedMemory>,
+    ) -> Result<OutputData, Error> {
         for (x, y) in self.arguments.iter().zip(arguments.iter()) {
             if x.0 != y.ty {
                 return Err(invalid_argument_type(
@@ -145,6 +215,67 @@ impl MethodView {
                     &format!("{:?}", y.ty),
                 ));
             }
+        }
+        let key = self.key();
+        let out = if let Some(i) = cm.call_stack.iter().position(|x| x.method == key) {
+            if !self.has_annotation("Recursive") {
+                let mut chain: Vec<_> = cm.call_stack[i..]
+                    .iter()
+                    .map(|x| (x.method.clone(), x.call.clone()))
+                    .collect();
+                chain.push((key, self.reference.clone()));
+                return Err(recursive_method(&chain));
+            }
+            self.call_recursive(cm, &arguments, i)?
+        } else {
+            cm.call_stack.push(StackFrame {
+                method: key,
+                call: self.reference.clone(),
+                start: cm.allocated(),
+                outlined: false,
+            });
+            let out = self.compile_call(ls, cm, arguments);
+            cm.call_stack.pop();
+            out?
+        };
+        Ok(self.scoped(cm, out))
+    }
+
+    /// Surrounds the code of a call with the markers telling the source debugger which method
+    /// runs
+    fn scoped(&self, cm: &CodeManager, mut out: OutputData) -> OutputData {
+        if cm.debug_scopes {
+            // The location of the rest of the statement would make the debugger stop at the
+            // statement again, it knows that the caller runs again from the frames
+            if let Some(Mir::Location(..)) = out.mir.0.last() {
+                out.mir.0.pop();
+            }
+            let mut mir = MirCodeBlock(vec![Mir::Debug(DebugMarker::Enter {
+                method: self.key(),
+                call: self.reference.clone(),
+            })]);
+            mir.add(out.mir).add_mir(Mir::Debug(DebugMarker::Leave));
+            out.mir = mir;
+        }
+        out
+    }
+
+    fn compile_call(
+        &self,
+        ls: &mut LocalState,
+        cm: &mut CodeManager,
+        arguments: Vec<TypedMemory>,
+    ) -> Result<OutputData, Error> {
+        if let Either::Left(a) = &self.code {
+            if let Some(e) = self.call_outlined(a, cm, &arguments)? {
+                return Ok(e);
+            }
+        }
+        let return_loc = self.return_location(cm)?;
+        let mut ls = ls.shadow_method(return_loc.clone());
+        let mut mir = MirCodeBlock::default();
+        for (x, y) in self.arguments.iter().zip(arguments.iter()) {
+            cm.declare(&mut mir, &x.1, y);
             ls.vars.insert(x.1.clone(), y.clone());
         }
 
@@ -156,22 +287,249 @@ impl MethodView {
                 (jk, lc)
             }
         };
-        let mir = if need_block(&k.mir.0) {
-            vec![Mir::Block(k.mir)]
-        } else {
-            remove_skips(k.mir.0, false)
-        };
+        mir.add(into_body(k.mir));
+        if cm.debug_info && self.code.is_left() {
+            // The rest of the statement comes from the call
+            let mut methods = cm.methods();
+            methods.pop();
+            mir.add_mir(Mir::Location(self.reference.clone(), methods));
+        }
         // Maybe later add tail auto return
         Ok(OutputData {
             return_value,
-            span: self
-                .return_type
-                .as_ref()
-                .map(|x| x.span.clone())
-                .unwrap_or_else(|| self.name.0.clone()),
-            mir: MirCodeBlock(mir),
+            span: self.return_span(),
+            mir,
         })
     }
+
+    /// Calls the code the method was compiled to once, `None` if the method is inlined
+    fn call_outlined(
+        &self,
+        code: &CodeBlock,
+        cm: &mut CodeManager,
+        arguments: &[TypedMemory],
+    ) -> Result<Option<OutputData>, Error> {
+        if self.has_annotation("Inline") {
+            return Ok(None);
+        }
+        let key = self.key();
+        // A method called once is never worth outlining
+        let annotated = self.has_annotation("NoInline") || self.has_annotation("Recursive");
+        if !cm.procedures.contains_key(&key) && (annotated || cm.inlined.contains_key(&key)) {
+            self.outline(code, cm)?;
+        }
+        let outlined = match cm.procedures.get_mut(&key) {
+            Some(Some(e)) if e.calls < MAX_CALLS => e,
+            _ => {
+                *cm.inlined.entry(key).or_default() += 1;
+                return Ok(None);
+            }
+        };
+        outlined.calls += 1;
+        let mut mir = MirCodeBlock::default();
+        let accesses = outlined.procedure.accesses();
+        for (param, arg) in argument_cells(&outlined.arguments, arguments) {
+            if accesses.contains(&param) {
+                mir.copy(param, arg);
+            }
+        }
+        mir.add_mir(Mir::Call(outlined.procedure.clone()));
+        // The method can modify its arguments like `self`
+        let writes = outlined.procedure.writes();
+        for (param, arg) in argument_cells(&outlined.arguments, arguments) {
+            if writes.contains(&param) {
+                mir.copy(arg, param);
+            }
+        }
+        // The next calls overwrite the return value so each call copies it
+        let return_value = outlined.return_value.clone();
+        let return_value = match return_value {
+            Some(e) => {
+                let locations = cm.alloc_block(e.locations.len());
+                mir.copy_bulk(&locations, &e.locations, &e.span)?;
+                Some(TypedMemory::new(e.ty, locations, e.span))
+            }
+            None => None,
+        };
+        Ok(Some(OutputData {
+            return_value,
+            span: self.return_span(),
+            mir,
+        }))
+    }
+
+    /// Calls a `@Recursive` method from its own code. The variables of the calls in progress
+    /// since the first one, at `index` in the call stack, are pushed on a stack during the call.
+    fn call_recursive(
+        &self,
+        cm: &mut CodeManager,
+        arguments: &[TypedMemory],
+        index: usize,
+    ) -> Result<OutputData, Error> {
+        let depth = self.recursion_depth()?;
+        let (procedure, parameters, return_value, stack_pointer) =
+            match cm.procedures.get(&self.key()) {
+                Some(Some(e)) => (
+                    e.procedure.clone(),
+                    e.arguments.clone(),
+                    e.return_value.clone(),
+                    e.stack_pointer.expect("Recursive method without stack"),
+                ),
+                _ => unreachable!("Recursive methods are always outlined"),
+            };
+        // The variables created after this call are set before being read
+        let frame: Vec<u32> = (cm.call_stack[index].start + 1..=cm.allocated())
+            .filter(|x| *x != stack_pointer)
+            .collect();
+        let slots: Vec<Vec<u32>> = (0..depth)
+            .map(|_| cm.alloc_block(frame.len()))
+            .collect();
+        let save = |to_stack: bool| -> Vec<(MirCodeBlock, Vec<u8>)> {
+            slots
+                .iter()
+                .enumerate()
+                .map(|(i, slot)| {
+                    let copies = frame.iter().zip(slot).map(|(a, b)| {
+                        if to_stack {
+                            Mir::Copy(*b, *a)
+                        } else {
+                            Mir::Copy(*a, *b)
+                        }
+                    });
+                    (MirCodeBlock(copies.collect()), vec![i as u8])
+                })
+                .collect()
+        };
+        let mut mir = MirCodeBlock::default();
+        let mut push = save(true);
+        push.push((stack_overflow(), (depth..16).collect()));
+        mir.add_mir(Mir::Match(stack_pointer, push))
+            .add_mir(Mir::Increment(stack_pointer));
+        for (param, arg) in argument_cells(&parameters, arguments) {
+            mir.copy(param, arg);
+        }
+        mir.add_mir(Mir::Call(procedure));
+        // The values given back are moved out of the frame before it is restored
+        let given: Vec<(u32, u32)> = argument_cells(&parameters, arguments)
+            .map(|(param, arg)| (cm.alloc(), param, arg))
+            .map(|(temp, param, arg)| {
+                mir.copy(temp, param);
+                (temp, arg)
+            })
+            .collect();
+        let return_value = match return_value {
+            Some(e) => {
+                let locations = cm.alloc_block(e.locations.len());
+                mir.copy_bulk(&locations, &e.locations, &e.span)?;
+                Some(TypedMemory::new(e.ty, locations, e.span))
+            }
+            None => None,
+        };
+        mir.add_mir(Mir::Decrement(stack_pointer))
+            .add_mir(Mir::Match(stack_pointer, save(false)));
+        for (temp, arg) in given {
+            mir.copy(arg, temp);
+        }
+        Ok(OutputData {
+            return_value,
+            span: self.return_span(),
+            mir,
+        })
+    }
+
+    /// Compiles the method with its arguments and return value at fixed locations and adds it
+    /// to the procedures, unless it is better to inline it
+    fn outline(&self, code: &CodeBlock, cm: &mut CodeManager) -> Result<(), Error> {
+        let key = self.key();
+        let recursive = self.has_annotation("Recursive");
+        let stack_pointer = if recursive {
+            self.recursion_depth()?;
+            Some(cm.alloc())
+        } else {
+            None
+        };
+        let return_value = self.return_location(cm)?;
+        let mut ls = LocalState {
+            vars: HashMap::new(),
+            return_loc: return_value.clone(),
+        };
+        let mut arguments = Vec::new();
+        let mut body = MirCodeBlock::default();
+        for (ty, name) in &self.arguments {
+            let tm = TypedMemory::new(ty.clone(), cm.alloc_type(ty)?, self.name.0.clone());
+            cm.declare(&mut body, name, &tm);
+            ls.vars.insert(name.clone(), tm.clone());
+            arguments.push(tm);
+        }
+        let procedure = Procedure::new(key.clone(), (cm.alloc(), cm.alloc()));
+        let mut outlined = Some(Outlined {
+            procedure: procedure.clone(),
+            arguments,
+            return_value,
+            stack_pointer,
+            calls: 0,
+        });
+        // The recursive calls are compiled while the method is
+        if recursive {
+            cm.procedures.insert(key.clone(), outlined.take());
+        }
+        // The locations of the code start at the method, the same code runs for every call
+        cm.call_stack.last_mut().unwrap().outlined = true;
+        let code = compile_code_block(code, &mut ls, cm, code.0.clone());
+        cm.call_stack.last_mut().unwrap().outlined = false;
+        body.add(into_body(code?.mir));
+        let copied: usize = ls
+            .vars
+            .values()
+            .chain(ls.return_loc.iter())
+            .map(|x| x.locations.len())
+            .sum();
+        let size = body.instr_count();
+        if !self.has_annotation("NoInline")
+            && !recursive
+            && (size < OUTLINE_THRESHOLD || size < OUTLINE_RATIO * copied)
+        {
+            cm.procedures.insert(key, None);
+            return Ok(());
+        }
+        procedure.set_code(body);
+        if let Some(outlined) = outlined {
+            cm.procedures.insert(key, Some(outlined));
+        }
+        Ok(())
+    }
+}
+
+/// The pairs of cells of the parameters of a procedure and of the arguments given to it
+fn argument_cells<'a>(
+    parameters: &'a [TypedMemory],
+    arguments: &'a [TypedMemory],
+) -> impl Iterator<Item = (u32, u32)> + 'a {
+    parameters
+        .iter()
+        .zip(arguments)
+        .flat_map(|(x, y)| x.locations.iter().copied().zip(y.locations.iter().copied()))
+}
+
+/// Prints that the stack of a recursive method is full and stops the program
+fn stack_overflow() -> MirCodeBlock {
+    let mut mir = MirCodeBlock::default();
+    for byte in "Stack overflow\n".bytes() {
+        mir.add_mir(Mir::WriteRegister(1, Either::Left(byte / 16)))
+            .add_mir(Mir::WriteRegister(2, Either::Left(byte % 16)))
+            .add_mir(Mir::WriteRegister(0, Either::Left(1)));
+    }
+    mir.add_mir(Mir::Stop);
+    mir
+}
+
+/// The code of a method, the returns skip the rest of it
+fn into_body(mir: MirCodeBlock) -> MirCodeBlock {
+    MirCodeBlock(if need_block(&mir.0) {
+        vec![Mir::Block(mir)]
+    } else {
+        remove_skips(mir.0, false)
+    })
 }
 
 impl TokenParser<Method> for VecDeque<Token> {
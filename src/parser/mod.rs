@@ -1,6 +1,6 @@
 use std::collections::VecDeque;
 
-use errors::{invalid_token, Error, Span};
+use errors::{invalid_token, nesting_too_deep, Error, Span};
 
 use crate::parser::ty::Type;
 
@@ -78,13 +78,59 @@ fn validate(current_token: Token) -> Token {
     }
 }
 
+/// Closes the innermost open block, returns the type of the block if none is open
+fn close_block(
+    token_map: &mut VecDeque<Token>,
+    open: &mut Vec<(usize, VecDeque<Token>)>,
+    closable_type: ClosableType,
+    span: Span,
+    max_nesting: usize,
+) -> Result<Option<ClosableType>, Error> {
+    let (start, outer) = match open.pop() {
+        Some(e) => e,
+        None => return Ok(Some(closable_type)),
+    };
+    let inside = std::mem::replace(token_map, outer);
+    check_chains(&inside, max_nesting)?;
+    token_map.push_back(Token::Block(
+        Span::new(span.file, start, span.end),
+        closable_type,
+        inside,
+    ));
+    Ok(None)
+}
+
+/// Checks that no expression chains more than `max_nesting` operators, method calls or
+/// `else if`, each of them nests the expression once more
+fn check_chains(tokens: &VecDeque<Token>, max_nesting: usize) -> Result<(), Error> {
+    let mut chained = 0;
+    for token in tokens {
+        match token {
+            Token::SemiColon(_) | Token::Comma(_) => chained = 0,
+            Token::Dot(_)
+            | Token::Equals(_)
+            | Token::BooleanOperator(..)
+            | Token::Keyword(_, Keyword::As | Keyword::Else | Keyword::Return) => chained += 1,
+            _ => (),
+        }
+        if chained > max_nesting {
+            return Err(nesting_too_deep(token.span(), max_nesting));
+        }
+    }
+    Ok(())
+}
+
+/// Splits the characters in tokens, the blocks can't be nested more than `max_nesting` times
 pub fn parse(
     token_map: &mut VecDeque<Token>,
     char: &mut VecDeque<char>,
     initial_size: usize,
     file: &str,
+    max_nesting: usize,
 ) -> Result<Option<ClosableType>, Error> {
     let mut current_token = None;
+    // The start and the tokens before each block being parsed
+    let mut open: Vec<(usize, VecDeque<Token>)> = Vec::new();
     while let Some(c) = char.pop_front() {
         let current = initial_size - char.len() - 1;
         match c {
@@ -179,31 +225,30 @@ pub fn parse(
                 if let Some(e) = current_token.take() {
                     token_map.push_back(validate(e));
                 }
-                let mut vec = VecDeque::new();
-                let closable_type = parse(&mut vec, char, initial_size, file)?.unwrap();
-                token_map.push_back(Token::Block(
-                    Span::new(file.to_owned(), current, initial_size - char.len()),
-                    closable_type,
-                    vec,
-                ));
-            }
-            '}' => {
-                if let Some(e) = current_token.take() {
-                    token_map.push_back(validate(e));
+                if open.len() >= max_nesting {
+                    return Err(nesting_too_deep(
+                        &Span::new(file.to_owned(), current, current + 1),
+                        max_nesting,
+                    ));
                 }
-                return Ok(Some(ClosableType::Brace));
+                open.push((current, std::mem::take(token_map)));
             }
-            ')' => {
+            '}' | ')' | ']' | '>' => {
                 if let Some(e) = current_token.take() {
                     token_map.push_back(validate(e));
                 }
-                return Ok(Some(ClosableType::Parenthesis));
-            }
-            ']' => {
-                if let Some(e) = current_token.take() {
-                    token_map.push_back(validate(e));
+                let closable_type = match c {
+                    '}' => ClosableType::Brace,
+                    ')' => ClosableType::Parenthesis,
+                    ']' => ClosableType::Bracket,
+                    _ => ClosableType::Type,
+                };
+                let span = Span::new(file.to_owned(), current, initial_size - char.len());
+                let closed = close_block(token_map, &mut open, closable_type, span, max_nesting)?;
+                if let Some(e) = closed {
+                    return Ok(Some(e));
                 }
-                return Ok(Some(ClosableType::Bracket));
+                continue;
             }
             '&' => {
                 if char.front() == Some(&'&') {
@@ -231,12 +276,6 @@ pub fn parse(
                     continue;
                 }
             }
-            '>' => {
-                if let Some(e) = current_token.take() {
-                    token_map.push_back(validate(e));
-                }
-                return Ok(Some(ClosableType::Type));
-            }
             '.' | ':' | ',' | ' ' | ';' | '=' | '\n' | '\r' | '@' => {
                 if let Some(e) = current_token.take() {
                     token_map.push_back(validate(e));
@@ -377,6 +416,10 @@ pub fn parse(
             }
         }
     }
+    if !open.is_empty() {
+        panic!("Unclosed block");
+    }
+    check_chains(token_map, max_nesting)?;
     Ok(None)
 }
 
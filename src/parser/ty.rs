@@ -1,4 +1,8 @@
-use std::{collections::VecDeque, fmt::Debug};
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    hash::{Hash, Hasher},
+};
 
 use errors::{
     expected_number_as_type, invalid_type_template, Error, Span, SpannedObject, SpannedVector,
@@ -33,6 +37,13 @@ impl PartialEq for Type {
     }
 }
 
+impl Hash for Type {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.1.hash(state);
+        self.template.as_ref().map(|x| &x.1).hash(state);
+    }
+}
+
 impl Debug for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(a) = &self.template {
@@ -1,6 +1,26 @@
-use std::time::Instant;
+use std::{cell::RefCell, collections::VecDeque, rc::Rc, time::Instant};
 
-use crate::{actions::test_context::TestContext, compile, run};
+use cythan::format::HeaderData;
+use either::Either;
+use errors::Span;
+use mir::{Bound, Mir, MirCodeBlock, OptLevel, PassManager};
+
+use crate::{
+    actions::{
+        build_context::compile,
+        debug_context::{Debugger, Stop},
+        diff_context::{differential, differential_mir, StepLimits},
+        fuzz_context::fuzz,
+        profile_context::{profile, Report},
+        report_context::build_report,
+        run_context::{run, run_bin},
+        source_debug_context::SourceDebugger,
+        test_context::TestContext,
+    },
+    compiler::class_loader::DEFAULT_MAX_NESTING,
+    parser::parse,
+    container, Compiler,
+};
 
 // TODO: Create test using Annotations
 /*
@@ -10,7 +30,9 @@ fn execute(file: &str, input: &str, output: &str) {
     let (opt, ctx) = time("run_optimized", || {
         run(
             &{
-                let a = time("compile_optimized", || compile(file.to_owned(), true));
+                let a = time("compile_optimized", || {
+                    compile(file.to_owned(), PassManager::new(OptLevel::O2), false)
+                });
                 a
             },
             TestContext::new(input),
@@ -25,7 +47,7 @@ fn execute(file: &str, input: &str, output: &str) {
     let (normal, ctx) = time("run_unoptimized", || {
         run(
             &time("compile_unoptimized", || {
-                compile(file.to_owned(), false)
+                compile(file.to_owned(), PassManager::new(OptLevel::O0), false)
             }),
             TestContext::new(input),
         )
@@ -61,6 +83,463 @@ pub fn run_test_pendu() {
     execute("Pendu", "gramihjkkkjkjkhjkhjkre", "\n\n\n\n------\n\n\n_________\n\n\n\n\n\n------\n\n\ng________\n\n\n\n\n\n------\n\n\ngr_____r_\n\n\n\n\n\n------\n\n\ngra__a_r_\n\n\n\n\n\n------\n\n\ngramma_r_\n\n\n\n\n\n------\n\n\ngrammair_\n\nTu n'as pas trouvé de lettre -1 vie\n |\n |\n |\n |\n------\n\ngrammair_\n\nTu n'as pas trouvé de lettre -1 vie\n |--\n |\n |\n |\n------\n\ngrammair_\n\nTu n'as pas trouvé de lettre -1 vie\n |--|\n |  O\n |  |\n |\n------\n\ngrammair_\n\nTu n'as pas trouvé de lettre -1 vie\n |--|\n |  O\n | /|\n |\n------\n\ngrammair_\n\nTu n'as pas trouvé de lettre -1 vie\n |--|\n |  O\n | /|\\\n |\n------\n\ngrammair_\n\nTu n'as pas trouvé de lettre -1 vie\n |--|\n |  O\n | /|\\\n | / \\\n------\n\ngrammair_\n\nGROSSE MERDE!\n");
 }
 
+#[test]
+pub fn run_test_outline() {
+    execute("Outline", "", "3 7 12\n");
+}
+
+#[test]
+pub fn run_test_recursive() {
+    execute("Recursive", "", "10 12345\nStack overflow\n");
+}
+
+#[test]
+pub fn run_test_grow() {
+    // The unrolled loop is bigger than the code before the optimizations
+    execute("Grow", "", "?>=<;:987654321\n");
+}
+
+fn execute_differential(file: &str, input: &str) {
+    let report = time("differential", || differential(file, input));
+    for e in &report.divergences {
+        println!("{}", e);
+    }
+    assert!(report.divergences.is_empty(), "Backends diverged");
+}
+
+#[test]
+pub fn differential_morpion() {
+    execute_differential("Morpion", "1234567");
+    // The input runs out before the end of the game
+    execute_differential("Morpion", "1593");
+}
+
+#[test]
+pub fn differential_pendu() {
+    execute_differential("Pendu", "gramire");
+}
+
+#[test]
+pub fn differential_outline() {
+    execute_differential("Outline", "");
+}
+
+#[test]
+pub fn differential_recursive() {
+    execute_differential("Recursive", "");
+}
+
+/// The writes printing `c`, its digits can't be 0
+fn print_mir(c: char) -> Vec<Mir> {
+    vec![
+        Mir::WriteRegister(1, Either::Left(c as u8 / 16)),
+        Mir::WriteRegister(2, Either::Left(c as u8 % 16)),
+        Mir::WriteRegister(0, Either::Left(1)),
+    ]
+}
+
+/// Runs the code through the interpreter and the binary, without optimizations
+fn execute_mir(code: Vec<Mir>, output: &str) {
+    let code = MirCodeBlock(code);
+    let report = differential_mir(&code, &code, "", StepLimits::default());
+    for e in &report.executions {
+        assert_eq!(e.output.iter().collect::<String>(), output, "{}", e.name);
+    }
+}
+
+#[test]
+pub fn lowering_zero_variables() {
+    // Variables and registers never written contain 0
+    let mut code = vec![Mir::If0(1, print_mir('Z').into(), print_mir('N').into())];
+    code.push(Mir::ReadRegister(2, 5));
+    code.push(Mir::If0(2, print_mir('Z').into(), print_mir('N').into()));
+    execute_mir(code, "ZZ");
+}
+
+#[test]
+pub fn lowering_match_without_0() {
+    let matching = |c| Mir::Match(1, vec![(print_mir(c).into(), (1..16).collect())]);
+    let mut code = vec![Mir::Set(1, 3), matching('A'), Mir::Set(1, 0), matching('B')];
+    code.extend(print_mir('Z'));
+    execute_mir(code, "AZ");
+}
+
+#[test]
+pub fn lowering_empty_then_branch() {
+    let mut code = vec![Mir::Set(1, 3), Mir::If0(1, vec![].into(), print_mir('N').into())];
+    code.push(Mir::Set(1, 0));
+    code.push(Mir::If0(1, vec![].into(), print_mir('A').into()));
+    code.extend(print_mir('Z'));
+    execute_mir(code, "NZ");
+}
+
+#[test]
+pub fn lowering_code_after_jumps() {
+    // A loop ending with a stop but left by a break
+    let mut code = vec![Mir::Loop(
+        vec![Mir::If0(1, Mir::Break.into(), vec![].into()), Mir::Stop].into(),
+    )];
+    code.extend(print_mir('A'));
+    // A block ending with a stop but left by a skip
+    code.push(Mir::Block(
+        vec![Mir::If0(1, Mir::Skip.into(), vec![].into()), Mir::Stop].into(),
+    ));
+    code.extend(print_mir('B'));
+    // A match whose arms all stop but without an arm for 3
+    code.push(Mir::Set(2, 3));
+    code.push(Mir::Match(2, vec![(Mir::Stop.into(), vec![0, 1, 2])]));
+    code.extend(print_mir('C'));
+    execute_mir(code, "ABC");
+}
+
+#[test]
+pub fn fuzz_optimizer() {
+    let failures = time("fuzz", || fuzz(0..1000));
+    for e in &failures {
+        println!("Seed {} with input {:?} failed:", e.seed, e.input);
+        for i in e.program.iter() {
+            println!("{}", i);
+        }
+        println!("{}", e.divergence);
+    }
+    assert!(failures.is_empty(), "Generated programs diverged");
+}
+
+#[test]
+pub fn nesting_limit() {
+    let lex = |source: String| {
+        let mut chars: VecDeque<char> = source.chars().collect();
+        let size = chars.len();
+        parse(&mut VecDeque::new(), &mut chars, size, "test", DEFAULT_MAX_NESTING)
+    };
+    let parentheses = |n: usize| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+    assert!(lex(parentheses(DEFAULT_MAX_NESTING)).is_ok());
+    assert!(lex(parentheses(DEFAULT_MAX_NESTING + 1)).is_err());
+    assert!(lex(parentheses(100_000)).is_err());
+    assert!(lex(format!("a{};", ".b()".repeat(100_000))).is_err());
+}
+
+#[test]
+pub fn nesting_limit_stack() {
+    // The code just under the limit must fit in the default stack of a thread, even in debug
+    let compile = |source: String| {
+        std::thread::Builder::new()
+            .stack_size(2 << 20)
+            .spawn(move || {
+                Compiler::new()
+                    .add_source("Main.ct", source)
+                    .compile()
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    };
+    // The braces of the class and of `main` are open around the ifs
+    let ifs = |n: usize| {
+        format!(
+            "class Main {{ Val main() {{ Bool b = true; {}b;{} return 0; }} }}",
+            "if b { ".repeat(n),
+            " };".repeat(n)
+        )
+    };
+    assert!(compile(ifs(DEFAULT_MAX_NESTING - 2)).is_ok());
+    assert!(compile(ifs(DEFAULT_MAX_NESTING - 1))
+        .unwrap_err()
+        .contains("[21] Error"));
+    // Each inlined call nests a return and a call, the return of `main` calls `f0`
+    let calls = |n: usize| {
+        format!(
+            "class Main {{ {} Val f{}() {{ return 1; }} Val main() {{ return Self.f0(); }} }}",
+            (0..n)
+                .map(|i| format!("Val f{}() {{ return Self.f{}(); }}", i, i + 1))
+                .collect::<String>(),
+            n
+        )
+    };
+    assert!(compile(calls((DEFAULT_MAX_NESTING - 4) / 2)).is_ok());
+    assert!(compile(calls((DEFAULT_MAX_NESTING - 2) / 2))
+        .unwrap_err()
+        .contains("[21] Error"));
+}
+
+/// The report of the only error the class `Main` is rejected with
+fn compile_error(source: &str) -> String {
+    let diagnostics = Compiler::new()
+        .add_source("Main.ct", source)
+        .compile()
+        .err()
+        .unwrap();
+    assert_eq!(diagnostics.reports.len(), 1);
+    diagnostics.reports[0].clone()
+}
+
+#[test]
+pub fn recursion_errors() {
+    // The calls of methods without `@Recursive` are inlined, they can't call themselves
+    let direct =
+        compile_error("class Main { Val main() { Self.f(); return 0; } f() { Self.f(); } }");
+    assert!(direct.contains("[19] Error"));
+    assert!(direct.contains("Main.f -> Main.f\n"));
+    let mutual = compile_error(
+        "class Main { Val main() { Self.f(); return 0; } f() { Self.g(); } g() { Self.f(); } }",
+    );
+    assert!(mutual.contains("[19] Error"));
+    assert!(mutual.contains("Main.f -> Main.g -> Main.f\n"));
+    for depth in ["0", "16"].iter() {
+        let source = format!(
+            "class Main {{ Val main() {{ Self.f(); return 0; }} @Recursive({}) f() {{ }} }}",
+            depth
+        );
+        let error = compile_error(&source);
+        assert!(error.contains("[20] Error"));
+        assert!(error.contains("The depth must be a number from 1 to 15"));
+    }
+}
+
+#[test]
+pub fn compile_in_memory() {
+    let artifacts = Compiler::new()
+        .add_source("Main.ct", "class Main { Val main() { 'o'.print(); 'k'.print(); return 0; } }")
+        .entry("Main")
+        .opt_level(OptLevel::O2)
+        .compile()
+        .unwrap();
+    let (_, ctx) = run_bin(&artifacts.binary, TestContext::new(""));
+    assert_eq!(ctx.lock().unwrap().print, "ok");
+    let diagnostics = Compiler::new()
+        .add_source("Main.ct", "class Main { Val main() { return missing; } }")
+        .compile()
+        .err()
+        .unwrap();
+    assert_eq!(diagnostics.reports.len(), 1);
+}
+
+#[test]
+pub fn call_limit() {
+    // The loop must not be unrolled, its call would be copied past the calls the front end counted
+    let source = format!(
+        "class Many {{ @NoInline Val add(Val a) {{ a.inc(); return a; }} Val main() {{ {} \
+         Val i = 10; loop {{ if i.equalsZero() {{ break; }}; Self.add(1); i.dec(); }}; \
+         Self.add(2).print(); return 0; }} }}",
+        "Self.add(1); ".repeat(250)
+    );
+    for level in [OptLevel::O0, OptLevel::O2].iter() {
+        let artifacts = Compiler::new()
+            .add_source("Many.ct", &source)
+            .entry("Many")
+            .opt_level(*level)
+            .compile()
+            .unwrap();
+        let (_, ctx) = run_bin(&artifacts.binary, TestContext::new(""));
+        assert_eq!(ctx.lock().unwrap().print, "3");
+    }
+}
+
+#[test]
+pub fn debug_info() {
+    let source = "class Main { Val main() { Val a = 3; a.print(); return a; } }";
+    let compile = |debug_info| {
+        Compiler::new()
+            .add_source("Main.ct", source)
+            .opt_level(OptLevel::O0)
+            .debug_info(debug_info)
+            .compile()
+            .unwrap()
+    };
+    let (plain, debug) = (compile(false), compile(true));
+    assert!(plain.debug_info.is_none());
+    // The locations don't change the code
+    assert_eq!(plain.binary, debug.binary);
+    let debug_info = debug.debug_info.unwrap();
+    let spans: Vec<&Span> = debug_info
+        .entries
+        .iter()
+        .filter_map(|x| x.origin.as_ref()?.span.as_ref())
+        .collect();
+    let first = spans[0];
+    assert_eq!(&source[first.start..first.end], "Val a = 3");
+    // The code of the inlined `print` points to its statements
+    assert!(spans.iter().any(|x| x.file == "std/Val.ct"));
+    let entry = &debug_info.entries[debug_info.entries.len() / 2];
+    assert_eq!(debug_info.lookup(entry.end - 1).unwrap().start, entry.start);
+}
+
+#[test]
+pub fn debugger() {
+    let source = "class Main { Val main() { Val a = 3; a.print(); 'x'.print(); return a; } }";
+    let artifacts = Compiler::new()
+        .add_source("Main.ct", source)
+        .opt_level(OptLevel::O0)
+        .debug_info(true)
+        .compile()
+        .unwrap();
+    let binary = container(&artifacts.binary, artifacts.debug_info.as_ref(), &artifacts.sources);
+    let mut debugger = Debugger::new(binary, "").unwrap();
+    let start = debugger.resolve("start").unwrap();
+    assert_eq!(debugger.pc(), start);
+    // The first instruction after the start is the first statement
+    assert!(debugger.command("step").unwrap().contains("Val a = 3"));
+    let register = debugger.resolve("'#return_0").unwrap();
+    debugger.command(&format!("watch {}", register)).unwrap();
+    let stop = debugger.run(None);
+    assert!(matches!(stop, Stop::Watchpoint { cell, .. } if cell == register));
+    let initial = debugger.machine.cases.clone();
+    let steps = debugger.steps;
+    assert_eq!(debugger.run(None), Stop::Ended);
+    let output = debugger.command("where").unwrap();
+    assert!(output.ends_with("Output: \"3x\""));
+    // Going back undoes the writes and the output
+    debugger.command(&format!("back {}", debugger.steps - steps)).unwrap();
+    assert_eq!(debugger.machine.cases, initial);
+    debugger.command(&format!("unwatch {}", register)).unwrap();
+    // The watchpoint stopped the machine after the `3` was printed, only the `x` is new
+    assert_eq!(debugger.run(None), Stop::Ended);
+    assert!(debugger.command("where").unwrap().ends_with("Output: \"x\""));
+    assert!(debugger.command("break nowhere").is_err());
+}
+
+#[test]
+pub fn source_debugger() {
+    let source = "class Main {
+    twice(Val a) {
+        a.print();
+        a.print();
+    }
+    Val main() {
+        Val n = 2;
+        loop {
+            if n.equalsZero() {
+                break;
+            };
+            n.dec();
+        };
+        Self.twice(n);
+        return n;
+    }
+}";
+    let compiler = Compiler::new()
+        .add_source("src/Main.ct", source)
+        .debug_info(true)
+        .debug_scopes(true);
+    let mir = compiler.generate().unwrap();
+    let mut commands: VecDeque<&str> = [
+        "next", "next", "print n", "break twice", "continue", "where", "locals", "finish",
+        "continue",
+    ]
+    .iter()
+    .copied()
+    .collect();
+    let shown = Rc::new(RefCell::new(Vec::new()));
+    let log = shown.clone();
+    let mut debugger = SourceDebugger::new(compiler.sources(), "", move |out| {
+        log.borrow_mut().push(out.to_owned());
+        commands.pop_front().map(|x| x.to_owned())
+    });
+    assert_eq!(debugger.run(&mir), "The program ended");
+    assert_eq!(
+        &shown.borrow()[..5],
+        [
+            "src/Main.ct:7: Val n = 2;",
+            "src/Main.ct:8: loop {",
+            // `next` steps over the body of the loop
+            "src/Main.ct:14: Self.twice(n);",
+            "Val n = 0",
+            "Breakpoint 1 at twice",
+        ]
+    );
+    assert_eq!(shown.borrow()[5], "Breakpoint at Main.twice at src/Main.ct:3: a.print();");
+    let backtrace = &shown.borrow()[6];
+    assert!(backtrace.starts_with("#0 Main.twice at src/Main.ct:3"));
+    assert!(backtrace.contains("called from src/Main.ct:14"));
+    assert_eq!(shown.borrow()[7], "Val a = 0");
+    assert_eq!(shown.borrow()[8], "src/Main.ct:15: return n;\nOutput: \"00\"");
+}
+
+#[test]
+pub fn profile_report() {
+    let source = "class Main {
+    show(Val a) {
+        a.print();
+        a.print();
+    }
+    Val main() {
+        Val n = 5;
+        loop {
+            if n.equalsZero() {
+                break;
+            };
+            n.dec();
+            Self.show(n);
+        };
+        return n;
+    }
+}";
+    let artifacts = Compiler::new()
+        .add_source("Main.ct", source)
+        // The optimizations would run the whole program at compile time
+        .opt_level(OptLevel::O0)
+        .debug_info(true)
+        .compile()
+        .unwrap();
+    let (profile, output) = profile(&HeaderData::default(), &artifacts.binary, "").unwrap();
+    assert_eq!(output, "4433221100");
+    let (steps, _) = run_bin(&artifacts.binary, TestContext::new(""));
+    assert_eq!(profile.steps, steps);
+    let report = Report::new(artifacts.debug_info.as_ref().unwrap(), &profile);
+    assert_eq!(report.stacks.iter().map(|x| x.1).sum::<usize>(), steps);
+    let main = report.methods.iter().find(|x| x.0 == "Main.main").unwrap();
+    let show = report.methods.iter().find(|x| x.0 == "Main.show").unwrap();
+    // The steps of `show` are part of the steps of `main`
+    assert!(show.2 > 0 && main.2 >= show.2 + main.1);
+    assert!(report
+        .folded()
+        .lines()
+        .any(|x| x.starts_with("Main.main;Main.show;Val.print ")));
+}
+
+#[test]
+pub fn step_report() {
+    let source = "class Main {
+    show(Val a) {
+        a.print();
+        a.print();
+    }
+    Val main() {
+        Val n = 5;
+        loop {
+            if n.equalsZero() {
+                break;
+            };
+            n.dec();
+            Self.show(n);
+        };
+        return n;
+    }
+}";
+    let compiler = || Compiler::new().add_source("Main.ct", source).opt_level(OptLevel::O0);
+    let report = build_report(compiler()).unwrap();
+    let (steps, _) = run_bin(&compiler().compile().unwrap().binary, TestContext::new(""));
+    // The loop goes back to its start 5 times
+    let bounds: Vec<usize> = report
+        .bounds
+        .iter()
+        .map(|x| match x {
+            Bound::Loop(Some(span)) if span.file == "Main.ct" => 5,
+            _ => 0,
+        })
+        .collect();
+    let estimate = report.steps.as_ref().unwrap();
+    assert!(estimate.best <= steps && steps <= estimate.worst.eval(&bounds));
+    let show = report.methods.iter().find(|x| x.0 == "Main.show").unwrap();
+    assert_eq!(show.1.sites, 1);
+    assert!(show.1.steps.worst.is_fixed());
+    assert!(report.cells > 0 && report.cells <= report.optimized);
+    assert!(report.optimized <= report.variables as usize);
+    assert!(report.to_string().contains("n1 is the iterations of the loop at Main.ct:8: loop {"));
+}
+
 pub fn time<T>(legend: &str, f: impl FnOnce() -> T) -> T {
     let instant = Instant::now();
     let t = f();